@@ -0,0 +1,356 @@
+//! Debugging frontend showing CPU registers, disassembly, memory, tiles and
+//! sprites, with breakpoints. Build it with `--features debugger`.
+
+use std::{env, fs, process::exit};
+
+use eframe::egui::{self, ColorImage, Key, RichText, TextureHandle, TextureOptions};
+use gbemu::{ButtonState, Emulator, SCREEN_SIZE};
+
+const SCREEN_SCALE: f32 = 3.0;
+const TILE_SCALE: f32 = 2.0;
+/// Tiles in VRAM bank 0, laid out as 16 tiles per row.
+const TILES: usize = 384;
+const TILES_PER_ROW: usize = 16;
+const DISASM_LINES: usize = 20;
+const MEM_ROWS: u16 = 16;
+const OAM_ADDR: u16 = 0xFE00;
+const VRAM_ADDR: u16 = 0x8000;
+const IO_BGP: u16 = 0xFF47;
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: gbemu-debug <rom-or-state-file>");
+        exit(1);
+    };
+
+    let data = fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("cannot open file '{}': {:?}", path, e);
+        exit(1);
+    });
+    let emu = if Emulator::is_save_state(&data) {
+        Emulator::from_state(&data)
+    } else {
+        Emulator::new(&data)
+    };
+    let emu = emu.unwrap_or_else(|e| {
+        eprintln!("Emulator error: {:?}", e);
+        exit(1);
+    });
+
+    let app = Box::new(|_: &eframe::CreationContext| {
+        Ok(Box::new(Debugger::new(emu)) as Box<dyn eframe::App>)
+    });
+    if let Err(e) = eframe::run_native("gbemu-debug", Default::default(), app) {
+        eprintln!("cannot start GUI: {:?}", e);
+        exit(1);
+    }
+}
+
+struct Debugger {
+    emu: Emulator,
+    is_running: bool,
+    breakpoints: Vec<u16>,
+    breakpoint_input: String,
+    mem_addr: u16,
+    mem_input: String,
+    status: String,
+    screen: Option<TextureHandle>,
+    tiles: Option<TextureHandle>,
+}
+
+impl eframe::App for Debugger {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.is_running {
+            // Do not send keys typed into text fields to the game.
+            let btns = if ctx.wants_keyboard_input() {
+                ButtonState::default()
+            } else {
+                ctx.input(get_button_state)
+            };
+
+            if self.emu.step_frame_until_any(btns, &self.breakpoints) {
+                self.is_running = false;
+                self.status = format!("Hit breakpoint at ${:04X}", self.emu.pc());
+            }
+            ctx.request_repaint();
+        }
+
+        egui::SidePanel::left("cpu").show(ctx, |ui| {
+            self.show_controls(ui);
+            ui.separator();
+            self.show_registers(ui);
+            ui.separator();
+            self.show_breakpoints(ui);
+        });
+        egui::SidePanel::right("memory").show(ctx, |ui| {
+            self.show_disassembly(ui);
+            ui.separator();
+            self.show_memory(ui);
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.show_screen(ui);
+            ui.separator();
+            ui.horizontal_top(|ui| {
+                self.show_tiles(ui);
+                self.show_sprites(ui);
+            });
+        });
+    }
+}
+
+impl Debugger {
+    fn new(emu: Emulator) -> Self {
+        Self {
+            emu,
+            is_running: false,
+            breakpoints: Vec::new(),
+            breakpoint_input: String::new(),
+            mem_addr: 0xC000,
+            mem_input: String::new(),
+            status: "Paused".to_string(),
+            screen: None,
+            tiles: None,
+        }
+    }
+
+    fn show_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let label = if self.is_running { "Pause" } else { "Run" };
+            if ui.button(label).clicked() {
+                self.is_running = !self.is_running;
+                self.status = if self.is_running { "Running" } else { "Paused" }.to_string();
+            }
+            if ui.button("Step").clicked() {
+                self.is_running = false;
+                self.emu.step_instruction();
+                self.status = "Stepped".to_string();
+            }
+            if ui.button("Frame").clicked() {
+                self.is_running = false;
+                self.emu.step_frame(ButtonState::default());
+                self.status = "Stepped a frame".to_string();
+            }
+        });
+        ui.label(&self.status);
+    }
+
+    fn show_registers(&self, ui: &mut egui::Ui) {
+        let r = self.emu.registers();
+        let flag = |bit: u8, name: char| if r.f >> bit & 1 == 1 { name } else { '-' };
+
+        ui.heading("Registers");
+        egui::Grid::new("registers").show(ui, |ui| {
+            for (name, val) in [
+                ("AF", u16::from_be_bytes([r.a, r.f])),
+                ("BC", u16::from_be_bytes([r.b, r.c])),
+                ("DE", u16::from_be_bytes([r.d, r.e])),
+                ("HL", u16::from_be_bytes([r.h, r.l])),
+                ("SP", r.sp),
+                ("PC", r.pc),
+            ] {
+                ui.monospace(name);
+                ui.monospace(format!("{:04X}", val));
+                ui.end_row();
+            }
+        });
+
+        let flags: String = [flag(7, 'Z'), flag(6, 'N'), flag(5, 'H'), flag(4, 'C')]
+            .iter()
+            .collect();
+        ui.monospace(format!("Flags {}  IME {}", flags, r.ime as u8));
+        if r.is_halted {
+            ui.monospace("HALTED");
+        }
+        if r.is_stopped {
+            ui.monospace("STOPPED");
+        }
+    }
+
+    fn show_breakpoints(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Breakpoints");
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.breakpoint_input).desired_width(60.0));
+            if ui.button("Add").clicked() {
+                if let Some(addr) = parse_addr(&self.breakpoint_input) {
+                    self.toggle_breakpoint(addr);
+                    self.breakpoint_input.clear();
+                }
+            }
+        });
+
+        let mut removed = None;
+        for &addr in &self.breakpoints {
+            ui.horizontal(|ui| {
+                ui.monospace(format!("${:04X}", addr));
+                if ui.small_button("x").clicked() {
+                    removed = Some(addr);
+                }
+            });
+        }
+        if let Some(addr) = removed {
+            self.toggle_breakpoint(addr);
+        }
+    }
+
+    /// Disassemble from PC onwards, clicking a line toggles a breakpoint.
+    fn show_disassembly(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Disassembly");
+        let pc = self.emu.pc();
+        let mut addr = pc;
+        let mut clicked = None;
+
+        for _ in 0..DISASM_LINES {
+            let (text, next) = self.emu.disassemble(addr);
+            let marker = match (addr == pc, self.breakpoints.contains(&addr)) {
+                (true, _) => '>',
+                (false, true) => '*',
+                _ => ' ',
+            };
+
+            let line = RichText::new(format!("{} {:04X}  {}", marker, addr, text)).monospace();
+            if ui.selectable_label(false, line).clicked() {
+                clicked = Some(addr);
+            }
+            addr = next;
+        }
+
+        if let Some(addr) = clicked {
+            self.toggle_breakpoint(addr);
+        }
+    }
+
+    fn show_memory(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Memory");
+        ui.horizontal(|ui| {
+            let input = ui.add(egui::TextEdit::singleline(&mut self.mem_input).desired_width(60.0));
+            if input.lost_focus() {
+                if let Some(addr) = parse_addr(&self.mem_input) {
+                    self.mem_addr = addr & !0xF;
+                }
+            }
+            if ui.button("<").clicked() {
+                self.mem_addr = self.mem_addr.wrapping_sub(MEM_ROWS * 16);
+            }
+            if ui.button(">").clicked() {
+                self.mem_addr = self.mem_addr.wrapping_add(MEM_ROWS * 16);
+            }
+        });
+
+        for row in 0..MEM_ROWS {
+            let base = self.mem_addr.wrapping_add(row * 16);
+            let bytes: Vec<String> = (0..16)
+                .map(|i| format!("{:02X}", self.emu.read_memory(base.wrapping_add(i))))
+                .collect();
+            ui.monospace(format!("{:04X}: {}", base, bytes.join(" ")));
+        }
+    }
+
+    fn show_screen(&mut self, ui: &mut egui::Ui) {
+        let (w, h) = SCREEN_SIZE;
+        let image = ColorImage::from_rgb([w, h], &self.emu.frame().to_rgb24());
+        let tex = set_texture(ui.ctx(), &mut self.screen, "screen", image);
+
+        let size = egui::vec2(w as f32, h as f32) * SCREEN_SCALE;
+        ui.image((tex.id(), size));
+    }
+
+    /// Draw tiles in VRAM using the BGP palette.
+    fn show_tiles(&mut self, ui: &mut egui::Ui) {
+        let (w, h) = (TILES_PER_ROW * 8, TILES / TILES_PER_ROW * 8);
+        let bgp = self.emu.read_memory(IO_BGP);
+        let mut rgb = vec![0; w * h * 3];
+
+        for tile in 0..TILES {
+            let (tx, ty) = (tile % TILES_PER_ROW * 8, tile / TILES_PER_ROW * 8);
+            for row in 0..8 {
+                let addr = VRAM_ADDR + (tile * 16 + row * 2) as u16;
+                let lo = self.emu.read_memory(addr);
+                let hi = self.emu.read_memory(addr + 1);
+
+                for col in 0..8 {
+                    let bit = 7 - col;
+                    let id = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+                    let shade = 255 - ((bgp >> (id * 2)) & 0b11) * 85;
+                    let i = ((ty + row) * w + tx + col) * 3;
+                    rgb[i..i + 3].fill(shade);
+                }
+            }
+        }
+
+        ui.vertical(|ui| {
+            ui.heading("Tiles");
+            let image = ColorImage::from_rgb([w, h], &rgb);
+            let tex = set_texture(ui.ctx(), &mut self.tiles, "tiles", image);
+            ui.image((tex.id(), egui::vec2(w as f32, h as f32) * TILE_SCALE));
+        });
+    }
+
+    /// List OAM entries.
+    fn show_sprites(&self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            ui.heading("Sprites");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("sprites").striped(true).show(ui, |ui| {
+                    for name in ["#", "Y", "X", "Tile", "Flags"] {
+                        ui.monospace(name);
+                    }
+                    ui.end_row();
+
+                    for i in 0..40 {
+                        let addr = OAM_ADDR + i * 4;
+                        ui.monospace(i.to_string());
+                        for j in 0..4 {
+                            let val = self.emu.read_memory(addr + j);
+                            ui.monospace(format!("{:02X}", val));
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+    }
+
+    fn toggle_breakpoint(&mut self, addr: u16) {
+        if let Some(i) = self.breakpoints.iter().position(|&a| a == addr) {
+            self.breakpoints.remove(i);
+        } else {
+            self.breakpoints.push(addr);
+            self.breakpoints.sort();
+        }
+    }
+}
+
+/// Update the texture in `slot` with `image`, creating it if needed.
+fn set_texture<'a>(
+    ctx: &egui::Context,
+    slot: &'a mut Option<TextureHandle>,
+    name: &str,
+    image: ColorImage,
+) -> &'a TextureHandle {
+    match slot {
+        Some(tex) => tex.set(image, TextureOptions::NEAREST),
+        None => *slot = Some(ctx.load_texture(name, image, TextureOptions::NEAREST)),
+    }
+    slot.as_ref().unwrap()
+}
+
+/// Parse a hexadecimal address, with an optional `0x` or `$` prefix.
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s.strip_prefix("0x").or(s.strip_prefix('$')).unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+/// Same key bindings as the main frontend.
+fn get_button_state(input: &egui::InputState) -> ButtonState {
+    ButtonState {
+        a: input.key_down(Key::Z),
+        b: input.key_down(Key::X),
+        select: input.key_down(Key::Enter),
+        start: input.key_down(Key::Backspace),
+        up: input.key_down(Key::W) || input.key_down(Key::ArrowUp),
+        down: input.key_down(Key::S) || input.key_down(Key::ArrowDown),
+        left: input.key_down(Key::A) || input.key_down(Key::ArrowLeft),
+        right: input.key_down(Key::D) || input.key_down(Key::ArrowRight),
+    }
+}
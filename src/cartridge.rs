@@ -1,49 +1,257 @@
 mod mbc;
+mod mbc7;
 
-use crate::{info::*, log, macros::match_range, EmuError};
+use std::sync::Arc;
 
-#[derive(Default)]
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::crc32, info::*, log, macros::match_range, EmuError};
+
+/// Sizes of the clock data appended to battery saves of cartridges with
+/// an RTC, by VBA and BGB. It is 48 bytes with a 64-bit timestamp and 44
+/// bytes with a 32-bit one.
+const RTC_FOOTER_SIZES: [usize; 2] = [44, 48];
+
+/// What a cartridge header declares, along with notes about the features
+/// it uses which are not emulated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeInfo {
+    pub title: String,
+    /// CRC-32 of the whole ROM, which ROM databases list.
+    pub crc32: u32,
+    /// Cartridge type byte, which tells the MBC and other hardware.
+    pub cart_type: u8,
+    /// Name of the MBC, like `MBC1` or `none`. `None` if the type is unknown.
+    pub mbc: Option<&'static str>,
+    /// ROM size declared in the header, 0 if unknown.
+    pub rom_size: usize,
+    /// External RAM size declared in the header.
+    pub ram_size: usize,
+    pub has_battery: bool,
+    /// Has a real-time clock.
+    pub has_rtc: bool,
+    /// Has an accelerometer for sensing tilt, see `Emulator::set_tilt`.
+    pub has_accelerometer: bool,
+    pub has_rumble: bool,
+    pub supports_cgb: bool,
+    pub cgb_only: bool,
+    pub supports_sgb: bool,
+    /// Why the game may not work fully, as notes for showing to users.
+    /// Empty if everything it uses is emulated.
+    pub unsupported: Vec<String>,
+}
+
+impl CartridgeInfo {
+    /// Read the header of `rom`, it works for ROMs which cannot be loaded.
+    pub fn from_rom(rom: &[u8]) -> Result<Self, EmuError> {
+        check_header(rom)?;
+
+        let cgb_flag = rom[CART_CGB_FLAG];
+        let supports_cgb = matches!(cgb_flag, CART_CGB_TOO | CART_CGB_ONLY);
+        // The last byte of the title is the CGB flag in newer cartridges.
+        let title_end = if supports_cgb {
+            CART_CGB_FLAG
+        } else {
+            *CART_TITLE.end() + 1
+        };
+        let title = rom[*CART_TITLE.start()..title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { ' ' })
+            .collect::<String>()
+            .trim()
+            .to_string();
+
+        let cart_type = rom[CART_TYPE];
+        let mbc = mbc::mbc_info(cart_type);
+        let mut info = Self {
+            title,
+            crc32: crc32(rom),
+            cart_type,
+            mbc: mbc.map(|m| m.0),
+            rom_size: rom_size(rom[CART_ROM_SIZE]),
+            ram_size: ext_ram_size(rom[CART_RAM_SIZE]),
+            has_battery: matches!(
+                cart_type,
+                0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+            ),
+            has_rtc: matches!(cart_type, 0x0F | 0x10),
+            has_accelerometer: cart_type == 0x22,
+            has_rumble: matches!(cart_type, 0x1C..=0x1E),
+            supports_cgb,
+            cgb_only: cgb_flag == CART_CGB_ONLY,
+            supports_sgb: rom[CART_SGB_FLAG] == CART_SGB_TOO,
+            unsupported: Vec::new(),
+        };
+
+        if mbc::is_mbc1_multicart(rom) {
+            info.mbc = Some("MBC1M");
+        }
+
+        let notes = &mut info.unsupported;
+        match mbc {
+            None => notes.push(format!(
+                "unknown cartridge type {:02X}, it cannot be loaded",
+                cart_type
+            )),
+            Some((name, false)) => {
+                notes.push(format!("{} is not emulated, it cannot be loaded", name))
+            }
+            Some(_) => (),
+        }
+        if info.has_rtc {
+            notes.push("has a real-time clock, which is not emulated".to_string());
+        }
+        if info.has_rumble {
+            notes.push("rumble is not emulated".to_string());
+        }
+        if info.supports_sgb {
+            notes.push(
+                "SGB enhanced: borders, colors and sound are not emulated, \
+                 only multiplayer joypads are"
+                    .to_string(),
+            );
+        }
+        if rom.len() < info.rom_size {
+            notes.push("ROM is smaller than the header declares, it is mirrored".to_string());
+        }
+
+        Ok(info)
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Cartidge {
     pub(crate) is_cgb: bool,
     mbc: mbc::Mbc,
 
     /// Cartridge ROM fixed size on load.
-    rom: Box<[u8]>,
-    /// External RAM banks are allocated on demand.
+    /// It is not a part of the machine state, so it is not serialized.
+    /// Shared between clones, as it is never written to.
+    #[serde(skip)]
+    rom: Arc<[u8]>,
+    /// External RAM banks are allocated on demand. MBC7 has no RAM, its
+    /// EEPROM is kept here instead.
     ram: Vec<u8>,
+    mbc7: Option<mbc7::Mbc7>,
 }
 
 impl Cartidge {
     /// Copy the rom and create a new cartridge.
     pub(crate) fn new(rom: &[u8]) -> Result<Self, EmuError> {
+        check_header(rom)?;
         let is_cgb_rom = matches!(rom[CART_CGB_FLAG], CART_CGB_TOO | CART_CGB_ONLY);
         let mbc = mbc::Mbc::from_rom(rom)?;
 
-        if rom.len() % SIZE_ROM_BANK != 0 {
+        if !rom.len().is_multiple_of(SIZE_ROM_BANK) {
             log::warn("cartridge: ROM size is not a multiple of 16kiB");
         }
+        let declared = rom_size(rom[CART_ROM_SIZE]);
+        if rom.len() < declared {
+            log::info(&format!(
+                "cartridge: ROM is {} bytes but header declares {}, mirroring it",
+                rom.len(),
+                declared
+            ));
+        }
 
         let mut r = Self {
             is_cgb: is_cgb_rom,
+            mbc7: mbc.is_mbc7().then(mbc7::Mbc7::new),
             mbc,
-            rom: rom.to_vec().into_boxed_slice(),
+            rom: rom.into(),
             ram: Vec::new(),
         };
-        r.alloc_ram(1);
+        r.alloc_ram(0);
 
         Ok(r)
     }
 
+    pub(crate) fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Get a 16kiB ROM bank, the last one may be shorter.
+    /// It is empty if the bank is past the ROM end.
+    pub(crate) fn rom_bank(&self, bank: usize) -> &[u8] {
+        let start = (bank * SIZE_ROM_BANK).min(self.rom.len());
+        let end = (start + SIZE_ROM_BANK).min(self.rom.len());
+        &self.rom[start..end]
+    }
+
+    pub(crate) fn is_mbc_supported(&self) -> bool {
+        self.mbc.is_supported()
+    }
+
+    pub(crate) fn rom_bank_count(&self) -> usize {
+        self.rom.len().div_ceil(SIZE_ROM_BANK)
+    }
+
+    /// Get the ROM banks mapped at 0x0000 and 0x4000, after mirroring.
+    pub(crate) fn mapped_rom_banks(&self) -> (usize, usize) {
+        let banks = self.rom_bank_count().next_power_of_two();
+        (self.mbc.rom0_idx % banks, self.mbc.rom1_idx % banks)
+    }
+
+    /// Get the ROM for sharing it with another cartridge.
+    pub(crate) fn shared_rom(&self) -> Arc<[u8]> {
+        self.rom.clone()
+    }
+
+    /// Set ROM after restoring a saved state, as it is not a part of it.
+    pub(crate) fn set_rom(&mut self, rom: Arc<[u8]>) {
+        self.rom = rom;
+    }
+
+    /// Get external RAM contents as in a battery save file, its size is
+    /// the RAM size declared in the cartridge header.
+    pub(crate) fn save_data(&self) -> Vec<u8> {
+        let mut r = self.ram.clone();
+        r.resize(self.save_data_size(), 0);
+        r
+    }
+
+    /// Replace external RAM contents with the ones from a battery save,
+    /// which must be as large as `save_data` makes it. Clock data appended
+    /// by other emulators for cartridges with an RTC is ignored, as the
+    /// clock is not emulated.
+    pub(crate) fn load_save_data(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        let size = self.save_data_size();
+        let has_rtc = matches!(self.rom.get(CART_TYPE), Some(0x0F | 0x10));
+        let extra = data.len().checked_sub(size);
+        if extra != Some(0) && !(has_rtc && extra.is_some_and(|n| RTC_FOOTER_SIZES.contains(&n))) {
+            return Err(EmuError::SaveDataSize(size));
+        }
+
+        self.ram = data[..size].to_vec();
+        self.ram.resize(size.next_multiple_of(SIZE_EXT_RAM), 0);
+        self.alloc_ram(self.mbc.ram_idx);
+        Ok(())
+    }
+
+    fn save_data_size(&self) -> usize {
+        if self.mbc7.is_some() {
+            mbc7::SIZE_EEPROM
+        } else {
+            self.rom.get(CART_RAM_SIZE).map_or(0, |&c| ext_ram_size(c))
+        }
+    }
+
     pub(crate) fn read(&self, addr: usize) -> u8 {
-        // Some ROM sizes may not be multiples of SIZE_ROM_BANK, in such cases
-        // an address might overflow on last ROM bank.
-        let safe_read = |addr: usize| self.rom.get(addr).copied().unwrap_or(0xFF);
+        // Like a ROM chip, which has its unused upper address lines left
+        // unconnected, the ROM repeats every power-of-two size that holds it.
+        // Undersized ROMs are common in homebrew. The part of that size
+        // past the ROM end reads as open bus.
+        let mirror = self.rom.len().next_power_of_two() - 1;
+        let safe_read = |addr: usize| self.rom.get(addr & mirror).copied().unwrap_or(0xFF);
 
         match_range! { v@addr {
             ADDR_ROM0 => { safe_read(self.mbc.rom0_idx * SIZE_ROM_BANK + v) }
             ADDR_ROM1 => { safe_read(self.mbc.rom1_idx * SIZE_ROM_BANK + v) }
             ADDR_EXT_RAM => {
-                if self.mbc.ram_enabled {
+                if let Some(mbc7) = &self.mbc7 {
+                    if self.mbc.are_mbc7_regs_enabled() { mbc7.read(v) } else { 0xFF }
+                } else if self.mbc.ram_enabled {
                     self.ram[self.get_ram_addr(v)]}
                 else {
                     0xFF
@@ -56,10 +264,20 @@ impl Cartidge {
 
     pub(crate) fn write(&mut self, addr: usize, val: u8) {
         match_range! { v@addr {
-            ADDR_ROM0 => { self.mbc.write(addr, val) }
-            ADDR_ROM1 => { self.mbc.write(addr, val) }
+            ADDR_ROM0 => {
+                self.mbc.write(addr, val);
+                self.alloc_ram(self.mbc.ram_idx);
+            }
+            ADDR_ROM1 => {
+                self.mbc.write(addr, val);
+                self.alloc_ram(self.mbc.ram_idx);
+            }
             ADDR_EXT_RAM => {
-                if self.mbc.ram_enabled {
+                if let Some(mbc7) = &mut self.mbc7 {
+                    if self.mbc.are_mbc7_regs_enabled() {
+                        mbc7.write(v, val, &mut self.ram);
+                    }
+                } else if self.mbc.ram_enabled {
                     let a = self.get_ram_addr(v);
                     self.ram[a] = val;
                 }
@@ -69,6 +287,13 @@ impl Cartidge {
         }}
     }
 
+    /// Size of external RAM which is not allocated yet, but may be on a
+    /// bank switch or when a battery save is loaded.
+    #[cfg(feature = "libretro")]
+    pub(crate) fn unallocated_ram(&self) -> usize {
+        SIZE_MAX_EXT_RAM.saturating_sub(self.ram.len())
+    }
+
     /// Allocate RAM if insufficient for a given bank.
     fn alloc_ram(&mut self, bank: usize) {
         // Since RAM sizes can vary for different Cartridges and figuring
@@ -80,7 +305,122 @@ impl Cartidge {
         }
     }
 
+    /// Write to a RAM bank whether or not it is mapped or RAM is enabled,
+    /// false if the cartridge does not have the bank.
+    pub(crate) fn write_ram_bank(&mut self, bank: usize, offset: usize, val: u8) -> bool {
+        let size = self.rom.get(CART_RAM_SIZE).map_or(0, |&c| ext_ram_size(c));
+        if self.mbc7.is_some() || bank >= size / SIZE_EXT_RAM {
+            return false;
+        }
+
+        self.alloc_ram(bank);
+        self.ram[bank * SIZE_EXT_RAM + offset] = val;
+        true
+    }
+
+    pub(crate) fn tilt(&self) -> (f32, f32) {
+        self.mbc7.as_ref().map_or((0.0, 0.0), |m| m.tilt)
+    }
+
+    /// Set the tilt sensed by the accelerometer, in g along the X(right)
+    /// and Y(down) axes. Ignored if the cartridge has none.
+    pub(crate) fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Some(mbc7) = &mut self.mbc7 {
+            mbc7.tilt = (x, y);
+        }
+    }
+
     fn get_ram_addr(&self, offset: usize) -> usize {
         self.mbc.ram_idx * SIZE_EXT_RAM + offset
     }
 }
+
+/// Check that `rom` has a cartridge header before anything is read from it.
+fn check_header(rom: &[u8]) -> Result<(), EmuError> {
+    let header = rom.get(CART_HEADER).ok_or(EmuError::FileTooSmall)?;
+    if header.iter().all(|&b| b == header[0]) {
+        return Err(EmuError::MissingHeader);
+    }
+    Ok(())
+}
+
+/// ROM size for the ROM size code in cartridge header, 0 if unknown.
+/// Banking uses the size of the file, this is only what it declares.
+fn rom_size(code: u8) -> usize {
+    match code {
+        0..=8 => (2 * SIZE_ROM_BANK) << code,
+        // Legacy codes found in a few old headers.
+        0x52 => 72 * SIZE_ROM_BANK,
+        0x53 => 80 * SIZE_ROM_BANK,
+        0x54 => 96 * SIZE_ROM_BANK,
+        _ => 0,
+    }
+}
+
+/// External RAM size for the RAM size code in cartridge header.
+fn ext_ram_size(code: u8) -> usize {
+    match code {
+        2 => SIZE_EXT_RAM,
+        3 => 4 * SIZE_EXT_RAM,
+        4 => 16 * SIZE_EXT_RAM,
+        5 => 8 * SIZE_EXT_RAM,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MBC1 ROM of `banks` banks with the ROM size code `code`, every byte
+    /// of a bank is its number.
+    fn banked_rom(banks: usize, code: u8) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..banks).flat_map(|b| [b as u8; SIZE_ROM_BANK]).collect();
+        rom[CART_TYPE] = 0x01;
+        rom[CART_ROM_SIZE] = code;
+        rom
+    }
+
+    /// Check that every bank which MBC1 can map at 0x4000 reads as itself.
+    fn check_banks(cart: &mut Cartidge, banks: usize) {
+        // Banks 0x20, 0x40 and 0x60 cannot be mapped there.
+        for bank in (1..banks).filter(|b| b % 0x20 != 0) {
+            cart.write(0x2000, bank as u8 & 0x1F);
+            cart.write(0x4000, (bank >> 5) as u8);
+            assert_eq!(cart.read(0x4000), bank as u8, "bank {}", bank);
+            assert_eq!(cart.read(0x7FFF), bank as u8, "bank {}", bank);
+        }
+    }
+
+    #[test]
+    fn legacy_rom_size_codes() {
+        for (banks, code) in [(72, 0x52), (80, 0x53), (96, 0x54)] {
+            let rom = banked_rom(banks, code);
+            let info = CartridgeInfo::from_rom(&rom).unwrap();
+            assert_eq!(info.rom_size, banks * SIZE_ROM_BANK);
+            assert!(info.unsupported.is_empty(), "{:?}", info.unsupported);
+
+            let mut cart = Cartidge::new(&rom).unwrap();
+            assert_eq!(cart.rom_bank_count(), banks);
+            check_banks(&mut cart, banks);
+        }
+    }
+
+    #[test]
+    fn file_length_is_used_over_header() {
+        // Header declares 96 banks, but the file has 64.
+        let rom = banked_rom(64, 0x54);
+        assert_eq!(
+            CartridgeInfo::from_rom(&rom).unwrap().rom_size,
+            96 * SIZE_ROM_BANK
+        );
+        let mut cart = Cartidge::new(&rom).unwrap();
+        assert_eq!(cart.rom_bank_count(), 64);
+        check_banks(&mut cart, 64);
+
+        // Header declares 72 banks, but the file has 80.
+        let mut cart = Cartidge::new(&banked_rom(80, 0x52)).unwrap();
+        assert_eq!(cart.rom_bank_count(), 80);
+        check_banks(&mut cart, 80);
+    }
+}
@@ -1,9 +1,11 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    info::{CART_TYPE, SIZE_ROM_BANK},
-    EmuError,
+    info::{CART_LOGO, CART_LOGO_VAL, CART_TYPE, SIZE_ROM_BANK},
+    log, EmuError,
 };
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Mbc {
     /// Type of the Memory Bank Controller present in cartridge,
     /// which needs to be emulated as part of the memory system.
@@ -22,16 +24,24 @@ pub(crate) struct Mbc {
     bank_reg2: u8,
     bank_mode: u8,
     max_rom_banks: usize,
+    /// MBC1 multicart(MBC1M), which has only 4 bits of the first bank
+    /// register wired, so the second one selects a game of 16 banks.
+    multicart: bool,
+
+    /// Unsupported MBC writes were reported, so that it is logged once.
+    #[serde(skip)]
+    reported: bool,
 }
 
 impl Mbc {
     pub(crate) fn from_rom(rom: &[u8]) -> Result<Self, EmuError> {
-        let kind = CART_MBC_TYPE_TABLE[rom[CART_TYPE] as usize];
+        let cart_type = *rom.get(CART_TYPE).ok_or(EmuError::UnknownMBC)?;
+        let kind = CART_MBC_TYPE_TABLE[cart_type as usize];
 
         match kind {
-            MbcType::None | MbcType::Mbc1 => (),
             MbcType::Unknown => return Err(EmuError::UnknownMBC),
-            _ => unimplemented!(),
+            k if !k.is_supported() => return Err(EmuError::UnsupportedMBC(cart_type)),
+            _ => (),
         }
 
         Ok(Self {
@@ -40,29 +50,48 @@ impl Mbc {
             rom0_idx: 0,
             rom1_idx: 1,
             ram_idx: 0,
+            multicart: is_mbc1_multicart(rom),
             ..Default::default()
         })
     }
 
+    pub(crate) fn is_mbc7(&self) -> bool {
+        matches!(self.kind, MbcType::Mbc7)
+    }
+
+    /// Check if the MBC7 registers are accessible, it needs a second
+    /// enable along with the usual one.
+    pub(crate) fn are_mbc7_regs_enabled(&self) -> bool {
+        self.ram_enabled && self.bank_reg2 == 0x40
+    }
+
+    /// Only false if restored from a state with an unsupported MBC.
+    pub(crate) fn is_supported(&self) -> bool {
+        self.kind.is_supported()
+    }
+
     pub(crate) fn write(&mut self, addr: usize, val: u8) {
         match self.kind {
-            MbcType::Unknown => panic!("Unknown MBC type found"),
             MbcType::None => (),
             MbcType::Mbc1 => self.mbc1_write(addr, val),
-
-            MbcType::Mbc2 => todo!(),
-            MbcType::Mbc3 => todo!(),
-            MbcType::Mbc5 => todo!(),
-            MbcType::Mbc6 => todo!(),
-            MbcType::Mbc7 => todo!(),
-            MbcType::Mmm01 => todo!(),
-            MbcType::HuC1 => todo!(),
-            MbcType::HuC3 => todo!(),
+            MbcType::Mbc7 => {
+                self.mbc7_write(addr, val);
+                return;
+            }
+
+            // Loading rejects these, but a state could still have them.
+            // Ignore writes, the cartridge stays at its initial banks.
+            kind => {
+                if !self.reported {
+                    log::warn(&format!("mbc: writes to unsupported {:?} ignored", kind));
+                    self.reported = true;
+                }
+            }
         }
 
         // For MBC one only
         self.rom1_idx %= self.max_rom_banks;
-        if mask_val(self.rom1_idx as u8, 5) == 0 {
+        if mask_val(self.rom1_idx as u8, 5) == 0 && !self.multicart {
             self.rom1_idx |= 0x01;
         }
     }
@@ -87,22 +116,38 @@ impl Mbc {
             self.bank_reg1 = 1;
         }
 
-        // Calculate addresses as specified by MBC-1.
-        let b1 = (self.bank_reg2 << 5) | self.bank_reg1;
+        // Calculate addresses as specified by MBC-1. The check for bank 0
+        // above is done on all 5 bits even if only 4 are wired.
+        let (reg1, shift) = if self.multicart {
+            (mask_val(self.bank_reg1, 4), 4)
+        } else {
+            (self.bank_reg1, 5)
+        };
+        let b1 = (self.bank_reg2 << shift) | reg1;
         self.rom1_idx = b1 as usize % self.max_rom_banks;
 
         if self.bank_mode == 0 {
             self.rom0_idx = 0;
-            self.ram_idx = 1;
+            self.ram_idx = 0;
         } else {
-            let b0 = self.bank_reg2 << 5;
+            let b0 = self.bank_reg2 << shift;
             self.rom0_idx = b0 as usize % self.max_rom_banks;
             self.ram_idx = self.bank_reg2 as usize;
         }
     }
+
+    fn mbc7_write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val == 0xA,
+            // All 8 bits select the bank, even bank 0.
+            0x2000..=0x3FFF => self.rom1_idx = val as usize % self.max_rom_banks,
+            0x4000..=0x5FFF => self.bank_reg2 = val,
+            _ => {}
+        }
+    }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 enum MbcType {
     #[default]
     Unknown,
@@ -119,6 +164,52 @@ enum MbcType {
     HuC3,
 }
 
+impl MbcType {
+    fn is_supported(self) -> bool {
+        matches!(self, Self::None | Self::Mbc1 | Self::Mbc7)
+    }
+
+    fn name(self) -> Option<&'static str> {
+        let name = match self {
+            Self::Unknown => return None,
+            Self::None => "none",
+            Self::Mbc1 => "MBC1",
+            Self::Mbc2 => "MBC2",
+            Self::Mbc3 => "MBC3",
+            Self::Mbc5 => "MBC5",
+            Self::Mbc6 => "MBC6",
+            Self::Mbc7 => "MBC7",
+            Self::Mmm01 => "MMM01",
+            Self::HuC1 => "HuC1",
+            Self::HuC3 => "HuC3",
+        };
+        Some(name)
+    }
+}
+
+/// Get the MBC name for a cartridge type and whether it is emulated,
+/// `None` if the type is unknown.
+pub(crate) fn mbc_info(cart_type: u8) -> Option<(&'static str, bool)> {
+    let kind = CART_MBC_TYPE_TABLE[cart_type as usize];
+    Some((kind.name()?, kind.is_supported()))
+}
+
+/// Check if it is an MBC1 multicart. They are 1MiB and each game has its
+/// own header, so the logo is repeated at the start of every 16 banks.
+pub(crate) fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    let is_mbc1 = rom
+        .get(CART_TYPE)
+        .is_some_and(|&t| matches!(CART_MBC_TYPE_TABLE[t as usize], MbcType::Mbc1));
+    if !is_mbc1 || rom.len() != 64 * SIZE_ROM_BANK {
+        return false;
+    }
+
+    [0x10, 0x20, 0x30].iter().any(|bank| {
+        let logo = bank * SIZE_ROM_BANK + CART_LOGO.start();
+        rom[logo..][..CART_LOGO_VAL.len()] == CART_LOGO_VAL
+    })
+}
+
 /// MBC type table, indexed by the value of CART_TYPE byte in cartridge header.
 const CART_MBC_TYPE_TABLE: [MbcType; 256] = {
     use MbcType::*;
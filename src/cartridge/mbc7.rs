@@ -0,0 +1,249 @@
+//! MBC7 registers, mapped at 0xA000-0xAFFF in place of RAM. It has a
+//! two-axis accelerometer and a 93LC56 serial EEPROM which holds the save
+//! data, the EEPROM is accessed by bit-banging its pins through a register.
+
+use serde::{Deserialize, Serialize};
+
+/// Size of the EEPROM, 128 16-bit words.
+pub(crate) const SIZE_EEPROM: usize = 256;
+
+/// Accelerometer value when level and the change in it for a tilt of 1g.
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_1G: f32 = 0x70 as f32;
+/// Accelerometer value after erasing, until a new one is latched.
+const ACCEL_ERASED: u16 = 0x8000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Mbc7 {
+    /// Tilt along the X(right) and Y(down) axes in g. It is an input like
+    /// the buttons, so it is not serialized.
+    #[serde(skip)]
+    pub(crate) tilt: (f32, f32),
+    /// Latched accelerometer values.
+    accel: (u16, u16),
+    /// A new value can be latched only after erasing the old one.
+    erased: bool,
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub(crate) fn new() -> Self {
+        Self {
+            tilt: (0.0, 0.0),
+            accel: (ACCEL_ERASED, ACCEL_ERASED),
+            erased: true,
+            eeprom: Eeprom::default(),
+        }
+    }
+
+    /// Read a register, `offset` is from 0xA000.
+    pub(crate) fn read(&self, offset: usize) -> u8 {
+        if offset >= 0x1000 {
+            return 0xFF;
+        }
+
+        let (x, y) = self.accel;
+        match (offset >> 4) & 0xF {
+            0x2 => x as u8,
+            0x3 => (x >> 8) as u8,
+            0x4 => y as u8,
+            0x5 => (y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    /// Write a register, `offset` is from 0xA000. `data` is the EEPROM.
+    pub(crate) fn write(&mut self, offset: usize, val: u8, data: &mut [u8]) {
+        if offset >= 0x1000 {
+            return;
+        }
+
+        match (offset >> 4) & 0xF {
+            0x0 if val == 0x55 => {
+                self.accel = (ACCEL_ERASED, ACCEL_ERASED);
+                self.erased = true;
+            }
+            0x1 if val == 0xAA && self.erased => {
+                let axis = |g: f32| (ACCEL_CENTER + ACCEL_1G * g.clamp(-4.0, 4.0)) as u16;
+                self.accel = (axis(self.tilt.0), axis(self.tilt.1));
+                self.erased = false;
+            }
+            0x8 => self.eeprom.write(val, data),
+            _ => (),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+enum EepromState {
+    /// Waiting for the start bit.
+    #[default]
+    Idle,
+    /// Receiving the opcode and address.
+    Command,
+    /// Sending words from the address onwards.
+    Read(u8),
+    /// Receiving the word to write to the address, or to all of them.
+    Write(Option<u8>),
+    /// Command is done, waiting for CS to go low.
+    Done,
+}
+
+/// 93LC56 EEPROM organized as 16-bit words. Its pins are bit 7(CS),
+/// bit 6(CLK), bit 1(DI) and bit 0(DO) of its register, bits are shifted
+/// in and out on the rising edges of CLK while CS is high. Writes finish
+/// at once, so it never reports being busy.
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct Eeprom {
+    cs: bool,
+    clk: bool,
+    di: bool,
+    /// Level of the DO pin.
+    out: bool,
+    write_enabled: bool,
+    state: EepromState,
+    /// Bits shifted in since the start bit and their count.
+    input: u16,
+    input_len: u8,
+    /// Word being shifted out, MSB first, and the bits left in it.
+    output: u16,
+    output_len: u8,
+}
+
+impl Eeprom {
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.out as u8
+    }
+
+    fn write(&mut self, val: u8, data: &mut [u8]) {
+        let cs = val & 0x80 != 0;
+        let clk = val & 0x40 != 0;
+        let rising = clk && !self.clk;
+        self.di = val & 0x02 != 0;
+        self.clk = clk;
+
+        if !cs {
+            self.cs = false;
+            self.state = EepromState::Idle;
+            return;
+        }
+        self.cs = true;
+        if rising {
+            self.clock(data);
+        }
+    }
+
+    fn clock(&mut self, data: &mut [u8]) {
+        use EepromState::*;
+
+        match self.state {
+            Idle => {
+                if self.di {
+                    self.state = Command;
+                    self.shift_in(true);
+                }
+            }
+            Command => {
+                // Opcode is 2 bits and the address 8, of which the top one
+                // is ignored as there are only 128 words.
+                if self.shift_in(false) == 10 {
+                    self.command(data);
+                }
+            }
+            Read(addr) => {
+                if self.output_len == 0 {
+                    // Sequential reads go on to the next word.
+                    let next = (addr + 1) & 0x7F;
+                    self.load_output(next, data);
+                }
+                self.out = self.output & 0x8000 != 0;
+                self.output <<= 1;
+                self.output_len -= 1;
+            }
+            Write(addr) => {
+                if self.shift_in(false) == 16 {
+                    if self.write_enabled {
+                        match addr {
+                            Some(a) => set_word(data, a, self.input),
+                            None => (0..0x80).for_each(|a| set_word(data, a, self.input)),
+                        }
+                    }
+                    self.out = true;
+                    self.state = Done;
+                }
+            }
+            Done => (),
+        }
+    }
+
+    /// Shift DI in and get the number of bits received. A start bit
+    /// resets them.
+    fn shift_in(&mut self, start: bool) -> u8 {
+        if start {
+            self.input = 0;
+            self.input_len = 0;
+        } else {
+            self.input = (self.input << 1) | self.di as u16;
+            self.input_len += 1;
+        }
+        self.input_len
+    }
+
+    fn command(&mut self, data: &mut [u8]) {
+        use EepromState::*;
+
+        let opcode = self.input >> 8;
+        let addr = self.input as u8 & 0x7F;
+        let top_bits = (self.input >> 6) & 0b11;
+        self.input = 0;
+        self.input_len = 0;
+        self.state = Done;
+
+        match opcode {
+            // READ, a dummy 0 bit comes before the data.
+            0b10 => {
+                self.load_output(addr, data);
+                self.out = false;
+            }
+            // WRITE
+            0b01 => self.state = Write(Some(addr)),
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    set_word(data, addr, 0xFFFF);
+                }
+                self.out = true;
+            }
+            // The rest are told by the top 2 address bits.
+            _ => match top_bits {
+                // EWDS
+                0b00 => self.write_enabled = false,
+                // WRAL
+                0b01 => self.state = Write(None),
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        (0..0x80).for_each(|a| set_word(data, a, 0xFFFF));
+                    }
+                    self.out = true;
+                }
+                // EWEN
+                _ => self.write_enabled = true,
+            },
+        }
+    }
+
+    fn load_output(&mut self, addr: u8, data: &[u8]) {
+        let a = addr as usize * 2;
+        self.output = u16::from_le_bytes([data[a], data[a + 1]]);
+        self.output_len = 16;
+        self.state = EepromState::Read(addr);
+    }
+}
+
+fn set_word(data: &mut [u8], addr: u8, val: u16) {
+    let a = addr as usize * 2;
+    data[a..a + 2].copy_from_slice(&val.to_le_bytes());
+}
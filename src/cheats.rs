@@ -0,0 +1,80 @@
+//! Cheat codes in the Game Genie and GameShark formats.
+//!
+//! Game Genie codes patch bytes read from the ROM, GameShark codes write to
+//! RAM at every VBlank, so they overwrite whatever the game stored there.
+
+/// A decoded cheat code, parse one from its code string with `parse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cheat {
+    /// Reads of ROM at `addr` give `data` instead. If `compare` is given
+    /// then only when the ROM has that value there, so that the code only
+    /// affects the intended bank.
+    GameGenie {
+        addr: u16,
+        data: u8,
+        compare: Option<u8>,
+    },
+    /// Write `data` to `addr` at every VBlank. For cartridge RAM `bank` is
+    /// the bank it is written to, whichever one is mapped. If the cartridge
+    /// has no such bank, like for the usual 01 with a single bank, and for
+    /// other memory it is written to the mapped one.
+    GameShark { bank: u8, addr: u16, data: u8 },
+}
+
+impl Cheat {
+    /// Parse a code, dashes in it are ignored. Game Genie codes have 6 or
+    /// 9 hex digits like `00A-17B` or `00A-17B-C49`, and GameShark codes
+    /// have 8 like `010238CD`. `None` if it is not a valid code.
+    pub fn parse(code: &str) -> Option<Self> {
+        let digits = code
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<u8>>>()?;
+        let byte = |i: usize| (digits[i] << 4) | digits[i + 1];
+
+        match digits.len() {
+            6 | 9 => {
+                // Digits are DDAAAA[C?C], the address is rotated by a digit
+                // and its top digit is inverted. The compare value is
+                // rotated right by 2 bits and XORed, the digit between its
+                // two digits is a checksum which is not checked.
+                let addr = digits[2..6].iter().fold(0, |a, &d| (a << 4) | d as u16);
+                let addr = ((addr & 0xF) << 12 | addr >> 4) ^ 0xF000;
+                let compare = (digits.len() == 9)
+                    .then(|| ((digits[6] << 4) | digits[8]).rotate_right(2) ^ 0xBA);
+
+                // Only ROM can be patched.
+                (addr < 0x8000).then_some(Self::GameGenie {
+                    addr,
+                    data: byte(0),
+                    compare,
+                })
+            }
+            8 => Some(Self::GameShark {
+                bank: byte(0),
+                data: byte(2),
+                addr: u16::from_le_bytes([byte(4), byte(6)]),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Apply Game Genie codes to `val` read from ROM at `addr`.
+pub(crate) fn patch_rom(cheats: &[Cheat], addr: u16, val: u8) -> u8 {
+    for cheat in cheats {
+        if let &Cheat::GameGenie {
+            addr: at,
+            data,
+            compare,
+        } = cheat
+        {
+            if at == addr && compare.is_none_or(|c| c == val) {
+                return data;
+            }
+        }
+    }
+
+    val
+}
@@ -2,13 +2,16 @@ mod decoder;
 mod isa;
 mod table;
 
-use std::num::Wrapping;
+use std::{collections::VecDeque, num::Wrapping};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
     info::{self, SPEED_SWITCH_MCYCLES},
     log,
-    macros::bit_fields,
+    macros::{bit_fields, in_ranges},
     mem::Mmu,
+    msg::{Interrupt, InterruptEvent, Registers, RuntimeError},
     regs::Key1,
 };
 use isa::{Cond, Instr, Opcode, Operand, Reg};
@@ -18,10 +21,13 @@ use isa::{Cond, Instr, Opcode, Operand, Reg};
 /// Only LDH has such operands, they are: `[C]` and `[imm8]`.
 const LDH_OFFSET: u16 = 0xFF00;
 
+/// Maximum number of entries kept in the interrupt log, oldest are dropped.
+const INT_LOG_SIZE: usize = 256;
+
 /// Gameboy CPU emulator with support for double speed mode.  
 /// Instruction semantics are implemented as specified in:
 /// https://rgbds.gbdev.io/docs/v0.8.0/gbz80.7
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Cpu {
     // CPU owns the mmu and mmu owns rest of the system.
     pub(crate) mmu: Mmu,
@@ -30,7 +36,36 @@ pub struct Cpu {
     pub(crate) is_halted: bool,
     /// When stopped everything is stopped until a joystick interrupt.
     pub(crate) is_stopped: bool,
+    #[serde(skip)]
     pub(crate) trace_execution: bool,
+    /// Emulate the HALT bug, an option for accuracy.
+    #[serde(skip)]
+    pub(crate) halt_bug: bool,
+    /// HALT bug was hit, the byte after HALT is read twice.
+    repeat_next_byte: bool,
+    /// Total M-cycles elapsed since power on.
+    pub(crate) mcycles: u64,
+    /// M-cycles the rest of the machine was ticked by during this step,
+    /// as memory accesses tick it when they happen. Always 0 after it.
+    #[serde(skip)]
+    ticked: u16,
+    /// Recently dispatched interrupts, bounded by `INT_LOG_SIZE`.
+    #[serde(skip)]
+    pub(crate) int_log: VecDeque<InterruptEvent>,
+    /// Instructions executed, for instrumentation.
+    #[serde(skip)]
+    pub(crate) instructions: u64,
+    /// Address of the last instruction fetched.
+    #[serde(skip)]
+    prev_pc: u16,
+    /// Set when execution likely went astray, see `check_execution`.
+    /// Cleared by whoever acts on it.
+    #[serde(skip)]
+    pub(crate) went_astray: bool,
+    /// Set when `LD B, B` is executed, which test ROMs and homebrew use as
+    /// a software breakpoint. Cleared by whoever acts on it.
+    #[serde(skip)]
+    pub(crate) hit_software_break: bool,
 
     // Machine registers
     flags: Flags,
@@ -81,7 +116,7 @@ impl Cpu {
             5 // It takes 5-mcycles invoke ISR on an interrupt.
         } else {
             if self.is_halted {
-                1
+                self.mmu.idle_mcycles()
             } else if self.is_stopped {
                 0
             } else {
@@ -94,17 +129,49 @@ impl Cpu {
             self.ime_scheduled = false;
         }
 
-        self.mmu.tick(mcycles);
+        // Memory accesses already ticked the machine, tick it for the rest
+        // of the cycles. VRAM DMA stalls the CPU while rest of the machine
+        // keeps running.
+        debug_assert!(
+            self.ticked <= mcycles,
+            "ticked more than the instruction takes"
+        );
+        let rest = mcycles - self.ticked + std::mem::take(&mut self.mmu.dma_stall);
+        self.mmu.tick(rest);
+        let mcycles = std::mem::take(&mut self.ticked) + rest;
+        self.mcycles += mcycles as u64;
         mcycles
     }
 
+    /// Tick the rest of the machine by an M-cycle and then read a byte,
+    /// every memory access by the CPU takes an M-cycle.
+    fn read_cycle(&mut self, addr: u16) -> u8 {
+        self.tick_cycle();
+        self.mmu.cpu_read(addr)
+    }
+
+    /// Tick the rest of the machine by an M-cycle and then write a byte.
+    fn write_cycle(&mut self, addr: u16, val: u8) {
+        self.tick_cycle();
+        self.mmu.write(addr, val);
+    }
+
+    fn tick_cycle(&mut self) {
+        self.mmu.tick(1);
+        self.ticked += 1;
+    }
+
     /// Handle an interrupt if any and return true if handled.
     fn handle_interrupt(&mut self) -> bool {
         let ints = self.mmu.get_queued_ints();
 
         // Wakeup from low-power states when a servicable interrupts comes.
-        // We do not emulate any of the halt/stop bugs.
-        if ints.read() != 0 && (self.is_halted || self.is_stopped) {
+        // STOP is also exited by a joypad line going low, even if the
+        // joypad interrupt is not enabled.
+        // The HALT bug is handled when HALT runs, the STOP bugs are not
+        // emulated.
+        let joypad_wake = self.is_stopped && self.mmu.is_joypad_line_low();
+        if (ints.read() != 0 || joypad_wake) && (self.is_halted || self.is_stopped) {
             self.is_halted = false;
             self.is_stopped = false;
         }
@@ -117,21 +184,21 @@ impl Cpu {
         let mut iflag = self.mmu.iflag;
 
         // According to interrupt priority.
-        let new_pc = if ints.vblank == 1 {
+        let (kind, new_pc) = if ints.vblank == 1 {
             iflag.vblank = 0;
-            info::INT_VBLANK_VEC
+            (Interrupt::VBlank, info::INT_VBLANK_VEC)
         } else if ints.stat == 1 {
             iflag.stat = 0;
-            info::INT_STAT_VEC
+            (Interrupt::Stat, info::INT_STAT_VEC)
         } else if ints.timer == 1 {
             iflag.timer = 0;
-            info::INT_TIMER_VEC
+            (Interrupt::Timer, info::INT_TIMER_VEC)
         } else if ints.serial == 1 {
             iflag.serial = 0;
-            info::INT_SERIAL_VEC
+            (Interrupt::Serial, info::INT_SERIAL_VEC)
         } else if ints.joypad == 1 {
             iflag.joypad = 0;
-            info::INT_JOYPAD_VEC
+            (Interrupt::Joypad, info::INT_JOYPAD_VEC)
         } else {
             unreachable!("at least one interrupt is always present")
         };
@@ -139,6 +206,12 @@ impl Cpu {
         // Reset handeled interrupt in IF and disable further interrupts.
         self.mmu.iflag = iflag;
         self.ime = false;
+        self.log_interrupt(InterruptEvent {
+            kind,
+            mcycle: self.mcycles,
+            pc: self.pc.0,
+            vector: new_pc,
+        });
 
         // Start executing ISR. It takes a total of 5 M-cycles. Those are:
         // 2 wait states, 2 for saving PC and one for branching to ISR.
@@ -151,22 +224,32 @@ impl Cpu {
     fn exec_next_instr(&mut self) -> u16 {
         let old_pc = self.pc.0;
         let ins = self.fetch();
+        self.instructions += 1;
         let mut mcycles = ins.mcycles;
 
         let (oa, ob) = (ins.op1, ins.op2);
-        let a = self.get_op_val(oa);
+        // The destination of loads is not read, memory is accessed only
+        // as the instruction does.
+        use Opcode::*;
+        let a = if matches!(ins.op, Ld | Ldh | Pop) {
+            self.peek_op_val(oa)
+        } else {
+            self.get_op_val(oa)
+        };
         let b = self.get_op_val(ob);
 
         // M-cycles consumed for other memory accesses or operations by
         // instructions are calculated when they are run.
-        use Opcode::*;
         match ins.op {
+            Ld if matches!((oa, ob), (Operand::Reg(Reg::B), Operand::Reg(Reg::B))) => {
+                self.hit_software_break = true;
+            }
             Ld | Ldh => {
                 // `LD [a16], SP` loads two bytes.
                 if let (Operand::A16(a), Operand::Reg(Reg::SP)) = (oa, ob) {
                     let [h, l] = self.sp.0.to_be_bytes();
-                    self.mmu.write(a, l);
-                    self.mmu.write(a.wrapping_add(1), h);
+                    self.write_cycle(a, l);
+                    self.write_cycle(a.wrapping_add(1), h);
                 } else {
                     self.set_op_val(oa, b);
                 }
@@ -248,7 +331,17 @@ impl Cpu {
             // Setting IME=1 by EI is delayed by one cycle.
             Ei => self.ime_scheduled = true,
             // Halt CPU until an interrupt is recieved.
-            Halt => self.is_halted = true,
+            // If one is already pending with IME=0 it does not halt, and
+            // the byte after it is read twice as PC is not incremented
+            // after reading it the first time.
+            Halt => {
+                let pending = self.mmu.get_queued_ints().read() != 0;
+                if self.halt_bug && !self.ime && pending {
+                    self.repeat_next_byte = true;
+                } else {
+                    self.is_halted = true;
+                }
+            }
 
             Stop => {
                 let key = self.mmu.key1;
@@ -258,6 +351,7 @@ impl Cpu {
                     mcycles = SPEED_SWITCH_MCYCLES;
                 } else {
                     self.is_stopped = true;
+                    self.mmu.ppu.blank_frame();
                 }
                 self.mmu.timer.set_div(0);
             }
@@ -281,13 +375,20 @@ impl Cpu {
             Nop => (),
             Daa => self.do_daa(),
 
-            Illegal | Prefix => log::warn("cpu: illegal instruction detected, skipping"),
+            Illegal | Prefix => {
+                log::warn("cpu: illegal instruction detected, skipping");
+                // Illegal instructions are a single byte long.
+                let pc = self.pc.0.wrapping_sub(1);
+                let opcode = self.mmu.read(pc);
+                self.mmu
+                    .report_error(RuntimeError::IllegalInstruction { pc, opcode });
+            }
         }
 
         if self.trace_execution {
-            let newa = self.get_op_val(oa);
+            let newa = self.peek_op_val(oa);
             let sx = format!("[{oa}={a}|{newa} {ob}={b}]");
-            eprintln!(
+            log::trace(&format!(
                 "{sx:30} [Z{} N{} C{}] [PC:${:04X} IVEC({}): {:05b}] {}",
                 self.flags.z,
                 self.flags.n,
@@ -296,31 +397,116 @@ impl Cpu {
                 self.ime as u8,
                 self.mmu.iflag.read(),
                 ins,
-            );
+            ));
         }
 
         mcycles
     }
 
+    /// Get register values, for debugging.
+    pub(crate) fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            f: self.flags.read(),
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp.0,
+            pc: self.pc.0,
+            ime: self.ime,
+            is_halted: self.is_halted,
+            is_stopped: self.is_stopped,
+        }
+    }
+
+    /// Set the register values from `regs`, halt and stop states are kept.
+    pub(crate) fn set_registers(&mut self, regs: &Registers) {
+        self.a = regs.a;
+        self.flags.write(regs.f);
+        self.b = regs.b;
+        self.c = regs.c;
+        self.d = regs.d;
+        self.e = regs.e;
+        self.h = regs.h;
+        self.l = regs.l;
+        self.sp = Wrapping(regs.sp);
+        self.pc = Wrapping(regs.pc);
+        self.ime = regs.ime;
+    }
+
+    /// Decode the instruction at `addr` without executing it and return
+    /// it as text along with the address of the next instruction.
+    pub(crate) fn disassemble(&self, addr: u16) -> (String, u16) {
+        let (ins, next) = decoder::decode(&self.mmu, addr);
+        (ins.to_string(), next)
+    }
+
     /// Fetch the instruction pointed by PC, point PC to the next instruction
-    /// and increment `mcycles` according to the length of instruction.
+    /// and tick the machine by an M-cycle for each byte read.
     fn fetch(&mut self) -> Instr {
-        let (ins, pc) = decoder::decode(&mut self.mmu, self.pc.0);
-        if pc < self.pc.0 {
-            log::warn("cpu: PC overflow, wrapped back to zero")
+        let start = self.pc.0;
+        let repeat = std::mem::take(&mut self.repeat_next_byte);
+        let (ins, pc) = decoder::fetch(&mut |a| self.read_cycle(a), start, repeat);
+        if pc < start {
+            log::warn("cpu: PC overflow, wrapped back to zero");
+            self.mmu.report_error(RuntimeError::PcOverflow);
         }
+        self.check_execution(start, pc);
 
+        self.prev_pc = start;
         self.pc.0 = pc;
         ins
     }
 
-    /// Get numerical value for the operand.  
+    /// Report execution which is likely due to a banking or jump bug:
+    /// falling through from bank 0 into a bank other than 1, or entering
+    /// memory which does not hold code, like VRAM, OAM or I/O registers.
+    /// `start` is the address of the instruction and `next` of the one
+    /// after it.
+    fn check_execution(&mut self, start: u16, next: u16) {
+        let err = if start < 0x4000 && next >= 0x4000 {
+            let bank = self.mmu.cart.mapped_rom_banks().1;
+            (bank != 1).then_some(RuntimeError::BankFallThrough { bank })
+        } else if is_non_code_addr(start) && !is_non_code_addr(self.prev_pc) {
+            Some(RuntimeError::BadExecutionAddress {
+                pc: start,
+                from: self.prev_pc,
+                from_bank: self.mmu.rom_bank_at(self.prev_pc),
+            })
+        } else {
+            None
+        };
+
+        if let Some(err) = err {
+            log::warn(&format!("cpu: {}", err));
+            self.mmu.report_error(err);
+            self.went_astray = true;
+        }
+    }
+
+    /// Get numerical value for the operand, reading memory operands
+    /// takes an M-cycle.  
     /// For Cond 0 is returned as it has no numeric meaning.  
-    fn get_op_val(&self, op: Operand) -> u16 {
+    fn get_op_val(&mut self, op: Operand) -> u16 {
+        match self.get_op_addr(op) {
+            Some(addr) => self.read_cycle(addr) as u16,
+            None => self.peek_op_val(op),
+        }
+    }
+
+    /// Get numerical value for the operand like `get_op_val`, but memory
+    /// is read without taking any time, for looking at it.
+    fn peek_op_val(&self, op: Operand) -> u16 {
+        if let Some(addr) = self.get_op_addr(op) {
+            return self.mmu.read(addr) as u16;
+        }
+
         match op {
             Operand::Absent => 0,
             Operand::Reg(r) => self.get_reg(r),
-            Operand::RegMem(r) => self.mmu.read(self.get_mem_addr(r)) as u16,
 
             // Cond is seperately inspected whenever needed, so just return 0.
             Operand::Cond(_) => 0,
@@ -335,23 +521,29 @@ impl Cpu {
             // Flags should be set when `SP + e8` is used as a operand.
             Operand::SPplusI8(i) => (self.sp.0 as i32 + i as i32) as u16,
 
+            Operand::RegMem(_) | Operand::A8(_) | Operand::A16(_) => unreachable!(),
+        }
+    }
+
+    /// Get the address of a memory operand, `None` for other operands.
+    fn get_op_addr(&self, op: Operand) -> Option<u16> {
+        match op {
+            Operand::RegMem(r) => Some(self.get_mem_addr(r)),
             // [imm8] is a memory operand for LDH, see `LDH_OFFSET`.
-            Operand::A8(u) => self.mmu.read(u as u16 + LDH_OFFSET) as u16,
-            Operand::A16(u) => self.mmu.read(u) as u16,
+            Operand::A8(u) => Some(u as u16 + LDH_OFFSET),
+            Operand::A16(u) => Some(u),
+            _ => None,
         }
     }
 
     /// Set value for the given operand.Panics if the operand is not a
     /// destination, that is,  
     /// either a register(direct or indirect) or a memory address.
+    /// Writing memory operands takes an M-cycle.
     fn set_op_val(&mut self, op: Operand, val: u16) {
-        match op {
-            Operand::Reg(r) => self.set_reg(r, val),
-            Operand::RegMem(r) => self.mmu.write(self.get_mem_addr(r), val as u8),
-
-            // [imm8] is a memory operand for LDH, see `LDH_OFFSET`.
-            Operand::A8(u) => self.mmu.write(u as u16 + LDH_OFFSET, val as u8),
-            Operand::A16(u) => self.mmu.write(u, val as u8),
+        match (op, self.get_op_addr(op)) {
+            (_, Some(addr)) => self.write_cycle(addr, val as u8),
+            (Operand::Reg(r), None) => self.set_reg(r, val),
 
             _ => panic!("Operand is not a destination, it has no location"),
         }
@@ -423,16 +615,16 @@ impl Cpu {
         let [h, l] = v.to_be_bytes();
 
         self.sp -= 1;
-        self.mmu.write(self.sp.0, h);
+        self.write_cycle(self.sp.0, h);
         self.sp -= 1;
-        self.mmu.write(self.sp.0, l);
+        self.write_cycle(self.sp.0, l);
     }
 
     /// Pop 2-bytes and increment `mcycles`.
     fn do_pop(&mut self) -> u16 {
-        let l = self.mmu.read(self.sp.0);
+        let l = self.read_cycle(self.sp.0);
         self.sp += 1;
-        let h = self.mmu.read(self.sp.0);
+        let h = self.read_cycle(self.sp.0);
         self.sp += 1;
 
         u16::from_be_bytes([h, l])
@@ -653,7 +845,6 @@ impl Cpu {
         // Tell all other components that we are now operating in
         // dual speed mode. This is done only once, so this is fine.
         self.mmu.is_2x = true;
-        self.mmu.ppu.fetcher.is_2x = true;
         self.mmu.timer.is_2x = true;
         self.mmu.serial.is_2x = true;
 
@@ -664,6 +855,14 @@ impl Cpu {
         };
     }
 
+    /// Record a dispatched interrupt, dropping the oldest entry if full.
+    fn log_interrupt(&mut self, event: InterruptEvent) {
+        if self.int_log.len() == INT_LOG_SIZE {
+            self.int_log.pop_front();
+        }
+        self.int_log.push_back(event);
+    }
+
     /// Set carry(to carry.LSB==1) and zero(to zero==0) flags.
     /// Set rest of the flags to 0.
     fn set_cz00(&mut self, carry: u8, zero: u8) {
@@ -735,3 +934,16 @@ fn get_hl_delta(op: Operand) -> i16 {
         _ => 0,
     }
 }
+
+/// Check if `addr` is in memory which never holds code.
+fn is_non_code_addr(addr: u16) -> bool {
+    use info::*;
+    in_ranges!(
+        addr as usize,
+        ADDR_VRAM,
+        ADDR_OAM,
+        ADDR_UNUSABLE,
+        ADDR_IO_REGS,
+        ADDR_IE
+    )
+}
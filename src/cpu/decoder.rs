@@ -8,11 +8,24 @@ use super::table;
 ///
 /// Any overflows when calculating the new PC are ignored, it
 /// should be checked by the caller to see if PC has wrapped around.
-pub(crate) fn decode(mmu: &mut Mmu, pc: u16) -> (Instr, u16) {
-    let (ins, pc) = decode_one(&table::INSTR_TABLE, mmu, pc);
+pub(crate) fn decode(mmu: &Mmu, pc: u16) -> (Instr, u16) {
+    decode_from(&mut |a| mmu.read(a), pc, 1)
+}
+
+/// Decodes like `decode`, but each byte is read with `read` in the order
+/// the CPU fetches them, so that it can tick the machine per byte. If
+/// `repeat` then PC is not incremented after reading the first byte, so it
+/// is read again. It is how the HALT bug decodes.
+pub(crate) fn fetch(read: &mut impl FnMut(u16) -> u8, pc: u16, repeat: bool) -> (Instr, u16) {
+    decode_from(read, pc, !repeat as u16)
+}
+
+/// Decodes with PC incremented by `step` after the first byte.
+fn decode_from(read: &mut impl FnMut(u16) -> u8, pc: u16, step: u16) -> (Instr, u16) {
+    let (ins, pc) = decode_one(&table::INSTR_TABLE, read, pc, step);
 
     if matches!(ins.op, Opcode::Prefix) {
-        let (ins, pc) = decode_one(&table::PREF_INSTR_TABLE, mmu, pc);
+        let (ins, pc) = decode_one(&table::PREF_INSTR_TABLE, read, pc, 1);
         (ins, pc)
     } else {
         (ins, pc)
@@ -20,13 +33,18 @@ pub(crate) fn decode(mmu: &mut Mmu, pc: u16) -> (Instr, u16) {
 }
 
 /// Decodes one-byte instruction using the given table.
-fn decode_one(table: &[Instr], mmu: &mut Mmu, pc: u16) -> (Instr, u16) {
-    let mut ins = table[mmu.read(pc) as usize];
-    let pc = pc.wrapping_add(1);
+fn decode_one(
+    table: &[Instr],
+    read: &mut impl FnMut(u16) -> u8,
+    pc: u16,
+    step: u16,
+) -> (Instr, u16) {
+    let mut ins = table[read(pc) as usize];
+    let pc = pc.wrapping_add(step);
 
     // Only one of the operands can be immediate at a time.
-    let (op1, pc) = fill_in_if_imm(ins.op1, mmu, pc);
-    let (op2, pc) = fill_in_if_imm(ins.op2, mmu, pc);
+    let (op1, pc) = fill_in_if_imm(ins.op1, read, pc);
+    let (op2, pc) = fill_in_if_imm(ins.op2, read, pc);
     ins.op1 = op1;
     ins.op2 = op2;
 
@@ -35,18 +53,19 @@ fn decode_one(table: &[Instr], mmu: &mut Mmu, pc: u16) -> (Instr, u16) {
 
 /// Extracts immediate and returns its value as `Operand` and its size.  
 /// If not an immediate. then returns the `operand` unchanged and 0 size.
-fn fill_in_if_imm(operand: Operand, mmu: &mut Mmu, pc: u16) -> (Operand, u16) {
+fn fill_in_if_imm(operand: Operand, read: &mut impl FnMut(u16) -> u8, pc: u16) -> (Operand, u16) {
     use Operand::*;
-    let as_u16 = || u16::from_le_bytes([mmu.read(pc), mmu.read(pc + 1)]);
+    // The low byte is read first.
+    let mut as_u16 = || u16::from_le_bytes([read(pc), read(pc.wrapping_add(1))]);
 
     let (op, size) = match operand {
         A16(_) => (A16(as_u16()), 2),
         U16(_) => (U16(as_u16()), 2),
 
-        A8(_) => (A8(mmu.read(pc)), 1),
-        U8(_) => (U8(mmu.read(pc)), 1),
-        I8(_) => (I8(mmu.read(pc) as i8), 1),
-        SPplusI8(_) => (SPplusI8(mmu.read(pc) as i8), 1),
+        A8(_) => (A8(read(pc)), 1),
+        U8(_) => (U8(read(pc)), 1),
+        I8(_) => (I8(read(pc) as i8), 1),
+        SPplusI8(_) => (SPplusI8(read(pc) as i8), 1),
 
         _ => (operand, 0),
     };
@@ -58,7 +58,7 @@ pub(crate) const INSTR_TABLE: [Instr; 256] = {
     a[0x0D] = ins!(1, 1, Dec, Op::Reg(Reg::C));
     a[0x0E] = ins!(2, 2, Ld, Op::Reg(Reg::C), Op::U8(0));
     a[0x0F] = ins!(1, 1, Rrca);
-    a[0x10] = ins!(1, 1, Stop, Op::U8(0));
+    a[0x10] = ins!(2, 2, Stop, Op::U8(0));
     a[0x11] = ins!(3, 3, Ld, Op::Reg(Reg::DE), Op::U16(0));
     a[0x12] = ins!(2, 2, Ld, Op::RegMem(Reg::DE), Op::Reg(Reg::A));
     a[0x13] = ins!(2, 2, Inc, Op::Reg(Reg::DE));
@@ -564,3 +564,60 @@ pub(crate) const PREF_INSTR_TABLE: [Instr; 256] = {
 
     a
 };
+
+// Check the cycles of all 512 instructions at compile time. Branches take
+// at least as long when taken, and other instructions the same either way.
+// Each byte fetched and each memory access takes an M-cycle, and the CPU
+// ticks the machine as they happen, so an instruction must not take fewer.
+const _: () = {
+    let mut i = 0;
+    while i < 256 {
+        check_cycles(&INSTR_TABLE[i], false);
+        check_cycles(&PREF_INSTR_TABLE[i], true);
+        i += 1;
+    }
+};
+
+const fn check_cycles(ins: &Instr, is_prefixed: bool) {
+    let is_branch = matches!(ins.op, Jr | Jp | Call | Ret | Reti | Rst);
+    assert!(ins.branch_mcycles >= ins.mcycles);
+    assert!(is_branch || ins.branch_mcycles == ins.mcycles);
+
+    if matches!(ins.op, Prefix | Illegal) {
+        return;
+    }
+
+    let fetched = 1 + is_prefixed as u16 + imm_size(ins.op1) + imm_size(ins.op2);
+    let (m1, m2) = (is_mem(ins.op1) as u16, is_mem(ins.op2) as u16);
+    let accessed = match ins.op {
+        // `LD [a16], SP` writes two bytes.
+        Ld | Ldh if matches!(ins.op2, Op::Reg(Reg::SP)) => 2 * m1,
+        Ld | Ldh => m1 + m2,
+        Push | Pop => 2,
+        // Read, modify and write back.
+        Inc | Dec | Rlc | Rrc | Rl | Rr | Sla | Sra | Srl | Swap => 2 * m1,
+        Res | Set => 2 * m2,
+        _ => m1 + m2,
+    };
+    // Taken branches push or pop the return address.
+    let stacked = if matches!(ins.op, Call | Ret | Reti | Rst) {
+        2
+    } else {
+        0
+    };
+
+    assert!(ins.mcycles >= fetched + accessed);
+    assert!(ins.branch_mcycles >= fetched + accessed + stacked);
+}
+
+const fn imm_size(op: Operand) -> u16 {
+    match op {
+        Op::U16(_) | Op::A16(_) => 2,
+        Op::U8(_) | Op::I8(_) | Op::A8(_) | Op::SPplusI8(_) => 1,
+        _ => 0,
+    }
+}
+
+const fn is_mem(op: Operand) -> bool {
+    matches!(op, Op::RegMem(_) | Op::A8(_) | Op::A16(_))
+}
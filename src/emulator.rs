@@ -1,50 +1,763 @@
 use std::{
-    io::Write,
     sync::mpsc::{self, RecvError, TryRecvError},
-    time::Instant,
-};
-
-use macroquad::{
-    miniquad::date::now,
-    rand::{rand, srand},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    cartridge::Cartidge,
+    cartridge::{Cartidge, CartridgeInfo},
+    cheats::Cheat,
     cpu::Cpu,
-    frame::Frame,
-    info, log,
+    frame::{Color, Frame, ScaledFrame},
+    info,
+    ir::IrSource,
+    log,
     mem::Mmu,
-    msg::{EmulatorMsg, UserMsg},
-    EmuError,
+    msg::{
+        ButtonState, EmulatorMsg, FrameTiming, LcdcOverride, LcdcState, LineObjects,
+        OppositeDirections, Registers, RuntimeError, UserMsg, VideoMemory, Watch,
+    },
+    rewind::RewindBuffer,
+    scale::{self, ScaleFilter},
+    serial::Console,
+    sgb::SGB_PLAYERS,
+    slots::SaveSlots,
+    state, testrom, EmuError,
 };
 
+/// How far the emulated clock may run ahead of the wall clock before
+/// sleeping. Sleeping is coarse, so do it only in larger chunks.
+const MAX_LEAD_SECS: f64 = 0.002;
+
 pub struct Emulator {
     cpu: Cpu,
     /// Total T-cycles ticked since last `timer_reset`.
     tcycles: u64,
     target_freq: u32,
     actual_freq: f64,
-    start_time: Instant,
+    /// When it started running at the current speed. It is set only by
+    /// `run`, as not every platform has a clock, like wasm32.
+    start_time: Option<Instant>,
     is_running: bool,
+    is_paused: bool,
+    /// Pause once the frame being drawn is completed.
+    pause_pending: bool,
+    /// See `set_break_on_bad_execution`.
+    break_on_bad_execution: bool,
     frame_requested: bool,
+    scale_filter: Option<ScaleFilter>,
+    rewind: Option<RewindBuffer>,
+    is_rewinding: bool,
+    /// Frame count when the last state was saved to `rewind`.
+    rewind_frame_count: u64,
+    /// Time spent since the last `take_frame_timing`, work is counted
+    /// from the counts in `timing_counts`.
+    timing: FrameTiming,
+    timing_counts: FrameTiming,
+    /// Slots used by `save_to_slot` and `load_from_slot`.
+    slots: SaveSlots,
+    /// Boot ROM the machine starts with, also on reset.
+    boot_rom: Option<Vec<u8>>,
+    opposite_directions: OppositeDirections,
+    /// See `UserMsg::SetWatches`.
+    watches: Vec<Watch>,
+    /// Buttons last set for each player and what they were resolved to,
+    /// see `set_opposite_directions`.
+    last_buttons: [(ButtonState, ButtonState); SGB_PLAYERS],
+}
+
+impl Clone for Emulator {
+    /// Same as `fork`.
+    fn clone(&self) -> Self {
+        self.fork()
+    }
 }
 
 impl Emulator {
     pub fn new(rom: &[u8]) -> Result<Self, EmuError> {
+        Self::with_boot_rom(rom, None)
+    }
+
+    /// Start by running a DMG or CGB boot ROM, which shows the logo and
+    /// sets up the machine before the game starts. Without one, the
+    /// machine starts in the state the boot ROM leaves it in.
+    ///
+    /// The machine is a DMG or a CGB by which boot ROM it is, even if the
+    /// cartridge is for the other one.
+    pub fn from_rom_with_bootrom(rom: &[u8], boot_rom: &[u8]) -> Result<Self, EmuError> {
+        Self::with_boot_rom(rom, Some(boot_rom))
+    }
+
+    fn with_boot_rom(rom: &[u8], boot_rom: Option<&[u8]>) -> Result<Self, EmuError> {
+        if boot_rom
+            .is_some_and(|b| ![info::SIZE_DMG_BOOT_ROM, info::SIZE_CGB_BOOT_ROM].contains(&b.len()))
+        {
+            return Err(EmuError::InvalidBootRom);
+        }
+
         let cartidge = Cartidge::new(rom)?;
         let mmu = Mmu::new(cartidge);
-        let cpu = Cpu::new(mmu);
+        let mut emu = Self::with_cpu(Cpu::new(mmu));
+        emu.boot_rom = boot_rom.map(|b| b.to_vec());
+        emu.init();
+
+        Ok(emu)
+    }
+
+    /// Resume from a save-state file made by `save_state`. Rewind history
+    /// kept in it is restored, rewinding is enabled with a size limit
+    /// fitting it.
+    pub fn from_state(data: &[u8]) -> Result<Self, EmuError> {
+        let (cpu, history) = state::load(data)?;
+        let mut emu = Self::with_cpu(cpu);
+        if !history.is_empty() {
+            let rewind = RewindBuffer::decode(&history).ok_or(EmuError::InvalidSaveState)?;
+            emu.rewind = Some(rewind);
+        }
+        Ok(emu)
+    }
+
+    /// Resume from a state made by `machine_state` using `rom`.
+    pub(crate) fn from_machine_state(data: &[u8], rom: &[u8]) -> Result<Self, EmuError> {
+        Ok(Self::with_cpu(state::decode(data, rom.into())?))
+    }
+
+    /// Get a built-in test cartridge ROM for checking video latency and
+    /// frame pacing. It shows a bar moving 2 pixels per frame and inverts
+    /// the colors for one frame out of every 60.
+    pub fn test_pattern_rom() -> Vec<u8> {
+        testrom::test_pattern_rom()
+    }
+
+    /// Get a built-in demo cartridge ROM for checking that the controls
+    /// and video work. The D-pad moves a square and A, B, Select and Start
+    /// fill a box each while held.
+    #[cfg(feature = "demo")]
+    pub fn demo_rom() -> Vec<u8> {
+        testrom::demo_rom()
+    }
 
-        Ok(Self {
+    /// Check if `data` looks like a save-state file, rather than a ROM.
+    pub fn is_save_state(data: &[u8]) -> bool {
+        state::is_save_state(data)
+    }
+
+    fn with_cpu(cpu: Cpu) -> Self {
+        Self {
             cpu,
             tcycles: 0,
             target_freq: info::FREQUENCY,
             actual_freq: 0.0,
-            start_time: Instant::now(),
+            start_time: None,
             is_running: false,
+            is_paused: false,
+            pause_pending: false,
+            break_on_bad_execution: false,
             frame_requested: false,
-        })
+            scale_filter: None,
+            rewind: None,
+            is_rewinding: false,
+            rewind_frame_count: 0,
+            timing: FrameTiming::default(),
+            timing_counts: FrameTiming::default(),
+            slots: SaveSlots::new(),
+            boot_rom: None,
+            opposite_directions: OppositeDirections::default(),
+            watches: Vec::new(),
+            last_buttons: Default::default(),
+        }
+    }
+
+    /// Save the machine state along with the ROM, restore it using
+    /// `from_state`. Options set on the emulator are not saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        state::save(&self.cpu, &[])
+    }
+
+    /// Same as `save_state`, but also keep the last `frames` states of the
+    /// rewind history in it, so rewinding works after resuming from it.
+    /// A few more may be kept, they are kept in groups of 60.
+    pub fn save_state_with_rewind(&self, frames: usize) -> Vec<u8> {
+        let history = match &self.rewind {
+            Some(rewind) if frames > 0 => rewind.encode_latest(frames),
+            _ => Vec::new(),
+        };
+        state::save(&self.cpu, &history)
+    }
+
+    /// Replace the save-slots used by `save_to_slot` and `load_from_slot`.
+    pub fn set_save_slots(&mut self, slots: SaveSlots) {
+        self.slots = slots;
+    }
+
+    pub fn save_slots(&self) -> &SaveSlots {
+        &self.slots
+    }
+
+    /// Save the state to slot `name` of the save-slots set on it, see
+    /// `SaveSlots::save`.
+    pub fn save_to_slot(&mut self, name: &str) -> Result<(), EmuError> {
+        let mut slots = std::mem::take(&mut self.slots);
+        let res = slots.save(name, self);
+        self.slots = slots;
+        res
+    }
+
+    /// Restore the state saved in slot `name` of the save-slots set on it,
+    /// keeping the options set on the emulator.
+    pub fn load_from_slot(&mut self, name: &str) -> Result<(), EmuError> {
+        let state = self.slots.machine_state(name)?;
+        if self.slots.rom() != self.rom() {
+            return Err(EmuError::RomMismatch);
+        }
+
+        let state = state.to_vec();
+        self.restore_machine_state(&state)?;
+        self.reset_timers();
+        Ok(())
+    }
+
+    /// Get the cartridge ROM, as it was loaded.
+    pub fn rom(&self) -> &[u8] {
+        self.cpu.mmu.cart.rom()
+    }
+
+    /// Get the cartridge header information, see `CartridgeInfo::from_rom`.
+    pub fn cartridge_info(&self) -> CartridgeInfo {
+        CartridgeInfo::from_rom(self.rom()).expect("a loaded ROM has a header")
+    }
+
+    /// Number of 16kiB ROM banks, the last one may be shorter.
+    pub fn rom_bank_count(&self) -> usize {
+        self.cpu.mmu.cart.rom_bank_count()
+    }
+
+    /// Get contents of a 16kiB ROM bank, it is read directly from the
+    /// ROM and does not affect the machine. Use `rom_bank_count` for the
+    /// valid banks, it is empty for the others.
+    pub fn rom_bank(&self, bank: usize) -> &[u8] {
+        self.cpu.mmu.cart.rom_bank(bank)
+    }
+
+    /// Get the ROM banks currently mapped at 0x0000 and 0x4000.
+    pub fn mapped_rom_banks(&self) -> (usize, usize) {
+        self.cpu.mmu.cart.mapped_rom_banks()
+    }
+
+    /// Save only the machine state, without the ROM.
+    pub(crate) fn machine_state(&self) -> Vec<u8> {
+        state::encode(&self.cpu)
+    }
+
+    /// Upper bound of the length of `machine_state` for as long as the game
+    /// runs. Cartridge RAM banks are allocated as the game switches to them,
+    /// and the pixel FIFOs and the palette writes of a line vary in length.
+    #[cfg(feature = "libretro")]
+    pub(crate) fn max_machine_state_len(&self) -> usize {
+        const BUFFERS_HEADROOM: usize = 4 * info::KB;
+        self.machine_state().len() + self.cpu.mmu.cart.unallocated_ram() + BUFFERS_HEADROOM
+    }
+
+    /// Restore a state made by `machine_state`, keeping the ROM and
+    /// options set on the emulator.
+    pub(crate) fn restore_machine_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        let cpu = state::decode(data, self.cpu.mmu.cart.shared_rom())?;
+        self.replace_cpu(cpu);
+        Ok(())
+    }
+
+    /// Check that saving and restoring the state loses nothing which
+    /// affects the machine. A copy restored from the state of this machine
+    /// and a plain copy are both run for `frames` frames, and then their
+    /// states and frames are compared. Returns names of the components
+    /// which differ, empty if none do.
+    pub fn check_state_round_trip(&self, frames: u64) -> Vec<&'static str> {
+        let mut original = Self::with_cpu(self.cpu.clone());
+        let mut restored = Self::with_cpu(self.cpu.clone());
+        restored
+            .restore_machine_state(&self.machine_state())
+            .expect("own state is always valid");
+
+        for _ in 0..frames {
+            original.run_frame(&[]);
+            restored.run_frame(&[]);
+        }
+
+        let mut diverged: Vec<_> = state::encode_components(&original.cpu)
+            .into_iter()
+            .zip(state::encode_components(&restored.cpu))
+            .filter(|(a, b)| a.1 != b.1)
+            .map(|(a, _)| a.0)
+            .collect();
+        // Presentation buffers are not a part of the state, but frames
+        // drawn after running from the same state must be the same.
+        let drawn = original.cpu.mmu.ppu.frame_count > self.cpu.mmu.ppu.frame_count;
+        if drawn && original.frame().hash() != restored.frame().hash() {
+            diverged.push("frame");
+        }
+        diverged
+    }
+
+    /// Switch to another ROM, like a rebuilt one, keeping options set on
+    /// the emulator. The machine starts afresh, unless `keep_state` is
+    /// set, then only the ROM is replaced and the game continues from the
+    /// same state. Keeping it works if the RAM layout of the game did not
+    /// change much, the MBC of the old ROM keeps being used.
+    pub fn replace_rom(&mut self, rom: &[u8], keep_state: bool) -> Result<(), EmuError> {
+        let fresh = Self::with_boot_rom(rom, self.boot_rom.as_deref())?;
+
+        if keep_state {
+            self.cpu.mmu.cart.set_rom(fresh.cpu.mmu.cart.shared_rom());
+            return Ok(());
+        }
+
+        self.replace_cpu(fresh.cpu);
+        self.target_freq = info::FREQUENCY;
+        self.reset_timers();
+        self.timing_counts = FrameTiming::default();
+        // History is of the old game.
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        Ok(())
+    }
+
+    /// Replace the machine, keeping the options set on the old one.
+    fn replace_cpu(&mut self, mut cpu: Cpu) {
+        cpu.trace_execution = self.cpu.trace_execution;
+        cpu.halt_bug = self.cpu.halt_bug;
+        cpu.mmu.ppu.uncap_objects = self.cpu.mmu.ppu.uncap_objects;
+        cpu.mmu.sgb.enabled = self.cpu.mmu.sgb.enabled;
+        cpu.mmu.overclock = self.cpu.mmu.overclock;
+        cpu.mmu.freezes = self.cpu.mmu.freezes.clone();
+        cpu.mmu.cheats = self.cpu.mmu.cheats.clone();
+        cpu.mmu.ir_source = self.cpu.mmu.ir_source.clone();
+        let (x, y) = self.cpu.mmu.cart.tilt();
+        cpu.mmu.cart.set_tilt(x, y);
+        cpu.instructions = self.cpu.instructions;
+        cpu.mmu.ppu.lines_drawn = self.cpu.mmu.ppu.lines_drawn;
+        cpu.mmu.serial.console = self.cpu.mmu.serial.console.take();
+        cpu.mmu.serial.link_reported = self.cpu.mmu.serial.link_reported;
+        cpu.mmu.ppu.fetcher.lcdc_override = self.cpu.mmu.ppu.fetcher.lcdc_override;
+        cpu.mmu.ppu.object_mask = self.cpu.mmu.ppu.object_mask;
+        let dmg_colors = self.cpu.mmu.ppu.dmg_colors;
+        self.cpu = cpu;
+        // Also sets up the registers for a fresh machine.
+        self.set_dmg_colors(dmg_colors);
+    }
+
+    /// Restart the machine with the same ROM, like turning it off and on.
+    /// Cartridge RAM and options set on the emulator are kept.
+    pub fn reset(&mut self) {
+        let mut fresh = Self::with_boot_rom(self.rom(), self.boot_rom.as_deref())
+            .expect("the ROM was loaded before");
+        fresh
+            .load_save_data(&self.save_data())
+            .expect("save data is from the same cartridge");
+
+        self.replace_cpu(fresh.cpu);
+        self.reset_timers();
+        self.timing_counts = FrameTiming::default();
+    }
+
+    /// Get the time spent and work done since the last call, divide the
+    /// work by `frames` for per frame numbers. Times are only counted by
+    /// `run`.
+    pub fn take_frame_timing(&mut self) -> FrameTiming {
+        let counts = FrameTiming {
+            frames: self.cpu.mmu.ppu.frame_count,
+            instructions: self.cpu.instructions,
+            lines_drawn: self.cpu.mmu.ppu.lines_drawn,
+            ..Default::default()
+        };
+        let base = self.timing_counts;
+        let timing = FrameTiming {
+            frames: counts.frames.saturating_sub(base.frames),
+            instructions: counts.instructions.saturating_sub(base.instructions),
+            lines_drawn: counts.lines_drawn.saturating_sub(base.lines_drawn),
+            ..self.timing
+        };
+
+        self.timing = FrameTiming::default();
+        self.timing_counts = counts;
+        timing
+    }
+
+    /// Get the cartridge RAM contents in battery save(.sav/.srm) format.
+    /// It is empty if the cartridge has no RAM.
+    pub fn save_data(&self) -> Vec<u8> {
+        self.cpu.mmu.cart.save_data()
+    }
+
+    /// Replace the cartridge RAM contents with a battery save. It must be
+    /// as large as `save_data` makes it, clock data appended by other
+    /// emulators for cartridges with an RTC is ignored.
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        self.cpu.mmu.cart.load_save_data(data)
+    }
+
+    /// Create an independent copy of the machine to explore alternative
+    /// inputs from the current point, the ROM is shared between copies.
+    ///
+    /// Only the machine state and options are copied, the copy is not
+    /// running and is not connected to any channels given to `run`.
+    pub fn fork(&self) -> Self {
+        let mut emu = Self::with_cpu(self.cpu.clone());
+        emu.scale_filter = self.scale_filter;
+        emu.break_on_bad_execution = self.break_on_bad_execution;
+        emu.boot_rom = self.boot_rom.clone();
+        emu.opposite_directions = self.opposite_directions;
+        emu.last_buttons = self.last_buttons;
+        emu.watches = self.watches.clone();
+        emu
+    }
+
+    /// Get the last frame completed by the PPU.
+    pub fn frame(&self) -> &Frame {
+        self.cpu.mmu.ppu.frame()
+    }
+
+    /// Fill the frame with `color`, lines drawn afterwards replace it.
+    pub fn clear_frame(&mut self, color: Color) {
+        self.cpu.mmu.ppu.clear_frame(color);
+    }
+
+    /// Draw all sprites on a line instead of only the first 10.
+    /// This is an enhancement which reduces flicker, but is not accurate.
+    pub fn set_uncapped_sprites(&mut self, enable: bool) {
+        self.cpu.mmu.ppu.uncap_objects = enable;
+    }
+
+    /// Run the CPU at twice the speed while the PPU, timers and everything
+    /// else run at the normal speed. Games which slow down when they have
+    /// too much work for a frame get twice the CPU time for it.
+    /// This is an enhancement which is not accurate, timing sensitive
+    /// games may break.
+    pub fn set_cpu_overclock(&mut self, enable: bool) {
+        self.cpu.mmu.overclock = enable;
+    }
+
+    /// Emulate the HALT bug: HALT with IME=0 and an interrupt pending does
+    /// not halt, and the byte after it is executed twice. Games rarely do
+    /// it by accident, but accuracy test ROMs check for it.
+    pub fn set_halt_bug(&mut self, enable: bool) {
+        self.cpu.halt_bug = enable;
+    }
+
+    /// Pause `run` right after executing an instruction which is likely
+    /// due to a banking or jump bug, like one in VRAM or past the end of
+    /// bank 0 into a bank other than 1. It is reported as an error either
+    /// way and `EmulatorMsg::Paused` is sent on pausing. Useful for homebrew
+    /// development, along with the debugger.
+    pub fn set_break_on_bad_execution(&mut self, enable: bool) {
+        self.break_on_bad_execution = enable;
+        self.cpu.went_astray = false;
+    }
+
+    /// Read bytes the game sends over the link port as text, for homebrew
+    /// which prints debug messages through it. Get the lines printed using
+    /// `take_console_lines`, they are also sent by `run`.
+    pub fn set_serial_console(&mut self, enable: bool) {
+        let serial = &mut self.cpu.mmu.serial;
+        if enable != serial.console.is_some() {
+            serial.console = enable.then(Console::default);
+        }
+    }
+
+    /// Take the lines printed to the serial console since the last call,
+    /// at most the last 256 are kept. Empty if it is not enabled.
+    pub fn take_console_lines(&mut self) -> Vec<String> {
+        let console = self.cpu.mmu.serial.console.as_mut();
+        console.map_or(Vec::new(), |c| c.take_lines())
+    }
+
+    /// Check if the game started a transfer over the link port as the
+    /// master since the last call, which needs a peer to answer it. Nothing
+    /// is attached to it, so frontends can tell the user the game wants to
+    /// link. True only the first time, and never with the serial console.
+    pub fn take_link_activity(&mut self) -> bool {
+        self.cpu.mmu.serial.take_link_request()
+    }
+
+    /// Draw monochrome games in color like a CGB does, using the palettes
+    /// its boot ROM gives games it does not know. It has no effect on
+    /// games supporting CGB, or when started with a boot ROM, which
+    /// decides it. If nothing has run yet, then the registers are also set
+    /// as the CGB boot ROM leaves them, games may detect the machine as a
+    /// CGB due to it.
+    pub fn set_dmg_colors(&mut self, enable: bool) {
+        let enable = enable && !self.cpu.mmu.cart.is_cgb && self.boot_rom.is_none();
+        self.cpu.mmu.ppu.dmg_colors = enable;
+
+        if enable && self.cpu.mcycles == 0 {
+            let regs = Registers {
+                a: 0x11,
+                f: 0x80,
+                b: 0x00,
+                c: 0x00,
+                d: 0x00,
+                e: 0x08,
+                h: 0x00,
+                l: 0x7C,
+                ..self.registers()
+            };
+            self.cpu.set_registers(&regs);
+        }
+    }
+
+    /// Check if the CPU has executed STOP and is waiting for a button press.
+    pub fn is_stopped(&self) -> bool {
+        self.cpu.is_stopped
+    }
+
+    /// Handle SGB multiplayer requests, so that SGB games supporting
+    /// 2-4 players can read joypads set by `set_player_buttons`.
+    /// Games may also detect the machine as an SGB due to it.
+    pub fn set_sgb_joypads(&mut self, enable: bool) {
+        self.cpu.mmu.sgb.enabled = enable;
+    }
+
+    /// Set buttons held on a joypad, `player` starts from 0 for the first
+    /// one. Others can be read only if enabled by `set_sgb_joypads`.
+    /// There are 4 joypads, buttons for any other player are ignored.
+    pub fn set_player_buttons(&mut self, player: usize, buttons: ButtonState) {
+        let Some(&(last, last_resolved)) = self.last_buttons.get(player) else {
+            return;
+        };
+        let resolved = buttons.resolve_opposites(self.opposite_directions, last, last_resolved);
+        self.last_buttons[player] = (buttons, resolved);
+
+        let (dpad, btns) = resolved.to_internal_repr();
+        self.cpu.mmu.set_buttons(player, dpad, btns);
+    }
+
+    /// Set how far the cartridge is tilted, for cartridges with an
+    /// accelerometer like Kirby Tilt 'n' Tumble. `x` and `y` are in g along
+    /// the right and down axes of the screen, 0 when held level and 1 when
+    /// held on its side. Ignored for other cartridges.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.mmu.cart.set_tilt(x, y);
+    }
+
+    /// Set how opposite D-pad directions held together are passed to the
+    /// game, it applies from the next time buttons are set. All are passed
+    /// by default.
+    pub fn set_opposite_directions(&mut self, handling: OppositeDirections) {
+        self.opposite_directions = handling;
+    }
+
+    /// Log each executed instruction along with the registers it changed.
+    /// It is very slow, enable it only for a short while around a bug.
+    pub fn set_trace_execution(&mut self, enable: bool) {
+        self.cpu.trace_execution = enable;
+    }
+
+    /// Stable 64-bit hash of the machine state, it is the same for equal
+    /// states across runs and platforms. The frame and ROM are excluded.
+    pub fn state_hash(&self) -> u64 {
+        state::hash(&self.cpu)
+    }
+
+    /// Read a byte from the memory as the CPU would see it currently.
+    /// It has no side-effects.
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.cpu.mmu.read(addr)
+    }
+
+    /// Write a byte to the memory as the CPU would, writes to ROM and
+    /// registers have the same side-effects, like switching banks.
+    pub fn write_memory(&mut self, addr: u16, val: u8) {
+        self.cpu.mmu.write(addr, val)
+    }
+
+    /// Check if `LD B, B` was executed since the last call. Test ROMs use it
+    /// for telling that they are done, and homebrew as a breakpoint.
+    pub fn take_software_break(&mut self) -> bool {
+        std::mem::take(&mut self.cpu.hit_software_break)
+    }
+
+    /// Take the problems found while running since the last call, `run`
+    /// sends them as `EmulatorMsg::Error` instead.
+    pub fn take_errors(&mut self) -> Vec<RuntimeError> {
+        self.cpu.mmu.take_errors()
+    }
+
+    /// Keep `addr` at `val`, for cheats like infinite health which games
+    /// keep overwriting. The value is written at once and writes to it
+    /// write `val` again instead. `None` removes the freeze.
+    ///
+    /// Freezes are options, they are not saved in states.
+    pub fn set_memory_freeze(&mut self, addr: u16, val: Option<u8>) {
+        self.cpu.mmu.set_freeze(addr, val);
+    }
+
+    /// Enable a cheat code, see `Cheat`. Like freezes, cheats are options
+    /// and they are not saved in states.
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        if !self.cpu.mmu.cheats.contains(&cheat) {
+            self.cpu.mmu.cheats.push(cheat);
+        }
+    }
+
+    /// Disable a cheat code enabled by `add_cheat`.
+    pub fn remove_cheat(&mut self, cheat: Cheat) {
+        self.cpu.mmu.cheats.retain(|&c| c != cheat);
+    }
+
+    /// Get the cheat codes in effect, in the order they were added.
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cpu.mmu.cheats
+    }
+
+    /// Set where the light seen by the infrared port of a CGB comes from,
+    /// games which poll it for ambient noise behave differently. It is dark
+    /// by default. It is an option, it is not saved in states.
+    pub fn set_ir_source(&mut self, source: IrSource) {
+        self.cpu.mmu.ir_source = source;
+    }
+
+    /// Get how the PPU interprets LCDC, like whether the window was shown
+    /// in the last frame and which tile data is used.
+    pub fn lcdc_state(&self) -> LcdcState {
+        let ppu = &self.cpu.mmu.ppu;
+        LcdcState::new(
+            ppu.fetcher.lcdc.read(),
+            ppu.fetcher.lcdc_override,
+            ppu.window_lines,
+        )
+    }
+
+    /// Force LCDC bits on or off for drawing, see `LcdcOverride`.
+    pub fn set_lcdc_override(&mut self, overrides: LcdcOverride) {
+        self.cpu.mmu.ppu.fetcher.lcdc_override = overrides;
+    }
+
+    /// Read the current values of `watches`, in the same order.
+    pub fn watch_values(&self, watches: &[Watch]) -> Vec<u16> {
+        watches
+            .iter()
+            .map(|w| {
+                let lo = self.read_memory(w.addr) as u16;
+                if w.is_word {
+                    lo | (self.read_memory(w.addr.wrapping_add(1)) as u16) << 8
+                } else {
+                    lo
+                }
+            })
+            .collect()
+    }
+
+    /// Get the objects selected for line `ly` (0-143) from the current
+    /// OAM and in which order they are drawn, see `LineObjects`.
+    pub fn line_objects(&self, ly: u8) -> LineObjects {
+        self.cpu.mmu.ppu.line_objects(ly)
+    }
+
+    /// Hide OAM entries from drawing, bit N of `mask` hides entry N.
+    /// Hidden objects are left out before the limit of 10 objects per
+    /// line applies, as if they were not on the line.
+    pub fn set_object_mask(&mut self, mask: u64) {
+        self.cpu.mmu.ppu.object_mask = mask;
+    }
+
+    /// Get a copy of VRAM, OAM and registers used for drawing, for
+    /// showing tiles, maps and sprites in debuggers.
+    pub fn video_memory(&self) -> VideoMemory {
+        let m = &self.cpu.mmu;
+        let frame_info = self.frame().info;
+        let reg = |addr: usize| m.read(addr as u16);
+
+        VideoMemory {
+            vram: *m.ppu.fetcher.vram,
+            oam: m.ppu.oam,
+            is_cgb: frame_info.is_cgb,
+            lcdc: reg(info::IO_LCDC),
+            scy: reg(info::IO_SCY),
+            scx: reg(info::IO_SCX),
+            dmg_palettes: [reg(info::IO_BGP), reg(info::IO_OBP0), reg(info::IO_OBP1)],
+            bg_palettes: frame_info.bg_palettes,
+            obj_palettes: frame_info.obj_palettes,
+        }
+    }
+
+    /// Get the last completed frame upscaled using `filter`.
+    pub fn scaled_frame(&self, filter: ScaleFilter) -> ScaledFrame {
+        scale::scale(self.frame(), filter)
+    }
+
+    /// Run as fast as possible until the PPU completes a frame and return it.
+    /// Use it for driving the emulator without `run`, from the same thread.
+    ///
+    /// If the LCD is off, then it returns after a frame's worth of time.
+    /// If the CPU is stopped, then it returns immediately unless `buttons`
+    /// wakes it up.
+    pub fn step_frame(&mut self, buttons: ButtonState) -> &Frame {
+        self.set_player_buttons(0, buttons);
+        self.run_frame(&[]);
+        self.cpu.mmu.ppu.frame()
+    }
+
+    /// Same as `step_frame`, but returns early with true as soon as PC
+    /// becomes `pc`, the instruction there is not yet executed.
+    pub fn step_frame_until(&mut self, buttons: ButtonState, pc: u16) -> bool {
+        self.step_frame_until_any(buttons, &[pc])
+    }
+
+    /// Same as `step_frame_until`, but for any of the addresses in `pcs`,
+    /// use it for breakpoints.
+    pub fn step_frame_until_any(&mut self, buttons: ButtonState, pcs: &[u16]) -> bool {
+        self.set_player_buttons(0, buttons);
+        self.run_frame(pcs)
+    }
+
+    /// Execute one instruction, or dispatch an interrupt if one is pending.
+    pub fn step_instruction(&mut self) {
+        self.cpu.step();
+    }
+
+    /// Get address of the next instruction to be executed.
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc.0
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    /// Change the registers, for debuggers. The halt and stop states are
+    /// kept as they are.
+    pub fn set_registers(&mut self, regs: &Registers) {
+        self.cpu.set_registers(regs);
+    }
+
+    /// Decode the instruction at `addr` and return it as text along with
+    /// the address of the next instruction. Nothing is executed.
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        self.cpu.disassemble(addr)
+    }
+
+    /// Run until a frame is completed or PC becomes one of `stop_pcs`.
+    /// Returns true if stopped due to `stop_pcs`.
+    fn run_frame(&mut self, stop_pcs: &[u16]) -> bool {
+        let start = self.cpu.mmu.ppu.frame_count;
+        let mut dots = 0;
+
+        while self.cpu.mmu.ppu.frame_count == start && dots < info::PPU_FRAME_DOTS {
+            let mcycles = self.cpu.step() as u32;
+            if self.cpu.is_stopped {
+                break;
+            }
+            if stop_pcs.contains(&self.cpu.pc.0) {
+                return true;
+            }
+            dots += if self.cpu.mmu.is_2x {
+                mcycles * 2
+            } else {
+                mcycles * 4
+            } / self.cpu_speed_factor();
+        }
+
+        false
     }
 
     /// Start the emulator and run until `UserMsg::Shutdown` is recieved.
@@ -59,10 +772,8 @@ impl Emulator {
         user_msg_rx: mpsc::Receiver<UserMsg>,
         emu_msg_tx: mpsc::Sender<EmulatorMsg>,
     ) {
-        self.init();
-        self.reset_timers();
         self.is_running = true;
-        // self.cpu.trace_execution = true;
+        self.reset_timers();
 
         // Run several steps at once, total must be less than VBLANK interval.
         // VBLANK is 4560 dots and the longest it takes for a step is 24 dots.
@@ -70,45 +781,151 @@ impl Emulator {
         // mcycle is made up of 2 or 4 dots, and 4*6 = 24.
         // So number of steps should be less than 190 (=4560/24) always.
         while self.is_running {
-            for _ in 0..128 {
-                self.step();
-            }
+            self.run_steps(&emu_msg_tx);
 
-            // If CPU is stopped then we wait in blocking mode.
-            if !self.handle_msgs(&user_msg_rx, &emu_msg_tx, !self.cpu.is_stopped) {
+            // If CPU is stopped or paused then we wait in blocking mode.
+            let non_blocking = !self.cpu.is_stopped && !self.is_paused;
+            if !self.handle_msgs(&user_msg_rx, &emu_msg_tx, non_blocking) {
                 log::error("emulator: send/recieve channels closed abnormally");
                 break;
             }
 
             // Only send back frame after entring VBLANK mode to avoid jitter.
-            if self.frame_requested && self.cpu.mmu.get_mode() == info::MODE_VBLANK {
-                let mut f = Box::new(Frame::default());
-
-                print!("\r{:.3}Hz", self.actual_freq / 1e6);
-                std::io::stdout().flush().unwrap();
-
-                self.cpu.mmu.ppu.fill_frame(f.as_mut());
-                self.frame_requested = false;
-                emu_msg_tx.send(EmulatorMsg::NewFrame(f)).unwrap();
-            }
-
-            // Busy-wait until clock starts lagging behind.
-            loop {
-                let elapsed = self.start_time.elapsed().as_secs_f64();
-                let expected = elapsed * self.target_freq as f64;
-                let actual = self.tcycles as f64;
-                // if actual > expected {
-                //     sleep(Duration::from_secs_f64(
-                //         (actual - expected) / (self.target_freq as f64),
-                //     ));
-                //     break;
-                // }
-
-                if expected > actual {
-                    self.actual_freq = actual / elapsed;
+            // When paused or stopped the frame does not change, so send it
+            // right away.
+            let in_vblank = self.cpu.mmu.get_mode() == info::MODE_VBLANK;
+            let is_frozen = self.is_paused || self.cpu.is_stopped;
+            if self.frame_requested && (in_vblank || is_frozen) {
+                self.send_frame(&emu_msg_tx);
+            }
+
+            // Sleep once we are far enough ahead of the wall clock instead
+            // of busy-waiting, to keep the CPU usage low.
+            let elapsed = self.elapsed_secs();
+            let lead = self.tcycles as f64 / self.target_freq as f64 - elapsed;
+            if lead > MAX_LEAD_SECS {
+                let start = Instant::now();
+                thread::sleep(Duration::from_secs_f64(lead));
+                self.timing.sleep += start.elapsed();
+            }
+
+            self.update_actual_freq();
+        }
+    }
+
+    /// Handle the messages sent so far and run until the next frame is
+    /// completed, then send it if requested. It is an alternative to `run`
+    /// for running the emulator on the frontend thread, which then sets the
+    /// pace as it never sleeps. It runs at most a frame's worth of cycles,
+    /// nothing if paused, so that it returns even if the LCD is off.
+    ///
+    /// Returns false once `UserMsg::Shutdown` is handled or the channel is
+    /// closed, it must not be called after that.
+    pub fn run_once(
+        &mut self,
+        user_msg_rx: &mpsc::Receiver<UserMsg>,
+        emu_msg_tx: &mpsc::Sender<EmulatorMsg>,
+    ) -> bool {
+        if !self.is_running {
+            self.is_running = true;
+            self.reset_timers();
+        }
+
+        loop {
+            match user_msg_rx.try_recv() {
+                Ok(msg) => {
+                    if !self.handle_msg(msg, emu_msg_tx) {
+                        return false;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+        if !self.is_running {
+            return false;
+        }
+
+        let speed = (self.target_freq / info::FREQUENCY) as u64;
+        let end = self.tcycles + info::PPU_FRAME_DOTS as u64 * speed;
+        while !self.is_paused && !self.cpu.is_stopped && self.tcycles < end {
+            if self.run_steps(emu_msg_tx) {
+                break;
+            }
+        }
+        if self.frame_requested {
+            self.send_frame(emu_msg_tx);
+        }
+
+        self.update_actual_freq();
+        true
+    }
+
+    /// Run a batch of steps, or go back a frame if rewinding, and send the
+    /// problems and console lines. Returns true if a frame was completed.
+    fn run_steps(&mut self, emu_msg_tx: &mpsc::Sender<EmulatorMsg>) -> bool {
+        let start = Instant::now();
+        let frames = self.cpu.mmu.ppu.frame_count;
+        if self.is_paused {
+            // Nothing to run.
+        } else if self.is_rewinding {
+            self.rewind_frame();
+            self.apply_pending_pause(true);
+        } else {
+            for _ in 0..128 {
+                self.step();
+                if self.pause_pending && self.cpu.mmu.ppu.frame_count != frames {
                     break;
                 }
+                if std::mem::take(&mut self.cpu.went_astray) && self.break_on_bad_execution {
+                    self.is_paused = true;
+                    self.pause_pending = false;
+                    _ = emu_msg_tx.send(EmulatorMsg::Paused);
+                    break;
+                }
+            }
+            self.record_frame();
+            let new_frame = self.cpu.mmu.ppu.frame_count != frames;
+            if new_frame && !self.watches.is_empty() {
+                let values = self.watch_values(&self.watches);
+                _ = emu_msg_tx.send(EmulatorMsg::WatchValues(values));
             }
+            self.apply_pending_pause(new_frame);
+        }
+        self.timing.emulation += start.elapsed();
+
+        // A closed channel is noticed while handling messages.
+        for err in self.take_errors() {
+            _ = emu_msg_tx.send(EmulatorMsg::Error(err));
+        }
+        for line in self.take_console_lines() {
+            _ = emu_msg_tx.send(EmulatorMsg::ConsoleLine(line));
+        }
+        if self.take_link_activity() {
+            _ = emu_msg_tx.send(EmulatorMsg::LinkActivityDetected);
+        }
+
+        self.cpu.mmu.ppu.frame_count != frames
+    }
+
+    /// Send the last completed frame, as it was requested.
+    fn send_frame(&mut self, emu_msg_tx: &mpsc::Sender<EmulatorMsg>) {
+        let msg = if let Some(filter) = self.scale_filter {
+            EmulatorMsg::NewScaledFrame(Box::new(self.scaled_frame(filter)))
+        } else {
+            let mut f = Box::new(Frame::default());
+            self.cpu.mmu.ppu.fill_frame(f.as_mut());
+            EmulatorMsg::NewFrame(f)
+        };
+
+        self.frame_requested = false;
+        _ = emu_msg_tx.send(msg);
+    }
+
+    fn update_actual_freq(&mut self) {
+        let elapsed = self.elapsed_secs();
+        if elapsed > 0.0 {
+            self.actual_freq = self.tcycles as f64 / elapsed;
         }
     }
 
@@ -137,7 +954,59 @@ impl Emulator {
             self.target_freq = info::FREQUENCY_2X;
         }
 
-        self.tcycles += mcycles as u64 * 4;
+        self.tcycles += mcycles as u64 * 4 / self.cpu_speed_factor() as u64;
+    }
+
+    /// How many times faster the CPU runs than the rest of the machine.
+    fn cpu_speed_factor(&self) -> u32 {
+        if self.cpu.mmu.overclock {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Pause if requested and a frame was just completed, so that the last
+    /// frame is whole. No frames are produced while the LCD is off or the
+    /// CPU is stopped, then it pauses at once.
+    fn apply_pending_pause(&mut self, frame_completed: bool) {
+        let lcd_off = self.cpu.mmu.ppu.fetcher.lcdc.ppu_enable == 0;
+        if self.pause_pending && (frame_completed || lcd_off || self.cpu.is_stopped) {
+            self.pause_pending = false;
+            self.is_paused = true;
+        }
+    }
+
+    /// Save the state to the rewind history once per frame, right after
+    /// the frame is completed.
+    fn record_frame(&mut self) {
+        let count = self.cpu.mmu.ppu.frame_count;
+        if count == self.rewind_frame_count {
+            return;
+        }
+
+        self.rewind_frame_count = count;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.push_state(state::encode(&self.cpu));
+        }
+    }
+
+    /// Go back a frame in the rewind history, it stays at the oldest one
+    /// when the history runs out.
+    fn rewind_frame(&mut self) {
+        // Damaged deltas are rejected when the history is loaded.
+        if let Some(Ok(state)) = self.rewind.as_mut().and_then(|r| r.pop_state()) {
+            self.restore_machine_state(&state)
+                .expect("rewind states are always valid");
+            // States are saved right after a frame is completed, but the
+            // frame is not a part of it, so run to the next one to redraw.
+            self.run_frame(&[]);
+            self.rewind_frame_count = self.cpu.mmu.ppu.frame_count;
+        }
+
+        // Take a frame's worth of time, like running forward does.
+        let speed = (self.target_freq / info::FREQUENCY) as u64;
+        self.tcycles += info::PPU_FRAME_DOTS as u64 * speed;
     }
 
     /// Handle user messages and respond to them.
@@ -161,10 +1030,34 @@ impl Emulator {
             }
         };
 
+        self.handle_msg(msg, msg_tx)
+    }
+
+    /// Handle a user message and respond to it.
+    /// Returns false if sending failed, otherwise true.
+    fn handle_msg(&mut self, msg: UserMsg, msg_tx: &mpsc::Sender<EmulatorMsg>) -> bool {
         match msg {
             UserMsg::Buttons(btns) => {
-                let (dpad, btns) = btns.to_internal_repr();
-                self.cpu.mmu.update_joypad(dpad, btns);
+                self.set_player_buttons(0, btns);
+                true
+            }
+
+            UserMsg::PlayerButtons(player, btns) => {
+                self.set_player_buttons(player, btns);
+                true
+            }
+
+            UserMsg::UpdateTilt(x, y) => {
+                self.set_tilt(x, y);
+                true
+            }
+
+            UserMsg::SetSgbJoypads(enable) => {
+                self.set_sgb_joypads(enable);
+                true
+            }
+            UserMsg::SetOppositeDirections(handling) => {
+                self.set_opposite_directions(handling);
                 true
             }
 
@@ -174,46 +1067,281 @@ impl Emulator {
                 true
             }
 
+            UserMsg::GetFrameIfNew(seen) => {
+                if self.frame().info.sequence > seen {
+                    self.frame_requested = true;
+                    true
+                } else {
+                    msg_tx.send(EmulatorMsg::NoNewFrame).is_ok()
+                }
+            }
+
             UserMsg::GetFrequency => msg_tx
                 .send(EmulatorMsg::Frequency(self.actual_freq))
                 .is_ok(),
 
+            UserMsg::SetUncappedSprites(enable) => {
+                self.set_uncapped_sprites(enable);
+                true
+            }
+
+            UserMsg::SetCpuOverclock(enable) => {
+                self.set_cpu_overclock(enable);
+                true
+            }
+
+            UserMsg::SetHaltBug(enable) => {
+                self.set_halt_bug(enable);
+                true
+            }
+
+            UserMsg::SetSerialConsole(enable) => {
+                self.set_serial_console(enable);
+                true
+            }
+
+            UserMsg::SetBreakOnBadExecution(enable) => {
+                self.set_break_on_bad_execution(enable);
+                true
+            }
+
+            UserMsg::SetPaused(pause) => {
+                // Do not try to catch up for the time spent paused.
+                if self.is_paused && !pause {
+                    self.reset_timers();
+                }
+                if pause && !self.is_paused {
+                    self.pause_pending = true;
+                } else {
+                    self.is_paused = pause;
+                    self.pause_pending = false;
+                }
+                true
+            }
+
+            UserMsg::StepFrame => {
+                if self.is_paused {
+                    self.run_frame(&[]);
+                    self.record_frame();
+                }
+                true
+            }
+
+            UserMsg::SetTraceExecution(enable) => {
+                self.set_trace_execution(enable);
+                true
+            }
+
+            UserMsg::SetScaleFilter(filter) => {
+                self.scale_filter = filter;
+                true
+            }
+
+            UserMsg::GetFrameTiming => msg_tx
+                .send(EmulatorMsg::FrameTiming(self.take_frame_timing()))
+                .is_ok(),
+
+            UserMsg::GetVideoMemory => {
+                let mem = Box::new(self.video_memory());
+                msg_tx.send(EmulatorMsg::VideoMemory(mem)).is_ok()
+            }
+
+            UserMsg::GetLcdcState => msg_tx
+                .send(EmulatorMsg::LcdcState(self.lcdc_state()))
+                .is_ok(),
+
+            UserMsg::SetLcdcOverride(overrides) => {
+                self.set_lcdc_override(overrides);
+                true
+            }
+
+            UserMsg::GetLineObjects(ly) => msg_tx
+                .send(EmulatorMsg::LineObjects(self.line_objects(ly)))
+                .is_ok(),
+
+            UserMsg::SetObjectMask(mask) => {
+                self.set_object_mask(mask);
+                true
+            }
+
+            UserMsg::SetWatches(watches) => {
+                self.watches = watches;
+                true
+            }
+
+            UserMsg::GetInterruptLog => {
+                let log = self.cpu.int_log.iter().copied().collect();
+                msg_tx.send(EmulatorMsg::InterruptLog(log)).is_ok()
+            }
+
+            UserMsg::ReplaceRom(rom, keep_state) => match self.replace_rom(&rom, keep_state) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error(&format!("emulator: cannot replace ROM: {:?}", e));
+                    let err = RuntimeError::ReplaceRom(e);
+                    msg_tx.send(EmulatorMsg::Error(err)).is_ok()
+                }
+            },
+
+            UserMsg::SetMemoryFreeze(addr, val) => {
+                self.set_memory_freeze(addr, val);
+                true
+            }
+
+            UserMsg::SetIrSource(source) => {
+                self.set_ir_source(source);
+                true
+            }
+
+            UserMsg::AddCheat(cheat) => {
+                self.add_cheat(cheat);
+                true
+            }
+
+            UserMsg::RemoveCheat(cheat) => {
+                self.remove_cheat(cheat);
+                true
+            }
+
+            UserMsg::ListCheats => {
+                let cheats = self.cheats().to_vec();
+                msg_tx.send(EmulatorMsg::Cheats(cheats)).is_ok()
+            }
+
+            UserMsg::SaveSlot(name) => {
+                let reply = match self.save_to_slot(&name) {
+                    Ok(()) => EmulatorMsg::StateSaved(name),
+                    Err(e) => EmulatorMsg::SlotError(name, e),
+                };
+                msg_tx.send(reply).is_ok()
+            }
+
+            UserMsg::LoadSlot(name) => {
+                let reply = match self.load_from_slot(&name) {
+                    Ok(()) => EmulatorMsg::StateLoaded(name),
+                    Err(e) => EmulatorMsg::SlotError(name, e),
+                };
+                msg_tx.send(reply).is_ok()
+            }
+
+            UserMsg::Reset => {
+                self.reset();
+                true
+            }
+
+            UserMsg::SetRewindBuffer(max_size) => {
+                // Keep the history restored from a save-state.
+                match &mut self.rewind {
+                    Some(rewind) if max_size > 0 => rewind.set_max_size(max_size),
+                    _ => self.rewind = (max_size > 0).then(|| RewindBuffer::new(max_size)),
+                }
+                true
+            }
+
+            UserMsg::SetRewinding(rewind) => {
+                self.is_rewinding = rewind;
+                true
+            }
+
             UserMsg::Shutdown => {
                 self.is_running = false;
                 msg_tx.send(EmulatorMsg::ShuttingDown).is_ok()
             }
 
-            UserMsg::ClearFrame(_) => todo!(),
-            UserMsg::DebuggerStart => todo!(),
-            UserMsg::DebuggerStep => todo!(),
-            UserMsg::DebuggerStop => todo!(),
+            UserMsg::DebuggerStart => {
+                self.is_paused = true;
+                self.pause_pending = false;
+                msg_tx
+                    .send(EmulatorMsg::Registers(self.registers()))
+                    .is_ok()
+            }
+            UserMsg::DebuggerStep => {
+                if self.is_paused {
+                    self.step();
+                }
+                msg_tx
+                    .send(EmulatorMsg::Registers(self.registers()))
+                    .is_ok()
+            }
+            UserMsg::DebuggerStop => {
+                self.reset_timers();
+                self.is_paused = false;
+                true
+            }
+
+            UserMsg::ClearFrame(color) => {
+                self.clear_frame(color);
+                true
+            }
         }
     }
 
     /// Initialize the registers and state, make it ready for execution.
     fn init(&mut self) {
+        let m = &mut self.cpu.mmu;
+        m.joypad.write(0xCF);
+        m.wram_idx = 1;
+
+        // The boot ROM starts from 0 and sets up the rest itself, its size
+        // tells the model. The CGB one loads palettes and selects the mode
+        // for the cartridge through KEY0.
+        if let Some(boot_rom) = &self.boot_rom {
+            let is_cgb = boot_rom.len() == info::SIZE_CGB_BOOT_ROM;
+            m.cart.is_cgb = is_cgb;
+            m.ppu.fetcher.is_cgb = is_cgb;
+            // A DMG always gives objects priority by X coordinate.
+            m.ppu.fetcher.opri = !is_cgb as u8;
+            m.boot_rom = boot_rom.clone();
+            return;
+        }
+
+        // Palettes are initialized to white, like the boot ROM does.
+        // This also keeps the initial state deterministic.
+        m.ppu.bg_palette.fill(0xFF);
+        m.ppu.obj_palette.fill(0xFF);
+
+        // For monochrome games, the CGB boot ROM loads compatibility
+        // palettes and sets object priority by X coordinate. They are not
+        // accessible to those games, see `set_dmg_colors`.
+        if !m.cart.is_cgb {
+            m.ppu.load_dmg_compat_palettes();
+            m.ppu.fetcher.opri = 1;
+        }
+
+        // Games supporting CGB are run in CGB mode, they detect a CGB
+        // from A being 0x11, as the CGB boot ROM leaves it.
+        m.ppu.fetcher.is_cgb = m.cart.is_cgb;
+
         // Initial values for starting up the program.
         self.cpu.pc.0 = 0x0100;
         self.cpu.sp.0 = 0xFFFE;
-
         let m = &mut self.cpu.mmu;
-        m.joypad.write(0xCF);
-        m.wram_idx = 1;
         m.ppu.bgp = 0xFC;
         m.ppu.fetcher.lcdc.write(0x91);
         m.ppu.stat.write(0x85);
 
-        srand((now() * 1000.0) as u64);
-        for n in m.ppu.bg_palette.iter_mut() {
-            *n = rand() as u8;
-        }
-        for n in m.ppu.bg_palette.iter_mut() {
-            *n = rand() as u8;
+        if m.cart.is_cgb {
+            let regs = Registers {
+                a: 0x11,
+                f: 0x80,
+                b: 0x00,
+                c: 0x00,
+                d: 0xFF,
+                e: 0x56,
+                h: 0x00,
+                l: 0x0D,
+                ..self.registers()
+            };
+            self.cpu.set_registers(&regs);
         }
     }
 
     fn reset_timers(&mut self) {
         self.tcycles = 0;
-        self.start_time = Instant::now();
+        self.start_time = self.is_running.then(Instant::now);
+    }
+
+    fn elapsed_secs(&self) -> f64 {
+        self.start_time.map_or(0.0, |t| t.elapsed().as_secs_f64())
     }
 }
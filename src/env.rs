@@ -0,0 +1,111 @@
+//! Reward-agnostic environment layer for agent training experiments.
+//!
+//! It wraps the synchronous `Emulator::step_frame` API in a `reset`/`step`
+//! interface, similar to Gym environments. Rewards are left to the user and
+//! can be computed from the RAM view returned with each observation.
+
+use crate::{ButtonState, EmuError, Emulator, Frame};
+
+/// Decides if an episode has finished by looking at the memory.
+pub type DoneHook = Box<dyn FnMut(RamView) -> bool + Send>;
+
+pub struct Env {
+    rom: Box<[u8]>,
+    emu: Emulator,
+    done_hook: Option<DoneHook>,
+    /// Frames stepped since last reset.
+    frames: u64,
+}
+
+/// State of the environment after a reset or step.
+pub struct Observation<'a> {
+    pub frame: &'a Frame,
+    pub ram: RamView<'a>,
+    /// Set if the done hook signaled the end of the episode.
+    pub done: bool,
+    /// Frames stepped since last reset.
+    pub frames: u64,
+}
+
+/// Read-only view of the memory, any address can be read from it.
+/// Reading has no side-effects on the emulator state.
+#[derive(Clone, Copy)]
+pub struct RamView<'a> {
+    emu: &'a Emulator,
+}
+
+impl RamView<'_> {
+    pub fn read(&self, addr: u16) -> u8 {
+        self.emu.read_memory(addr)
+    }
+
+    /// Read little-endian 16-bit value.
+    pub fn read_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
+    }
+
+    /// Copy `len` bytes starting at `addr`, wrapping around at the end.
+    pub fn snapshot(&self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.read(addr.wrapping_add(i as u16)))
+            .collect()
+    }
+}
+
+impl Env {
+    pub fn new(rom: &[u8]) -> Result<Self, EmuError> {
+        Ok(Self {
+            emu: Emulator::new(rom)?,
+            rom: rom.into(),
+            done_hook: None,
+            frames: 0,
+        })
+    }
+
+    /// Set the hook which is called after every frame to check if the
+    /// episode is done, stepping stops early when it returns true.
+    pub fn set_done_hook(&mut self, hook: impl FnMut(RamView) -> bool + Send + 'static) {
+        self.done_hook = Some(Box::new(hook));
+    }
+
+    /// Restart the game from power on.
+    pub fn reset(&mut self) -> Observation<'_> {
+        // Already verified that the ROM is valid in `new`.
+        self.emu = Emulator::new(&self.rom).unwrap();
+        self.frames = 0;
+        self.observe(false)
+    }
+
+    /// Hold `buttons` for at most `frames` frames.
+    pub fn step(&mut self, buttons: ButtonState, frames: u32) -> Observation<'_> {
+        let mut done = false;
+
+        for _ in 0..frames {
+            self.emu.step_frame(buttons);
+            self.frames += 1;
+
+            if let Some(hook) = self.done_hook.as_mut() {
+                done = hook(RamView { emu: &self.emu });
+            }
+            if done {
+                break;
+            }
+        }
+
+        self.observe(done)
+    }
+
+    /// Access the underlying emulator.
+    pub fn emulator(&self) -> &Emulator {
+        &self.emu
+    }
+
+    fn observe(&self, done: bool) -> Observation<'_> {
+        Observation {
+            frame: self.emu.frame(),
+            ram: RamView { emu: &self.emu },
+            done,
+            frames: self.frames,
+        }
+    }
+}
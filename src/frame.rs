@@ -1,13 +1,37 @@
-use crate::info::SCREEN_RESOLUTION;
+use crate::{hash::Fnv64, info::SCREEN_RESOLUTION};
 
 pub const SCREEN_SIZE: (usize, usize) = SCREEN_RESOLUTION;
 
 #[derive(Clone)]
 pub struct Frame {
     pixels: [[Color; SCREEN_RESOLUTION.0]; SCREEN_RESOLUTION.1],
+    /// Palette indices of the pixels, see `index`.
+    indices: [[u8; SCREEN_RESOLUTION.0]; SCREEN_RESOLUTION.1],
+    pub(crate) info: FrameInfo,
 }
 
-#[derive(Default, Clone, Copy, PartialEq, Eq)]
+/// Bit set in pixel indices of object pixels.
+pub const INDEX_OBJ: u8 = 1 << 5;
+
+/// Information about how a frame was rendered, captured when it completed.
+/// Frontends can use it for picking a post-processing method.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Number of frames completed since power on, including this one.
+    /// Frames with the same sequence number are the same frame.
+    pub sequence: u64,
+    /// Rendered in CGB mode, using color palettes.
+    pub is_cgb: bool,
+    pub lcd_enabled: bool,
+    /// Monochrome palettes(DMG mode only), 2-bits per color index from
+    /// LSB: BGP, OBP0 and OBP1.
+    pub dmg_palettes: [u8; 3],
+    /// Color palettes(CGB mode only) for background and objects.
+    pub bg_palettes: [[Color; 4]; 8],
+    pub obj_palettes: [[Color; 4]; 8],
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -33,12 +57,107 @@ impl Frame {
     pub fn set(&mut self, x: usize, y: usize, color: Color) {
         self.pixels[y][x] = color;
     }
+
+    /// Get the palette index a pixel was drawn with, bits 0-1 are the color
+    /// index and bits 2-4 are the palette number, which is 0-1 for objects
+    /// (OBP0/OBP1) and 0 for the background in DMG mode. Objects have
+    /// `INDEX_OBJ` set. Palettes are in `info`, except for DMG palettes
+    /// changed while the frame was drawn.
+    pub fn index(&self, x: usize, y: usize) -> u8 {
+        self.indices[y][x]
+    }
+
+    pub(crate) fn set_index(&mut self, x: usize, y: usize, index: u8) {
+        self.indices[y][x] = index;
+    }
+
+    /// Set all pixels to `color` with palette index 0.
+    pub(crate) fn fill(&mut self, color: Color) {
+        self.pixels = [[color; SCREEN_RESOLUTION.0]; SCREEN_RESOLUTION.1];
+        self.indices = [[0; SCREEN_RESOLUTION.0]; SCREEN_RESOLUTION.1];
+    }
+
+    pub fn info(&self) -> FrameInfo {
+        self.info
+    }
+
+    /// Get pixels in row-major order as RGB-24, 3 bytes per pixel.
+    pub fn to_rgb24(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flatten()
+            .flat_map(|c| [c.r, c.g, c.b])
+            .collect()
+    }
+
+    /// Get pixels in row-major order as palette indices, 1 byte per pixel,
+    /// see `index`.
+    pub fn to_indexed(&self) -> Vec<u8> {
+        self.indices.iter().flatten().copied().collect()
+    }
+
+    /// A hash of the pixel colors which is stable across runs and platforms.
+    pub fn hash(&self) -> u64 {
+        let mut h = Fnv64::new();
+        for c in self.pixels.iter().flatten() {
+            h.write(&[c.r, c.g, c.b]);
+        }
+        h.finish()
+    }
+}
+
+/// A frame of arbitrary size, produced by upscaling a `Frame`.
+#[derive(Clone)]
+pub struct ScaledFrame {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    info: FrameInfo,
+}
+
+impl ScaledFrame {
+    pub(crate) fn new(width: usize, height: usize, info: FrameInfo) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::default(); width * height],
+            info,
+        }
+    }
+
+    /// Information of the frame it was scaled from.
+    pub fn info(&self) -> FrameInfo {
+        self.info
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Get pixels in row-major order as RGB-24, 3 bytes per pixel.
+    pub fn to_rgb24(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect()
+    }
 }
 
 impl Default for Frame {
     fn default() -> Self {
         Frame {
             pixels: [[Default::default(); SCREEN_RESOLUTION.0]; SCREEN_RESOLUTION.1],
+            indices: [[0; SCREEN_RESOLUTION.0]; SCREEN_RESOLUTION.1],
+            info: Default::default(),
         }
     }
 }
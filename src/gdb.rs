@@ -0,0 +1,317 @@
+//! A GDB remote serial protocol server, for debugging game code with GDB,
+//! LLDB or IDEs which speak the protocol.
+//!
+//! GDB has no Game Boy target, so registers are exchanged in the layout of
+//! its Z80 target: AF, BC, DE, HL, SP, PC, IX, IY, AF', BC', DE', HL' and IR,
+//! each 16-bit little-endian. Registers missing on the Game Boy are zero and
+//! writes to them are ignored. Connect with:
+//!
+//! ```text
+//! (gdb) set architecture z80
+//! (gdb) target remote localhost:<port>
+//! ```
+//!
+//! Supported: reading and writing registers and memory, software and
+//! hardware breakpoints (both are the same), single-step, continue and
+//! interrupting a running machine with Ctrl-C.
+
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{ButtonState, Emulator, Registers};
+
+/// Number of registers in the Z80 layout.
+const REG_COUNT: usize = 13;
+
+/// Signal numbers used in stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Wait for a debugger to connect on `addr` and serve it until it detaches
+/// or kills the session. The machine stays paused unless the debugger lets
+/// it continue, and then it runs as fast as possible.
+pub fn serve_gdb(emu: &mut Emulator, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    let mut session = Session {
+        reader: BufReader::new(stream.try_clone()?),
+        stream,
+        breakpoints: Vec::new(),
+    };
+    session.run(emu)
+}
+
+struct Session {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    breakpoints: Vec<u16>,
+}
+
+enum Reply {
+    Packet(String),
+    /// Send the reply and end the session.
+    Last(String),
+}
+
+impl Session {
+    fn run(&mut self, emu: &mut Emulator) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(emu, &packet)? {
+                Reply::Packet(r) => self.write_packet(&r)?,
+                Reply::Last(r) => return self.write_packet(&r),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, emu: &mut Emulator, packet: &str) -> io::Result<Reply> {
+        let (cmd, rest) = packet.split_at(1.min(packet.len()));
+        let ok = || Reply::Packet("OK".into());
+        let error = || Reply::Packet("E01".into());
+
+        let reply = match cmd {
+            "?" => Reply::Packet(stop_reply(SIGTRAP)),
+            "g" => Reply::Packet(encode_regs(&emu.registers())),
+            "G" => match decode_hex(rest) {
+                Some(bytes) if bytes.len() >= 12 => {
+                    let mut regs = emu.registers();
+                    for (i, v) in bytes.chunks_exact(2).take(6).enumerate() {
+                        set_reg(&mut regs, i, u16::from_le_bytes([v[0], v[1]]));
+                    }
+                    emu.set_registers(&regs);
+                    ok()
+                }
+                _ => error(),
+            },
+            "p" => match usize::from_str_radix(rest, 16) {
+                Ok(n) if n < REG_COUNT => {
+                    Reply::Packet(hex(&get_reg(&emu.registers(), n).to_le_bytes()))
+                }
+                _ => error(),
+            },
+            "P" => {
+                let parsed = rest.split_once('=').and_then(|(n, v)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let v = decode_hex(v)?;
+                    Some((n, u16::from_le_bytes([*v.first()?, *v.get(1)?])))
+                });
+                match parsed {
+                    Some((n, v)) if n < REG_COUNT => {
+                        let mut regs = emu.registers();
+                        set_reg(&mut regs, n, v);
+                        emu.set_registers(&regs);
+                        ok()
+                    }
+                    _ => error(),
+                }
+            }
+            "m" => match parse_range(rest) {
+                Some((addr, len)) => {
+                    let bytes: Vec<u8> = (0..len)
+                        .map(|i| emu.read_memory(addr.wrapping_add(i)))
+                        .collect();
+                    Reply::Packet(hex(&bytes))
+                }
+                None => error(),
+            },
+            "M" => {
+                let parsed = rest
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)));
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        for (i, &b) in data.iter().enumerate() {
+                            emu.write_memory(addr.wrapping_add(i as u16), b);
+                        }
+                        ok()
+                    }
+                    _ => error(),
+                }
+            }
+            "c" | "s" => {
+                if let Ok(addr) = u16::from_str_radix(rest, 16) {
+                    let mut regs = emu.registers();
+                    regs.pc = addr;
+                    emu.set_registers(&regs);
+                }
+
+                let signal = if cmd == "s" {
+                    emu.step_instruction();
+                    SIGTRAP
+                } else {
+                    self.resume(emu)?
+                };
+                Reply::Packet(stop_reply(signal))
+            }
+            "Z" | "z" => match parse_breakpoint(rest) {
+                Some(addr) => {
+                    self.breakpoints.retain(|&a| a != addr);
+                    if cmd == "Z" {
+                        self.breakpoints.push(addr);
+                    }
+                    ok()
+                }
+                None => Reply::Packet(String::new()),
+            },
+            "H" | "T" => ok(),
+            "D" => Reply::Last("OK".into()),
+            "k" => Reply::Last(String::new()),
+            _ => Reply::Packet(query_reply(packet).into()),
+        };
+
+        Ok(reply)
+    }
+
+    /// Run until a breakpoint is hit or the debugger interrupts, returns the
+    /// signal for the stop reply.
+    fn resume(&mut self, emu: &mut Emulator) -> io::Result<u8> {
+        loop {
+            if emu.step_frame_until_any(ButtonState::default(), &self.breakpoints) {
+                return Ok(SIGTRAP);
+            }
+            if self.is_interrupted()? {
+                return Ok(SIGINT);
+            }
+        }
+    }
+
+    /// Check without blocking whether the debugger sent an interrupt.
+    fn is_interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let interrupted = match self.reader.fill_buf() {
+            Ok([]) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(buf) => buf[0] == 0x03,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+            Err(e) => return Err(e),
+        };
+
+        if interrupted {
+            self.reader.consume(1);
+        }
+        self.stream.set_nonblocking(false)?;
+        Ok(interrupted)
+    }
+
+    /// Read the next packet and acknowledge it, `None` if disconnected.
+    /// Interrupts and acknowledgements outside packets are skipped.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            if self.reader.skip_until(b'$')? == 0 {
+                return Ok(None);
+            }
+            if self.reader.read_until(b'#', &mut buf)? == 0 || buf.pop() != Some(b'#') {
+                return Ok(None);
+            }
+
+            let mut csum = [0; 2];
+            self.reader.read_exact(&mut csum)?;
+            let expected = std::str::from_utf8(&csum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+
+            if expected == Some(checksum(&buf)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&buf).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+/// Reply to queries and other packets, empty for unsupported ones.
+fn query_reply(packet: &str) -> &'static str {
+    match packet.split(':').next().unwrap_or_default() {
+        "qSupported" => "PacketSize=1000",
+        "qAttached" => "1",
+        "qfThreadInfo" => "m1",
+        "qsThreadInfo" => "l",
+        "qC" => "QC1",
+        _ => "",
+    }
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn get_reg(regs: &Registers, n: usize) -> u16 {
+    let pair = |hi: u8, lo: u8| u16::from_be_bytes([hi, lo]);
+    match n {
+        0 => pair(regs.a, regs.f),
+        1 => pair(regs.b, regs.c),
+        2 => pair(regs.d, regs.e),
+        3 => pair(regs.h, regs.l),
+        4 => regs.sp,
+        5 => regs.pc,
+        _ => 0,
+    }
+}
+
+fn set_reg(regs: &mut Registers, n: usize, v: u16) {
+    let [hi, lo] = v.to_be_bytes();
+    match n {
+        // Lower 4-bits of F are always 0.
+        0 => (regs.a, regs.f) = (hi, lo & 0xF0),
+        1 => (regs.b, regs.c) = (hi, lo),
+        2 => (regs.d, regs.e) = (hi, lo),
+        3 => (regs.h, regs.l) = (hi, lo),
+        4 => regs.sp = v,
+        5 => regs.pc = v,
+        _ => (),
+    }
+}
+
+fn encode_regs(regs: &Registers) -> String {
+    let bytes: Vec<u8> = (0..REG_COUNT)
+        .flat_map(|n| get_reg(regs, n).to_le_bytes())
+        .collect();
+    hex(&bytes)
+}
+
+/// Parse `addr,length` of memory packets.
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((
+        u16::from_str_radix(addr, 16).ok()?,
+        u16::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Parse `type,addr,kind` of breakpoint packets, only software and
+/// hardware breakpoints are supported.
+fn parse_breakpoint(s: &str) -> Option<u16> {
+    let mut parts = s.split(',');
+    match parts.next()? {
+        "0" | "1" => u16::from_str_radix(parts.next()?, 16).ok(),
+        _ => None,
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,135 @@
+//! A thread-safe handle to an emulator running in its own thread, for GUI
+//! frameworks where it is easier to call methods from callbacks than to
+//! exchange messages over channels.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{info, Button, ButtonState, Emulator, Frame};
+
+struct Shared {
+    core: Mutex<Core>,
+    is_running: AtomicBool,
+    is_paused: AtomicBool,
+}
+
+struct Core {
+    emu: Emulator,
+    buttons: ButtonState,
+}
+
+/// Runs the emulator at real-time speed in a new thread, which is stopped
+/// when the handle is dropped. It is cheap to clone, clones refer to the
+/// same emulator.
+#[derive(Clone)]
+pub struct EmulatorHandle {
+    shared: Arc<Shared>,
+    thread: Option<Arc<JoinHandle<()>>>,
+}
+
+impl EmulatorHandle {
+    /// Start running `emu` in a new thread.
+    pub fn new(emu: Emulator) -> Self {
+        let shared = Arc::new(Shared {
+            core: Mutex::new(Core {
+                emu,
+                buttons: ButtonState::default(),
+            }),
+            is_running: AtomicBool::new(true),
+            is_paused: AtomicBool::new(false),
+        });
+
+        let s = shared.clone();
+        let thread = thread::spawn(move || run(&s));
+
+        Self {
+            shared,
+            thread: Some(Arc::new(thread)),
+        }
+    }
+
+    pub fn press_button(&self, button: Button) {
+        self.lock().buttons.set(button, true);
+    }
+
+    pub fn release_button(&self, button: Button) {
+        self.lock().buttons.set(button, false);
+    }
+
+    /// Set state of all the buttons at once.
+    pub fn set_buttons(&self, buttons: ButtonState) {
+        self.lock().buttons = buttons;
+    }
+
+    /// Get a copy of the last completed frame.
+    pub fn frame(&self) -> Frame {
+        self.lock().emu.frame().clone()
+    }
+
+    /// See `Emulator::save_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.lock().emu.save_state()
+    }
+
+    pub fn set_paused(&self, pause: bool) {
+        self.shared.is_paused.store(pause, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.is_paused.load(Ordering::Relaxed)
+    }
+
+    /// Access the emulator directly, it does not run meanwhile.
+    /// Keep it short, as it blocks the emulator thread.
+    pub fn with_emulator<R>(&self, f: impl FnOnce(&mut Emulator) -> R) -> R {
+        f(&mut self.lock().emu)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Core> {
+        // Core is left consistent even if a panic occurs while it is
+        // locked, as it is only changed by whole frames, so ignore poison.
+        self.shared.core.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for EmulatorHandle {
+    fn drop(&mut self) {
+        // Stop the thread when the last handle is dropped.
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        if let Some(thread) = Arc::into_inner(thread) {
+            self.shared.is_running.store(false, Ordering::Relaxed);
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Run a frame at a time and sleep until it is time for the next one.
+fn run(shared: &Shared) {
+    let frame_time = Duration::from_secs_f64(info::PPU_FRAME_DOTS as f64 / info::FREQUENCY as f64);
+    let mut next = Instant::now();
+
+    while shared.is_running.load(Ordering::Relaxed) {
+        if !shared.is_paused.load(Ordering::Relaxed) {
+            let mut core = shared.core.lock().unwrap_or_else(|e| e.into_inner());
+            let buttons = core.buttons;
+            core.emu.step_frame(buttons);
+        }
+
+        // Do not try to catch up if we fell far behind, like when paused.
+        next += frame_time;
+        let now = Instant::now();
+        if next > now {
+            thread::sleep(next - now);
+        } else {
+            next = now;
+        }
+    }
+}
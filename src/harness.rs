@@ -0,0 +1,78 @@
+//! Running accuracy test ROMs headlessly and telling if they passed.
+//!
+//! Two conventions for reporting results are understood:
+//! - Blargg's tests print their results over the serial port, ending with
+//!   a line saying `Passed` or `Failed`.
+//! - Mooneye tests execute `LD B, B` when done, with the Fibonacci numbers
+//!   3, 5, 8, 13, 21 and 34 in B, C, D, E, H and L if they passed.
+
+use crate::{ButtonState, EmuError, Emulator};
+
+/// Register values B, C, D, E, H and L of a passed mooneye test.
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    /// No result was reported within the frame limit.
+    TimedOut,
+}
+
+/// Result of `run_test_rom`.
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub outcome: TestOutcome,
+    /// Lines printed over the serial port.
+    pub serial: Vec<String>,
+    /// Frames run until the result was reported.
+    pub frames: u64,
+}
+
+/// Run a test ROM for at most `max_frames` frames, stopping at the first
+/// frame in which it reports a result. The HALT bug is emulated, as tests
+/// check for it.
+pub fn run_test_rom(rom: &[u8], max_frames: u64) -> Result<TestReport, EmuError> {
+    let mut emu = Emulator::new(rom)?;
+    emu.set_halt_bug(true);
+    emu.set_serial_console(true);
+
+    let mut report = TestReport {
+        outcome: TestOutcome::TimedOut,
+        serial: Vec::new(),
+        frames: 0,
+    };
+    while report.frames < max_frames {
+        emu.step_frame(ButtonState::default());
+        report.frames += 1;
+
+        let lines = emu.take_console_lines();
+        let outcome = lines.iter().find_map(|line| {
+            if line.contains("Passed") {
+                Some(TestOutcome::Passed)
+            } else if line.contains("Failed") {
+                Some(TestOutcome::Failed)
+            } else {
+                None
+            }
+        });
+        report.serial.extend(lines);
+
+        let outcome = outcome.or_else(|| {
+            emu.take_software_break().then(|| {
+                let r = emu.registers();
+                if [r.b, r.c, r.d, r.e, r.h, r.l] == MOONEYE_PASS {
+                    TestOutcome::Passed
+                } else {
+                    TestOutcome::Failed
+                }
+            })
+        });
+        if let Some(outcome) = outcome {
+            report.outcome = outcome;
+            break;
+        }
+    }
+
+    Ok(report)
+}
@@ -18,10 +18,15 @@ pub(crate) const SPEED_SWITCH_MCYCLES: u16 = 100; //2050;
 pub(crate) const SIZE_ROM_BANK: usize = 16 * KB;
 pub(crate) const SIZE_VRAM_BANK: usize = 8 * KB;
 pub(crate) const SIZE_EXT_RAM: usize = 8 * KB;
+/// Largest external RAM a cartridge header declares, 16 banks.
+#[cfg(feature = "libretro")]
+pub(crate) const SIZE_MAX_EXT_RAM: usize = 16 * SIZE_EXT_RAM;
 pub(crate) const SIZE_WRAM_BANK: usize = 4 * KB;
 pub(crate) const SIZE_OAM: usize = 160;
 // pub(crate) const SIZE_IO_REGS: usize = 128;
 pub(crate) const SIZE_HRAM: usize = 127;
+pub(crate) const SIZE_DMG_BOOT_ROM: usize = 0x100;
+pub(crate) const SIZE_CGB_BOOT_ROM: usize = 0x900;
 
 // Switchable banks count.
 pub(crate) const VRAM_BANKS: usize = 2;
@@ -41,6 +46,11 @@ pub(crate) const ADDR_IO_REGS: URange = 0xFF00..=0xFF7F;
 pub(crate) const ADDR_HRAM: URange = 0xFF80..=0xFFFE;
 pub(crate) const ADDR_IE: URange = 0xFFFF..=0xFFFF;
 
+// Boot ROM is mapped over the cartridge ROM until it is unmapped, the CGB
+// one leaves the cartridge header visible between the two parts.
+pub(crate) const ADDR_BOOT_ROM0: URange = 0x0000..=0x00FF;
+pub(crate) const ADDR_BOOT_ROM1: URange = 0x0200..=0x08FF;
+
 // Only lower 13-bits are connected to the WRAM0 for echo RAM.
 pub(crate) const ECHO_RAM_ADDR_MASK: usize = !(!0 << 13);
 
@@ -50,7 +60,6 @@ pub(crate) const SCREEN_RESOLUTION: (usize, usize) = (160, 144);
 
 // Start address for different VRAM tile data and map areas
 pub(crate) const TILE_BLOCK0: usize = 0x8000;
-pub(crate) const TILE_BLOCK1: usize = 0x8800;
 pub(crate) const TILE_BLOCK2: usize = 0x9000;
 pub(crate) const TILE_MAP0: usize = 0x9800;
 pub(crate) const TILE_MAP1: usize = 0x9C00;
@@ -70,9 +79,14 @@ pub(crate) const MODE_DRAW: u8 = 3;
 
 /// Dots for PPU modes: (Scan + Draw + HBlank)
 pub(crate) const PPU_HSCAN_DOTS: u16 = 456;
+/// Dots for OAM scan, the shortest mode in a scan-line.
+pub(crate) const PPU_SCAN_DOTS: u16 = 80;
 pub(crate) const PPU_LINE_PIXELS: u8 = SCREEN_RESOLUTION.0 as u8;
 pub(crate) const PPU_DRAW_LINES: u8 = SCREEN_RESOLUTION.1 as u8;
 pub(crate) const PPU_VBLANK_LINES: u8 = 10;
+/// Dots for one full frame, including VBlank.
+pub(crate) const PPU_FRAME_DOTS: u32 =
+    PPU_HSCAN_DOTS as u32 * (PPU_DRAW_LINES as u32 + PPU_VBLANK_LINES as u32);
 
 // IO register addresses.
 //---------------------------------------------------------
@@ -128,7 +142,7 @@ pub(crate) const IO_NR52: usize = 0xFF26;
 pub(crate) const IO_PCM12: usize = 0xFF76;
 pub(crate) const IO_PCM34: usize = 0xFF77;
 
-/// Audio wave pattern, 16-bytes.
+// Audio wave pattern, 16-bytes.
 // pub(crate) const IO_WAVE_RAM: URange = 0xFF30..=0xFF3F;
 
 // LCD: control, status, position and scrolling
@@ -160,18 +174,26 @@ pub(crate) const IO_SVBK: usize = 0xFF70;
 pub(crate) const IO_VBK: usize = 0xFF4F;
 
 // VRAM DMA: src(1:hi, 2:lo), dst(3:hi, 4:lo) and 5:length/mode/start.
-// pub(crate) const IO_HDMA1: usize = 0xFF51;
-// pub(crate) const IO_HDMA2: usize = 0xFF52;
-// pub(crate) const IO_HDMA3: usize = 0xFF53;
-// pub(crate) const IO_HDMA4: usize = 0xFF54;
-// pub(crate) const IO_HDMA5: usize = 0xFF55;
+pub(crate) const IO_HDMA1: usize = 0xFF51;
+pub(crate) const IO_HDMA2: usize = 0xFF52;
+pub(crate) const IO_HDMA3: usize = 0xFF53;
+pub(crate) const IO_HDMA4: usize = 0xFF54;
+pub(crate) const IO_HDMA5: usize = 0xFF55;
 
 /// OAM DMA control
 pub(crate) const IO_DMA: usize = 0xFF46;
 
+/// CGB mode select, the CGB boot ROM writes the cartridge CGB flag to it,
+/// or `KEY0_DMG_MODE` for monochrome games.
+pub(crate) const IO_KEY0: usize = 0xFF4C;
+pub(crate) const KEY0_DMG_MODE: u8 = 1 << 2;
+
 /// Speed switch for CGB dual-speed mode.
 pub(crate) const IO_KEY1: usize = 0xFF4D;
 
+/// Unmaps the boot ROM when written with a non-zero value.
+pub(crate) const IO_BOOT: usize = 0xFF50;
+
 // IR communications port
 pub(crate) const IO_RP: usize = 0xFF56;
 
@@ -180,15 +202,14 @@ pub(crate) const IO_RP: usize = 0xFF56;
 //---------------------------------------------------------
 pub(crate) const CART_HEADER: URange = 0x100..=0x14F;
 
-pub(crate) const CART_ENTRY: URange = 0x100..=0x103;
 pub(crate) const CART_LOGO: URange = 0x104..=0x133;
 pub(crate) const CART_TITLE: URange = 0x134..=0x143;
 pub(crate) const CART_CGB_FLAG: usize = 0x143;
 pub(crate) const CART_SGB_FLAG: usize = 0x146;
 pub(crate) const CART_TYPE: usize = 0x147;
+pub(crate) const CART_ROM_SIZE: usize = 0x148;
 pub(crate) const CART_RAM_SIZE: usize = 0x149;
 pub(crate) const CART_HEADER_CSUM: usize = 0x14D;
-pub(crate) const CART_GLOBAL_CSUM: URange = 0x14E..=0x14F;
 
 /// In real gameboys the value of logo in header should be equal to
 /// this value, otherwise, the game will not run.  
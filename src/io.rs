@@ -0,0 +1,131 @@
+//! IO register addresses and layouts, for debuggers and scripts which
+//! refer to registers by name. Read them using `Emulator::read_memory`.
+
+use crate::{info, regs};
+
+pub const P1: u16 = info::IO_JOYPAD as u16;
+pub const SB: u16 = info::IO_SB as u16;
+pub const SC: u16 = info::IO_SC as u16;
+pub const DIV: u16 = info::IO_DIV as u16;
+pub const TIMA: u16 = info::IO_TIMA as u16;
+pub const TMA: u16 = info::IO_TMA as u16;
+pub const TAC: u16 = info::IO_TAC as u16;
+pub const IF: u16 = info::IO_IF as u16;
+pub const NR52: u16 = info::IO_NR52 as u16;
+pub const LCDC: u16 = info::IO_LCDC as u16;
+pub const STAT: u16 = info::IO_STAT as u16;
+pub const SCY: u16 = info::IO_SCY as u16;
+pub const SCX: u16 = info::IO_SCX as u16;
+pub const LY: u16 = info::IO_LY as u16;
+pub const LYC: u16 = info::IO_LYC as u16;
+pub const DMA: u16 = info::IO_DMA as u16;
+pub const BGP: u16 = info::IO_BGP as u16;
+pub const OBP0: u16 = info::IO_OBP0 as u16;
+pub const OBP1: u16 = info::IO_OBP1 as u16;
+pub const WY: u16 = info::IO_WY as u16;
+pub const WX: u16 = info::IO_WX as u16;
+pub const KEY1: u16 = info::IO_KEY1 as u16;
+pub const VBK: u16 = info::IO_VBK as u16;
+pub const HDMA1: u16 = info::IO_HDMA1 as u16;
+pub const HDMA2: u16 = info::IO_HDMA2 as u16;
+pub const HDMA3: u16 = info::IO_HDMA3 as u16;
+pub const HDMA4: u16 = info::IO_HDMA4 as u16;
+pub const HDMA5: u16 = info::IO_HDMA5 as u16;
+pub const RP: u16 = info::IO_RP as u16;
+pub const BGPI: u16 = info::IO_BGPI as u16;
+pub const BGPD: u16 = info::IO_BGPD as u16;
+pub const OBPI: u16 = info::IO_OBPI as u16;
+pub const OBPD: u16 = info::IO_OBPD as u16;
+pub const OPRI: u16 = info::IO_OPRI as u16;
+pub const SVBK: u16 = info::IO_SVBK as u16;
+pub const PCM12: u16 = info::IO_PCM12 as u16;
+pub const PCM34: u16 = info::IO_PCM34 as u16;
+pub const IE: u16 = info::IO_IE as u16;
+
+/// An IO register and its layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Register {
+    pub name: &'static str,
+    pub addr: u16,
+    /// Bit-fields as names and widths from LSB, names starting with `_`
+    /// are unused bits. Empty if the whole register is a single value.
+    pub fields: &'static [(&'static str, u32)],
+}
+
+impl Register {
+    /// Find a register by its name, like `"LCDC"`, ignoring case.
+    pub fn by_name(name: &str) -> Option<&'static Register> {
+        REGISTERS.iter().find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn by_addr(addr: u16) -> Option<&'static Register> {
+        REGISTERS.iter().find(|r| r.addr == addr)
+    }
+
+    /// Get the value of the field `name` from a value of the register.
+    pub fn field(&self, val: u8, name: &str) -> Option<u8> {
+        let mut shift = 0;
+        for &(field, width) in self.fields {
+            if field == name {
+                return Some((val >> shift) & (u8::MAX >> (8 - width)));
+            }
+            shift += width;
+        }
+        None
+    }
+}
+
+macro_rules! reg {
+    ($name:ident) => {
+        reg!($name, &[])
+    };
+    ($name:ident, $fields:expr) => {
+        Register {
+            name: stringify!($name),
+            addr: $name,
+            fields: $fields,
+        }
+    };
+}
+
+/// All IO registers in the order of their addresses.
+pub const REGISTERS: &[Register] = &[
+    reg!(P1, regs::JoyPad::FIELDS),
+    reg!(SB),
+    reg!(SC, regs::SerialCtrl::FIELDS),
+    reg!(DIV),
+    reg!(TIMA),
+    reg!(TMA),
+    reg!(TAC, regs::TimerCtrl::FIELDS),
+    reg!(IF, regs::IntData::FIELDS),
+    reg!(NR52, regs::AudioCtrl::FIELDS),
+    reg!(LCDC, regs::LcdCtrl::FIELDS),
+    reg!(STAT, regs::LcdStat::FIELDS),
+    reg!(SCY),
+    reg!(SCX),
+    reg!(LY),
+    reg!(LYC),
+    reg!(DMA),
+    reg!(BGP),
+    reg!(OBP0),
+    reg!(OBP1),
+    reg!(WY),
+    reg!(WX),
+    reg!(KEY1, regs::Key1::FIELDS),
+    reg!(VBK),
+    reg!(HDMA1),
+    reg!(HDMA2),
+    reg!(HDMA3),
+    reg!(HDMA4),
+    reg!(HDMA5),
+    reg!(RP, regs::Rp::FIELDS),
+    reg!(BGPI, regs::CgbPaletteIndex::FIELDS),
+    reg!(BGPD),
+    reg!(OBPI, regs::CgbPaletteIndex::FIELDS),
+    reg!(OBPD),
+    reg!(OPRI),
+    reg!(SVBK),
+    reg!(PCM12),
+    reg!(PCM34),
+    reg!(IE, regs::IntData::FIELDS),
+];
@@ -0,0 +1,44 @@
+//! Light seen by the CGB infrared port, read through the RP register.
+//! There is nothing on the other side of it, so the light comes from a
+//! source chosen by the frontend. Sources depend only on the time since
+//! power on, so they give the same reads when a movie is replayed.
+
+use crate::hash::Fnv64;
+
+/// Noise changes at most once in these many M-cycles, about 61µs.
+const NOISE_PERIOD: u64 = 64;
+
+/// Where the light seen by the infrared port comes from, see
+/// `Emulator::set_ir_source`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum IrSource {
+    /// No light at all.
+    #[default]
+    Dark,
+    /// Random flickers like ambient light, the same for a seed.
+    Noise(u64),
+    /// Light turns on or off at the given M-cycle counts since power on,
+    /// as (M-cycle, is lit) in increasing order. It is dark before the
+    /// first one.
+    Scripted(Vec<(u64, bool)>),
+}
+
+impl IrSource {
+    /// Check if light is seen `mcycle` M-cycles after power on.
+    pub(crate) fn is_lit(&self, mcycle: u64) -> bool {
+        match self {
+            Self::Dark => false,
+            Self::Noise(seed) => {
+                let mut h = Fnv64::new();
+                h.write(&seed.to_le_bytes());
+                h.write(&(mcycle / NOISE_PERIOD).to_le_bytes());
+                // Low bits of FNV-1a are poorly mixed.
+                h.finish() >> 63 == 1
+            }
+            Self::Scripted(changes) => {
+                let idx = changes.partition_point(|&(at, _)| at <= mcycle);
+                idx > 0 && changes[idx - 1].1
+            }
+        }
+    }
+}
@@ -1,25 +1,90 @@
 mod cartridge;
+mod cheats;
 mod cpu;
+mod hash;
 mod info;
+mod ir;
 mod log;
 mod macros;
 mod mem;
 mod ppu;
 mod regs;
+mod rewind;
+mod scale;
 mod serial;
+mod sgb;
+mod state;
+mod testrom;
 mod timer;
 
 // Modules which have public interfaces, export them here.
 mod emulator;
+mod env;
 mod frame;
+mod harness;
+// Threads cannot be spawned in browsers.
+#[cfg(not(target_arch = "wasm32"))]
+mod handle;
+pub mod io;
 mod msg;
+mod slots;
 
+#[cfg(feature = "gdb")]
+mod gdb;
+#[cfg(feature = "libretro")]
+mod libretro;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "romdb")]
+mod romdb;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use cartridge::CartridgeInfo;
+pub use cheats::Cheat;
 pub use emulator::Emulator;
-pub use frame::{Color, Frame, SCREEN_SIZE};
-pub use msg::{ButtonState, EmulatorMsg, UserMsg};
+pub use env::{DoneHook, Env, Observation, RamView};
+pub use frame::{Color, Frame, FrameInfo, ScaledFrame, INDEX_OBJ, SCREEN_SIZE};
+#[cfg(feature = "gdb")]
+pub use gdb::serve_gdb;
+#[cfg(not(target_arch = "wasm32"))]
+pub use handle::EmulatorHandle;
+pub use harness::{run_test_rom, TestOutcome, TestReport};
+pub use ir::IrSource;
+pub use msg::{
+    Button, ButtonState, EmulatorMsg, FrameTiming, Interrupt, InterruptEvent, LcdcOverride,
+    LcdcState, LineObjects, OppositeDirections, Registers, RuntimeError, UserMsg, VideoMemory,
+    Watch,
+};
+pub use rewind::RewindBuffer;
+#[cfg(feature = "romdb")]
+pub use romdb::{DatEntry, RomDb};
+pub use scale::ScaleFilter;
+pub use slots::{SaveSlot, SaveSlots};
 
 /// Emulator error type.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EmuError {
+    /// ROM file ends before the cartridge header does, at 0x14F.
+    FileTooSmall,
+    /// ROM file has no cartridge header, the header area holds the same
+    /// byte all over, like an erased or zeroed file.
+    MissingHeader,
+    /// Boot ROM is neither of the DMG(256 bytes) or the CGB(2304 bytes)
+    /// size.
+    InvalidBootRom,
     UnknownMBC,
+    /// MBC is known but not emulated yet, its cartridge type is given.
+    UnsupportedMBC(u8),
+    /// Not a save-state file or it is corrupted.
+    InvalidSaveState,
+    /// Save-state file is from an incompatible version, which is given.
+    SaveStateVersion(u32),
+    /// State belongs to a different ROM than the other save-slots.
+    RomMismatch,
+    /// No save-slot with the given name.
+    UnknownSlot,
+    /// Battery save does not fit the cartridge, the size it must be is
+    /// given.
+    SaveDataSize(usize),
 }
@@ -0,0 +1,402 @@
+//! A libretro core, for running the emulator inside RetroArch and other
+//! libretro frontends. Build the library as a shared library with the
+//! `libretro` feature and load it as a core:
+//! `cargo rustc --lib --release --features libretro --crate-type cdylib`.
+//!
+//! Frames are sent as XRGB8888. Sound is not emulated yet, so silence is
+//! sent for frontends which sync to audio. Cartridge RAM is exposed as
+//! save RAM through a copy which is synced every frame.
+
+use std::{
+    ffi::{c_char, c_uint, c_void, CStr},
+    ptr,
+    sync::Mutex,
+};
+
+use crate::{info, ButtonState, Emulator, SCREEN_SIZE};
+
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+const RETRO_REGION_NTSC: c_uint = 0;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+// Joypad button IDs.
+const JOYPAD_B: c_uint = 0;
+const JOYPAD_SELECT: c_uint = 2;
+const JOYPAD_START: c_uint = 3;
+const JOYPAD_UP: c_uint = 4;
+const JOYPAD_DOWN: c_uint = 5;
+const JOYPAD_LEFT: c_uint = 6;
+const JOYPAD_RIGHT: c_uint = 7;
+const JOYPAD_A: c_uint = 8;
+
+const SAMPLE_RATE: f64 = 32768.0;
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    emu: Emulator,
+    /// Copy of the cartridge RAM which the frontend reads and writes.
+    save_ram: Vec<u8>,
+    /// The frontend loads save RAM after the game, it is given to the
+    /// emulator before the first frame.
+    save_ram_loaded: bool,
+    /// Size given for states, RetroArch keeps it for rewind and run-ahead
+    /// so it must not change while the game runs.
+    state_size: usize,
+    pixels: Vec<u32>,
+    silence: Vec<i16>,
+    /// Fraction of an audio frame left over from the last video frame.
+    sample_carry: f64,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn fps() -> f64 {
+    info::FREQUENCY as f64 / (info::PPU_FRAME_DOTS as f64)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_batch = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c"gbemu".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"gb|gbc".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let (w, h) = SCREEN_SIZE;
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: w as c_uint,
+            base_height: h as c_uint,
+            max_width: w as c_uint,
+            max_height: h as c_uint,
+            aspect_ratio: w as f32 / h as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: fps(),
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        core.emu.reset();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let cbs = CALLBACKS.lock().unwrap();
+    let mut guard = CORE.lock().unwrap();
+    let Some(core) = guard.as_mut() else {
+        return;
+    };
+
+    if !core.save_ram_loaded {
+        // The buffer is as large as `save_data` made it, so it always fits.
+        let _ = core.emu.load_save_data(&core.save_ram);
+        core.save_ram_loaded = true;
+    }
+
+    let buttons = match (cbs.input_poll, cbs.input_state) {
+        (Some(poll), Some(state)) => {
+            // SAFETY: Callbacks are set by the frontend before running.
+            unsafe { poll() };
+            let pressed = |id| unsafe { state(0, RETRO_DEVICE_JOYPAD, 0, id) != 0 };
+            ButtonState {
+                a: pressed(JOYPAD_A),
+                b: pressed(JOYPAD_B),
+                select: pressed(JOYPAD_SELECT),
+                start: pressed(JOYPAD_START),
+                up: pressed(JOYPAD_UP),
+                down: pressed(JOYPAD_DOWN),
+                left: pressed(JOYPAD_LEFT),
+                right: pressed(JOYPAD_RIGHT),
+            }
+        }
+        _ => ButtonState::default(),
+    };
+
+    let frame = core.emu.step_frame(buttons);
+    let (w, h) = SCREEN_SIZE;
+    for y in 0..h {
+        for x in 0..w {
+            let c = frame.get(x, y);
+            core.pixels[y * w + x] = (c.r as u32) << 16 | (c.g as u32) << 8 | c.b as u32;
+        }
+    }
+
+    let save_data = core.emu.save_data();
+    core.save_ram.copy_from_slice(&save_data);
+
+    if let Some(video) = cbs.video_refresh {
+        // SAFETY: The frame is copied by the frontend before returning.
+        unsafe { video(core.pixels.as_ptr().cast(), w as c_uint, h as c_uint, w * 4) };
+    }
+
+    // Send silence as sound is not emulated, some frontends sync to audio.
+    let samples = SAMPLE_RATE / fps() + core.sample_carry;
+    let frames = samples as usize;
+    core.sample_carry = samples - frames as f64;
+    if let Some(audio) = cbs.audio_batch {
+        core.silence.resize(frames * 2, 0);
+        // SAFETY: The samples are copied by the frontend before returning.
+        unsafe { audio(core.silence.as_ptr(), frames) };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    match CORE.lock().unwrap().as_ref() {
+        Some(core) => core.state_size,
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let guard = CORE.lock().unwrap();
+    let Some(core) = guard.as_ref() else {
+        return false;
+    };
+
+    // The state is prefixed with its length, the buffer may be larger.
+    let state = core.emu.machine_state();
+    if 4 + state.len() > size {
+        return false;
+    }
+    let out = std::slice::from_raw_parts_mut(data.cast::<u8>(), size);
+    out[..4].copy_from_slice(&(state.len() as u32).to_le_bytes());
+    out[4..4 + state.len()].copy_from_slice(&state);
+    true
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut guard = CORE.lock().unwrap();
+    let Some(core) = guard.as_mut() else {
+        return false;
+    };
+
+    let data = std::slice::from_raw_parts(data.cast::<u8>(), size);
+    let Some((len, state)) = data.split_first_chunk::<4>() else {
+        return false;
+    };
+    let len = u32::from_le_bytes(*len) as usize;
+    match state.get(..len) {
+        Some(state) => core.emu.restore_machine_state(state).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must point to a valid `retro_game_info` with the ROM in memory.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data.cast::<u8>(), (*game).size);
+    let emu = match Emulator::new(rom) {
+        Ok(emu) => emu,
+        Err(e) => {
+            let path = (*game).path;
+            let name = if path.is_null() {
+                "game".into()
+            } else {
+                CStr::from_ptr(path).to_string_lossy()
+            };
+            eprintln!("gbemu: cannot load {}: {:?}", name, e);
+            return false;
+        }
+    };
+
+    if let Some(env) = CALLBACKS.lock().unwrap().environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !env(
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+            ptr::from_mut(&mut format).cast(),
+        ) {
+            eprintln!("gbemu: frontend does not support XRGB8888");
+            return false;
+        }
+    }
+
+    let (w, h) = SCREEN_SIZE;
+    *CORE.lock().unwrap() = Some(Core {
+        save_ram: emu.save_data(),
+        save_ram_loaded: false,
+        state_size: 4 + emu.max_machine_state_len(),
+        emu,
+        pixels: vec![0; w * h],
+        silence: Vec::new(),
+        sample_carry: 0.0,
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _type: c_uint,
+    _info: *const RetroGameInfo,
+    _num: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match CORE.lock().unwrap().as_mut() {
+        Some(core) if id == RETRO_MEMORY_SAVE_RAM && !core.save_ram.is_empty() => {
+            core.save_ram.as_mut_ptr().cast()
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match CORE.lock().unwrap().as_ref() {
+        Some(core) if id == RETRO_MEMORY_SAVE_RAM => core.save_ram.len(),
+        _ => 0,
+    }
+}
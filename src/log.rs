@@ -1,5 +1,10 @@
 #![allow(unused)]
 
+/// For instruction traces, which are printed as is.
+pub(crate) fn trace(m: &str) {
+    eprintln!("[TRACE] {m}")
+}
+
 pub(crate) fn debug(m: &str) {
     eprintln!("[DEBUG] {m}.")
 }
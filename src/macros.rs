@@ -2,7 +2,9 @@
 /// given underlying type(which should be an unsigned integer).  
 /// `new(ux) -> Self`, `read(&self) -> ux` and `write(&mut self, ux)` methods are
 /// generated which can be used to create/read/write the struct.  
-/// The generated struct implements: `Default`, `Copy` and `Clone` traits.
+/// A `FIELDS` constant lists the field names and widths, from LSB.  
+/// The generated struct implements: `Default`, `Copy`, `Clone` and
+/// serde's `Serialize` and `Deserialize` traits.
 ///
 /// A fields is specified as `name: width`, where sum of width of all fields
 /// should not exceed the number of bits in the underlying type.  
@@ -18,8 +20,8 @@
 /// **Note**: The struct generated is not space efficient.
 ///
 /// Example:
-/// ```no_run
-/// bit_mapped!{
+/// ```ignore
+/// bit_fields!{
 ///     #[derive(Default)]
 ///     struct SomeFields<u8> {
 ///         field1_b3: 3,
@@ -36,12 +38,16 @@ macro_rules! bit_fields {
         }
     ) => {
         $(#[$meta])*
-        #[derive(Default, Copy, Clone)]
+        #[derive(Default, Copy, Clone, serde::Serialize, serde::Deserialize)]
         $vis struct $name {
             $($(#[$metas])* $vis $fields: $utype),+
         }
 
         impl $name {
+            #[allow(unused)]
+            $vis const FIELDS: &'static [(&'static str, u32)] =
+                &[$((stringify!($fields), $widths)),+];
+
             #[allow(unused)]
             $vis fn new(v: $utype) -> Self {
                 let mut r = Self::default();
@@ -99,8 +105,8 @@ macro_rules! prefix_field {
 /// At the end a catch all arm must be present, like so: `_ => { ... }`.
 ///
 /// Example:
-/// ```no_run
-/// match_ranges! { bind_name@match_var {
+/// ```ignore
+/// match_range! { bind_name@match_var {
 ///     RANGE1 => { println!("First, offset={bind_name}") }
 ///     RANGE2 => { println!("Second, ...") }
 ///     // ...
@@ -1,55 +1,1307 @@
-use std::{env::args, process::exit, sync::mpsc, thread};
+use std::{
+    collections::BTreeMap,
+    env::args,
+    fs,
+    io::{self, BufRead, BufWriter, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
-use gbemu::{ButtonState, Emulator, EmulatorMsg, UserMsg, SCREEN_SIZE};
+use gbemu::{
+    run_test_rom, Button, ButtonState, CartridgeInfo, Cheat, Emulator, EmulatorMsg, FrameTiming,
+    IrSource, OppositeDirections, SaveSlots, TestOutcome, UserMsg, VideoMemory, SCREEN_SIZE,
+};
 use macroquad::prelude::*;
 use miniquad::window::set_window_size;
 
 const BLOCK_SZ: u32 = 5;
 const WX: u32 = SCREEN_SIZE.0 as u32 * BLOCK_SZ;
 const WY: u32 = SCREEN_SIZE.1 as u32 * BLOCK_SZ;
+/// Width of the debug viewer panel on the right of the frame.
+const VIEWER_W: f32 = 280.0;
+/// Grey levels for color indices in monochrome palettes, white first.
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
 
-#[macroquad::main("[C]GB-Emulator")]
-async fn main() {
-    let path = match args().count() {
-        2 => args().nth(1).unwrap(),
+/// Set once the window is open, panics before it are window failures.
+static WINDOW_OPENED: AtomicBool = AtomicBool::new(false);
 
-        _ => {
-            eprintln!(
-                "Usage: {} <rom-file>",
-                args().next().unwrap_or("gbemu".to_string())
+/// Save-slot used by the quick save and load keys.
+const QUICK_SLOT: &str = "quick";
+/// Keys for the numbered save-slots, named after the number.
+const SLOT_KEYS: [(KeyCode, &str); 4] = [
+    (KeyCode::Key1, "1"),
+    (KeyCode::Key2, "2"),
+    (KeyCode::Key3, "3"),
+    (KeyCode::Key4, "4"),
+];
+
+/// How long a problem reported by the emulator is shown for.
+const NOTICE_TIME: Duration = Duration::from_secs(5);
+
+/// How often the watched ROM file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How the frame is fitted in the window.
+#[derive(Default, Clone, Copy)]
+enum AspectMode {
+    /// Largest whole-number scale that fits, centered with borders.
+    #[default]
+    Integer,
+    /// Largest scale that fits while keeping the aspect ratio.
+    Fit,
+    /// Fill the whole window.
+    Stretch,
+}
+
+impl AspectMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "integer" => Some(Self::Integer),
+            "fit" => Some(Self::Fit),
+            "stretch" => Some(Self::Stretch),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Integer => "integer",
+            Self::Fit => "fit",
+            Self::Stretch => "stretch",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Self::Integer => Self::Fit,
+            Self::Fit => Self::Stretch,
+            Self::Stretch => Self::Integer,
+        }
+    }
+
+    /// Get the area to draw the frame in, as a rect in the window of
+    /// size `w`x`h`.
+    fn frame_rect(self, w: f32, h: f32) -> Rect {
+        let (sw, sh) = (SCREEN_SIZE.0 as f32, SCREEN_SIZE.1 as f32);
+        let (sx, sy) = match self {
+            Self::Integer => {
+                let s = (w / sw).min(h / sh).floor().max(1.0);
+                (s, s)
+            }
+            Self::Fit => {
+                let s = (w / sw).min(h / sh);
+                (s, s)
+            }
+            Self::Stretch => (w / sw, h / sh),
+        };
+
+        let (fw, fh) = (sw * sx, sh * sy);
+        Rect::new(((w - fw) / 2.0).floor(), ((h - fh) / 2.0).floor(), fw, fh)
+    }
+}
+
+/// Command line options.
+#[derive(Default)]
+struct Args {
+    path: String,
+    script: bool,
+    uncap_sprites: bool,
+    overclock: bool,
+    halt_bug: bool,
+    dmg_colors: bool,
+    /// Addresses to freeze and their values.
+    freezes: Vec<(u16, u8)>,
+    /// Cheat codes, checked to be valid.
+    cheats: Vec<String>,
+    /// Do not use the saved cheats of the game.
+    clear_cheats: bool,
+    touch: bool,
+    /// Reset when A, B, Start and Select are held together.
+    reset_combo: bool,
+    break_on_bad_execution: bool,
+    serial_console: bool,
+    opposite_dpad: OppositeDirections,
+    ir_source: IrSource,
+    aspect: Option<AspectMode>,
+    background: Option<Color>,
+    /// Rewind history size in MiB.
+    rewind_mib: usize,
+    /// Save the state on exit, always done if resumed from a state.
+    save_state: bool,
+    /// Seconds of rewind history to keep in the state saved on exit.
+    save_rewind_secs: usize,
+    timing_csv: Option<String>,
+    /// Run the built-in test cartridge instead of a file.
+    test_pattern: bool,
+    /// Run the built-in demo cartridge instead of a file.
+    demo: bool,
+    /// Start with the debug viewer panel open.
+    viewer: bool,
+    /// Run the emulator on the GUI thread, see `Driver`.
+    direct: bool,
+    /// Reload the ROM when the file changes.
+    watch: bool,
+    watch_keep_state: bool,
+    /// Address to serve a GDB remote debugger on, instead of running.
+    gdb: Option<String>,
+    /// DAT file for identifying the ROM.
+    rom_db: Option<String>,
+    boot_rom: Option<String>,
+    // Options for running headless.
+    frames: Option<u64>,
+    until_pc: Option<u16>,
+    exit_screenshot: Option<String>,
+    exit_state: Option<String>,
+}
+
+impl Args {
+    fn is_headless(&self) -> bool {
+        self.frames.is_some() || self.until_pc.is_some()
+    }
+
+    /// Check if a built-in cartridge is run instead of a file.
+    fn is_builtin(&self) -> bool {
+        self.test_pattern || self.demo
+    }
+}
+
+fn main() {
+    let argv: Vec<String> = args().skip(1).collect();
+    match argv.first().map(|s| s.as_str()) {
+        Some("export-sram") => return export_sram(&argv[1..]),
+        Some("import-sram") => return import_sram(&argv[1..]),
+        Some("extract-rom") => return extract_rom(&argv[1..]),
+        Some("slots") => return manage_slots(&argv[1..]),
+        Some("compat") => return compat_report(&argv[1..]),
+        Some("test") => return test_rom(&argv[1..]),
+        _ => (),
+    }
+
+    let mut args = parse_args();
+
+    // Open ROM or save-state file and load it.
+    let data = if args.test_pattern {
+        Emulator::test_pattern_rom()
+    } else if args.demo {
+        demo_rom()
+    } else {
+        read_source(&args.path)
+    };
+    let is_state = Emulator::is_save_state(&data);
+    if args.watch && (is_state || args.is_builtin() || is_remote(&args.path)) {
+        eprintln!("only ROM files can be watched");
+        exit(1);
+    }
+    if let Some(dat) = args.rom_db.as_ref().filter(|_| !is_state) {
+        identify_rom(&data, dat);
+    }
+    // A save-state is past the boot ROM.
+    let boot_rom = args.boot_rom.as_ref().filter(|_| !is_state);
+    let mut emu = load_emulator(&data, boot_rom.map(|p| read_file(p)).as_deref());
+
+    emu.set_uncapped_sprites(args.uncap_sprites);
+    emu.set_cpu_overclock(args.overclock);
+    emu.set_halt_bug(args.halt_bug);
+    emu.set_dmg_colors(args.dmg_colors);
+    emu.set_break_on_bad_execution(args.break_on_bad_execution);
+    emu.set_serial_console(args.serial_console);
+    emu.set_opposite_directions(args.opposite_dpad);
+    emu.set_ir_source(args.ir_source.clone());
+    for &(addr, val) in &args.freezes {
+        emu.set_memory_freeze(addr, Some(val));
+    }
+    for cheat in args.cheats.iter().filter_map(|c| Cheat::parse(c)) {
+        emu.add_cheat(cheat);
+    }
+
+    // State is saved on exit if asked for, or overwritten if resumed from
+    // one. There is no file to save next to if read from stdin or a URL.
+    args.save_state |= is_state;
+    let state_path = if args.is_builtin() || is_remote(&args.path) {
+        None
+    } else if is_state {
+        Some(PathBuf::from(&args.path))
+    } else {
+        Some(PathBuf::from(format!("{}.state", args.path)))
+    };
+
+    // Starting from a ROM, cartridge RAM comes from the battery save, like
+    // on a real cartridge. A save-state has its own copy of it.
+    if let Some(path) = battery_save_path(&emu, &state_path).filter(|p| p.exists()) {
+        if !is_state {
+            load_save_data(&mut emu, &path.to_string_lossy());
+        }
+    }
+
+    if let Some(addr) = &args.gdb {
+        run_gdb(emu, addr);
+    } else if args.script {
+        run_script(emu);
+    } else if args.is_headless() {
+        run_headless(emu, &args);
+    } else {
+        start_gui(emu, state_path, args);
+    }
+}
+
+/// Write battery save data from a save-state file to a file.
+fn export_sram(argv: &[String]) {
+    let [state_path, out_path] = argv else {
+        usage();
+    };
+
+    write_save_data(&load_emulator(&read_file(state_path), None), out_path);
+}
+
+/// Replace battery save data inside a save-state file.
+fn import_sram(argv: &[String]) {
+    let [state_path, in_path] = argv else {
+        usage();
+    };
+
+    let mut emu = load_emulator(&read_file(state_path), None);
+    load_save_data(&mut emu, in_path);
+    write_file(state_path, &emu.save_state());
+}
+
+/// Load battery save data from a file, exits if it does not fit the
+/// cartridge, so that it is not overwritten later.
+fn load_save_data(emu: &mut Emulator, path: &str) {
+    if let Err(e) = emu.load_save_data(&read_file(path)) {
+        eprintln!("cannot load the battery save '{}': {:?}", path, e);
+        exit(1);
+    }
+}
+
+/// Write the ROM from a save-state file to a file, and optionally the
+/// battery save data too.
+fn extract_rom(argv: &[String]) {
+    let (state_path, out_path, sram_path) = match argv {
+        [s, o] => (s, o, None),
+        [s, o, flag, srm] if flag == "--sram" => (s, o, Some(srm)),
+        _ => usage(),
+    };
+
+    let emu = load_emulator(&read_file(state_path), None);
+    write_file(out_path, emu.rom());
+
+    if let Some(path) = sram_path {
+        write_save_data(&emu, path);
+    }
+}
+
+fn write_save_data(emu: &Emulator, path: &str) {
+    let data = emu.save_data();
+    if data.is_empty() {
+        eprintln!("cartridge has no RAM, nothing to export");
+        exit(1);
+    }
+    write_file(path, &data);
+}
+
+/// Result of running a ROM for the compatibility report.
+struct CompatResult {
+    name: String,
+    /// Why it did not boot, if so.
+    failure: Option<String>,
+    /// Hash of the last frame, if it booted.
+    hash: Option<u64>,
+    /// The last frame is the same in a second run.
+    deterministic: bool,
+    /// The last frame is a single color.
+    blank: bool,
+    /// Components which differ after a state save and restore, see
+    /// `Emulator::check_state_round_trip`.
+    state_diverged: Vec<&'static str>,
+    errors: Vec<String>,
+}
+
+/// Run a test ROM headlessly and exit with 0 if it passed, 1 if it failed
+/// and 2 if it did not report a result in time.
+fn test_rom(argv: &[String]) {
+    let (path, opts) = argv.split_first().unwrap_or_else(|| usage());
+    let mut frames = 7200;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match opt.as_str() {
+            "--frames" => {
+                frames = opts
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            _ => usage(),
+        }
+    }
+
+    let report = run_test_rom(&read_source(path), frames).unwrap_or_else(|e| {
+        eprintln!("cannot load the ROM: {:?}", e);
+        exit(1);
+    });
+    for line in &report.serial {
+        println!("{}", line);
+    }
+
+    let (text, code) = match report.outcome {
+        TestOutcome::Passed => ("passed", 0),
+        TestOutcome::Failed => ("failed", 1),
+        TestOutcome::TimedOut => ("timed out", 2),
+    };
+    eprintln!("{}: {} after {} frames", path, text, report.frames);
+    exit(code);
+}
+
+/// Run each ROM in a directory for some frames and write a report of how
+/// each one did to stdout, as a Markdown table or CSV.
+fn compat_report(argv: &[String]) {
+    let (dir, opts) = argv.split_first().unwrap_or_else(|| usage());
+    let mut frames = 600;
+    let mut csv = false;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match opt.as_str() {
+            "--frames" => {
+                frames = opts
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--csv" => csv = true,
+            _ => usage(),
+        }
+    }
+
+    let entries = fs::read_dir(dir).unwrap_or_else(|e| {
+        eprintln!("cannot open directory '{}': {:?}", dir, e);
+        exit(1);
+    });
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| e.eq_ignore_ascii_case("gb") || e.eq_ignore_ascii_case("gbc"))
+        })
+        .collect();
+    paths.sort();
+
+    let results = paths.iter().map(|path| {
+        eprintln!("running '{}'", path.display());
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        compat_run(
+            name.into_owned(),
+            &read_file(&path.to_string_lossy()),
+            frames,
+        )
+    });
+
+    if csv {
+        println!("rom,boots,frame_hash,deterministic,blank,state_round_trip,errors");
+    } else {
+        println!(
+            "| ROM | Boots | Frame hash | Deterministic | Blank | State round trip | Errors |"
+        );
+        println!(
+            "|-----|-------|------------|---------------|-------|------------------|--------|"
+        );
+    }
+
+    for r in results {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let boots = match &r.failure {
+            None => "yes".to_string(),
+            Some(why) => format!("no: {}", why),
+        };
+        let round_trip = if r.state_diverged.is_empty() {
+            "ok".to_string()
+        } else {
+            format!("differs: {}", r.state_diverged.join(", "))
+        };
+        let errors = r.errors.join("; ");
+        let hash = r.hash.map(|h| format!("{:016x}", h)).unwrap_or_default();
+
+        if csv {
+            let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+            println!(
+                "{},{},{},{},{},{},{}",
+                quote(&r.name),
+                quote(&boots),
+                hash,
+                yes_no(r.deterministic),
+                yes_no(r.blank),
+                quote(&round_trip),
+                quote(&errors)
+            );
+        } else {
+            let cell = |s: &str| s.replace('|', "\\|");
+            println!(
+                "| {} | {} | {} | {} | {} | {} | {} |",
+                cell(&r.name),
+                cell(&boots),
+                hash,
+                yes_no(r.deterministic),
+                yes_no(r.blank),
+                round_trip,
+                cell(&errors)
             );
+        }
+    }
+}
 
-            exit(1);
+/// Run a ROM twice for `frames` frames with no buttons held, see
+/// `CompatResult`.
+fn compat_run(name: String, rom: &[u8], frames: u64) -> CompatResult {
+    let mut result = CompatResult {
+        name,
+        failure: None,
+        hash: None,
+        deterministic: false,
+        blank: false,
+        state_diverged: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let run = || {
+        let mut emu = Emulator::new(rom)?;
+        for _ in 0..frames {
+            emu.step_frame(ButtonState::default());
+        }
+        Ok::<_, gbemu::EmuError>(emu)
+    };
+
+    // Emulator bugs can panic, report them instead of stopping.
+    let mut emu = match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(Ok(emu)) => emu,
+        Ok(Err(e)) => {
+            result.failure = Some(format!("cannot load: {:?}", e));
+            return result;
+        }
+        Err(_) => {
+            result.failure = Some("emulator panicked".to_string());
+            return result;
         }
     };
 
-    // Open ROM file and load it.
-    let mut emu = match std::fs::read(&path) {
-        Ok(rom) => match Emulator::new(&rom) {
-            Ok(emu) => emu,
+    let frame = emu.frame();
+    let first = frame.get(0, 0);
+    result.hash = Some(frame.hash());
+    result.blank = (0..SCREEN_SIZE.1).all(|y| (0..SCREEN_SIZE.0).all(|x| frame.get(x, y) == first));
+    result.errors = emu.take_errors().iter().map(|e| e.to_string()).collect();
+
+    // A second's worth of frames is enough for diverged state to show.
+    result.state_diverged =
+        panic::catch_unwind(AssertUnwindSafe(|| emu.check_state_round_trip(60)))
+            .unwrap_or_else(|_| vec!["panicked"]);
+
+    result.deterministic = match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(Ok(again)) => Some(again.frame().hash()) == result.hash,
+        _ => false,
+    };
+    result
+}
+
+/// Manage save-slots in a save-slot file.
+fn manage_slots(argv: &[String]) {
+    let Some((cmd, path)) = argv.first().zip(argv.get(1)) else {
+        usage();
+    };
+    // The file is created when saving to it the first time.
+    let mut slots = match fs::exists(path) {
+        Ok(true) => SaveSlots::from_bytes(&read_file(path)).unwrap_or_else(|e| {
+            eprintln!("cannot load save-slots: {:?}", e);
+            exit(1);
+        }),
+        _ => SaveSlots::new(),
+    };
+
+    match (cmd.as_str(), &argv[2..]) {
+        ("list", []) => {
+            for s in slots.slots() {
+                println!("{}\t{}", s.timestamp(), s.name());
+            }
+        }
+
+        ("save", [name, state_path]) => {
+            let emu = load_emulator(&read_file(state_path), None);
+            if let Err(e) = slots.save(name, &emu) {
+                eprintln!("cannot save slot '{}': {:?}", name, e);
+                exit(1);
+            }
+            write_file(path, &slots.to_bytes());
+        }
+
+        ("load", [name, out_path]) => match slots.load(name) {
+            Ok(emu) => write_file(out_path, &emu.save_state()),
             Err(e) => {
-                eprintln!("Emulator error: {:?}", e);
+                eprintln!("cannot load slot '{}': {:?}", name, e);
                 exit(1);
             }
         },
-        Err(e) => {
-            eprintln!("cannot open file '{}': {:?}", path, e);
+
+        ("remove", [name]) => {
+            if !slots.remove(name) {
+                eprintln!("no slot named '{}'", name);
+                exit(1);
+            }
+            write_file(path, &slots.to_bytes());
+        }
+
+        _ => usage(),
+    }
+}
+
+/// Create emulator from either a ROM or save-state file contents,
+/// starting the ROM with `boot_rom` if given.
+fn load_emulator(data: &[u8], boot_rom: Option<&[u8]>) -> Emulator {
+    let emu = if Emulator::is_save_state(data) {
+        Emulator::from_state(data)
+    } else {
+        // Tell in advance why the game may not work.
+        for note in CartridgeInfo::from_rom(data).map_or(vec![], |i| i.unsupported) {
+            eprintln!("note: {}", note);
+        }
+        match boot_rom {
+            Some(boot_rom) => Emulator::from_rom_with_bootrom(data, boot_rom),
+            None => Emulator::new(data),
+        }
+    };
+
+    emu.unwrap_or_else(|e| {
+        eprintln!("Emulator error: {:?}", e);
+        exit(1);
+    })
+}
+
+/// Runs the emulator for the GUI, which talks to it with messages either
+/// way. It runs in its own thread, or on the GUI thread while a reply is
+/// waited for, which has less latency but runs it at the display rate.
+struct Driver {
+    tx: mpsc::Sender<UserMsg>,
+    rx: mpsc::Receiver<EmulatorMsg>,
+    mode: DriverMode,
+}
+
+enum DriverMode {
+    /// See `Emulator::run`.
+    Threaded(thread::JoinHandle<Emulator>),
+    /// See `Emulator::run_once`, it has the other ends of the channels.
+    Direct {
+        emu: Box<Emulator>,
+        rx: mpsc::Receiver<UserMsg>,
+        tx: mpsc::Sender<EmulatorMsg>,
+        is_running: bool,
+    },
+}
+
+impl Driver {
+    fn new(mut emu: Emulator, direct: bool) -> Self {
+        let (user_tx, user_rx) = mpsc::channel::<UserMsg>();
+        let (emu_tx, emu_rx) = mpsc::channel::<EmulatorMsg>();
+        let mode = if direct {
+            DriverMode::Direct {
+                emu: Box::new(emu),
+                rx: user_rx,
+                tx: emu_tx,
+                is_running: true,
+            }
+        } else {
+            DriverMode::Threaded(thread::spawn(move || {
+                emu.run(user_rx, emu_tx);
+                emu
+            }))
+        };
+
+        Self {
+            tx: user_tx,
+            rx: emu_rx,
+            mode,
+        }
+    }
+
+    fn sender(&self) -> mpsc::Sender<UserMsg> {
+        self.tx.clone()
+    }
+
+    /// Wait for a message from the emulator, `None` if it has stopped.
+    fn recv(&mut self) -> Option<EmulatorMsg> {
+        match &mut self.mode {
+            DriverMode::Threaded(_) => self.rx.recv().ok(),
+            DriverMode::Direct {
+                emu,
+                rx,
+                tx,
+                is_running,
+            } => loop {
+                if let Ok(msg) = self.rx.try_recv() {
+                    return Some(msg);
+                }
+                if !*is_running {
+                    return None;
+                }
+                *is_running = emu.run_once(rx, tx);
+            },
+        }
+    }
+
+    /// Get the emulator back after it was shut down, `None` if it crashed.
+    fn finish(self) -> Option<Emulator> {
+        match self.mode {
+            DriverMode::Threaded(handle) => handle.join().ok(),
+            DriverMode::Direct { emu, .. } => Some(*emu),
+        }
+    }
+}
+
+/// Wait for the reply to a request, `None` if the emulator has stopped.
+/// Problems and replies to save-slot requests which arrive in the meantime
+/// are printed and the last one is kept in `notice` for showing it.
+fn recv_reply(
+    driver: &mut Driver,
+    notice: &mut Option<(String, Instant)>,
+    paused: &mut bool,
+) -> Option<EmulatorMsg> {
+    loop {
+        let text = match driver.recv()? {
+            EmulatorMsg::Error(e) => format!("emulator: {}", e),
+            EmulatorMsg::ConsoleLine(line) => format!("serial: {}", line),
+            EmulatorMsg::LinkActivityDetected => {
+                "the game is trying to link, but no link cable is connected".to_string()
+            }
+            EmulatorMsg::Paused => {
+                *paused = true;
+                continue;
+            }
+            EmulatorMsg::StateSaved(name) => format!("saved to slot '{}'", name),
+            EmulatorMsg::StateLoaded(name) => format!("loaded slot '{}'", name),
+            EmulatorMsg::SlotError(name, e) => format!("slot '{}': {:?}", name, e),
+            msg => return Some(msg),
+        };
+        eprintln!("\n{}", text);
+        *notice = Some((text, Instant::now()));
+    }
+}
+
+/// Check if the path is stdin(`-`) or a URL rather than a file.
+fn is_remote(path: &str) -> bool {
+    path == "-" || path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Read contents of a file, stdin if it is `-`, or an http(s) URL.
+fn read_source(path: &str) -> Vec<u8> {
+    if path == "-" {
+        let mut data = Vec::new();
+        if let Err(e) = io::stdin().read_to_end(&mut data) {
+            eprintln!("cannot read stdin: {:?}", e);
             exit(1);
         }
+        data
+    } else if is_remote(path) {
+        fetch_url(path)
+    } else {
+        read_file(path)
+    }
+}
+
+#[cfg(feature = "http")]
+fn fetch_url(url: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    let res = ureq::get(url)
+        .call()
+        .map_err(|e| e.to_string())
+        .and_then(|r| {
+            r.into_reader()
+                .read_to_end(&mut data)
+                .map_err(|e| e.to_string())
+        });
+
+    if let Err(e) = res {
+        eprintln!("cannot download '{}': {}", url, e);
+        exit(1);
+    }
+    data
+}
+
+#[cfg(feature = "demo")]
+fn demo_rom() -> Vec<u8> {
+    Emulator::demo_rom()
+}
+
+#[cfg(not(feature = "demo"))]
+fn demo_rom() -> Vec<u8> {
+    eprintln!("cannot run the demo: built without the demo feature");
+    exit(1);
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch_url(url: &str) -> Vec<u8> {
+    eprintln!("cannot download '{}': built without the http feature", url);
+    exit(1);
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| {
+        eprintln!("cannot open file '{}': {:?}", path, e);
+        exit(1);
+    })
+}
+
+fn write_file(path: impl AsRef<std::path::Path>, data: &[u8]) {
+    if let Err(e) = fs::write(&path, data) {
+        eprintln!("cannot write file '{}': {:?}", path.as_ref().display(), e);
+        exit(1);
+    }
+}
+
+/// Parse command line arguments, print usage and exit if invalid.
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut path = None;
+    let mut argv = std::env::args().skip(1).peekable();
+    args.demo = argv.next_if_eq("demo").is_some();
+
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--script" => args.script = true,
+            "--uncap-sprites" => args.uncap_sprites = true,
+            "--overclock" => args.overclock = true,
+            "--halt-bug" => args.halt_bug = true,
+            "--dmg-colors" => args.dmg_colors = true,
+            "--freeze" => {
+                let freeze = next_value(&mut argv);
+                let parsed = freeze.split_once('=').and_then(|(addr, val)| {
+                    Some((parse_addr(addr)?, u8::from_str_radix(val, 16).ok()?))
+                });
+                args.freezes.push(parsed.unwrap_or_else(|| usage()));
+            }
+            "--cheat" => {
+                let code = next_value(&mut argv);
+                if Cheat::parse(&code).is_none() {
+                    usage();
+                }
+                args.cheats.push(code);
+            }
+            "--clear-cheats" => args.clear_cheats = true,
+            "--touch" => args.touch = true,
+            "--break-on-bad-exec" => args.break_on_bad_execution = true,
+            "--serial-console" => args.serial_console = true,
+            "--reset-combo" => args.reset_combo = true,
+            "--opposite-dpad" => {
+                args.opposite_dpad =
+                    parse_opposite_dpad(&next_value(&mut argv)).unwrap_or_else(|| usage())
+            }
+            "--ir" => {
+                args.ir_source = parse_ir_source(&next_value(&mut argv)).unwrap_or_else(|| usage())
+            }
+            "--aspect" => {
+                args.aspect =
+                    Some(AspectMode::parse(&next_value(&mut argv)).unwrap_or_else(|| usage()))
+            }
+            "--rewind" => {
+                args.rewind_mib = next_value(&mut argv).parse().unwrap_or_else(|_| usage())
+            }
+            "--save-state" => args.save_state = true,
+            "--save-rewind" => {
+                args.save_rewind_secs = next_value(&mut argv).parse().unwrap_or_else(|_| usage())
+            }
+            "--watch" => args.watch = true,
+            "--watch-keep-state" => {
+                args.watch = true;
+                args.watch_keep_state = true;
+            }
+            "--test-pattern" => args.test_pattern = true,
+            "--viewer" => args.viewer = true,
+            "--direct" => args.direct = true,
+            "--gdb" => args.gdb = Some(next_value(&mut argv)),
+            "--rom-db" => args.rom_db = Some(next_value(&mut argv)),
+            "--bootrom" => args.boot_rom = Some(next_value(&mut argv)),
+            "--timing-csv" => args.timing_csv = Some(next_value(&mut argv)),
+            "--background" => {
+                args.background =
+                    Some(parse_color(&next_value(&mut argv)).unwrap_or_else(|| usage()))
+            }
+            "--frames" => {
+                args.frames = Some(next_value(&mut argv).parse().unwrap_or_else(|_| usage()))
+            }
+            "--until-pc" => {
+                args.until_pc = Some(parse_addr(&next_value(&mut argv)).unwrap_or_else(|| usage()))
+            }
+            "--exit-screenshot" => args.exit_screenshot = Some(next_value(&mut argv)),
+            "--exit-state" => args.exit_state = Some(next_value(&mut argv)),
+            _ if arg.starts_with("--") || path.is_some() => usage(),
+            _ => path = Some(arg),
+        }
+    }
+
+    let has_exit_opts = args.exit_screenshot.is_some() || args.exit_state.is_some();
+    if (args.script && args.is_headless())
+        || (has_exit_opts && !args.is_headless())
+        || (args.gdb.is_some() && (args.script || args.is_headless()))
+    {
+        usage();
+    }
+
+    match path {
+        _ if args.test_pattern && args.demo => usage(),
+        Some(_) if args.is_builtin() => usage(),
+        Some(p) => args.path = p,
+        None if args.is_builtin() => (),
+        None => usage(),
+    }
+    args
+}
+
+/// Get the value following an option.
+fn next_value(argv: &mut impl Iterator<Item = String>) -> String {
+    argv.next().unwrap_or_else(|| usage())
+}
+
+/// Parse a hexadecimal address, with an optional `0x` or `$` prefix.
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.strip_prefix("0x").or(s.strip_prefix('$')).unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn parse_opposite_dpad(s: &str) -> Option<OppositeDirections> {
+    match s {
+        "allow" => Some(OppositeDirections::Allow),
+        "neutral" => Some(OppositeDirections::Neutral),
+        "last" => Some(OppositeDirections::LastPressed),
+        _ => None,
+    }
+}
+
+/// Parse `dark`, `noise` or `noise:<seed>`, a random seed if not given.
+fn parse_ir_source(s: &str) -> Option<IrSource> {
+    match s.split_once(':') {
+        None if s == "dark" => Some(IrSource::Dark),
+        None if s == "noise" => {
+            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+            Some(IrSource::Noise(now.map_or(0, |d| d.as_nanos() as u64)))
+        }
+        Some(("noise", seed)) => seed.parse().ok().map(IrSource::Noise),
+        _ => None,
+    }
+}
+
+/// Parse a color in hexadecimal `RRGGBB` format, with an optional `#` prefix.
+fn parse_color(s: &str) -> Option<Color> {
+    let s = s.strip_prefix('#').unwrap_or(s);
+    if s.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(s, 16).ok()?;
+    Some(Color::from_rgba(
+        (rgb >> 16) as u8,
+        (rgb >> 8) as u8,
+        rgb as u8,
+        255,
+    ))
+}
+
+fn usage() -> ! {
+    let name = args().next().unwrap_or("gbemu".to_string());
+    eprintln!("Usage: {} [options] <rom-or-state-file>", name);
+    eprintln!("       {} [options] --test-pattern", name);
+    eprintln!("       {} demo [options]", name);
+    eprintln!("       {} export-sram <state-file> <out.srm>", name);
+    eprintln!("       {} import-sram <state-file> <in.srm>", name);
+    eprintln!(
+        "       {} extract-rom <state-file> <out.gb> [--sram <out.srm>]",
+        name
+    );
+    eprintln!("       {} slots list <slots-file>", name);
+    eprintln!(
+        "       {} slots save <slots-file> <name> <rom-or-state-file>",
+        name
+    );
+    eprintln!(
+        "       {} slots load <slots-file> <name> <out-state-file>",
+        name
+    );
+    eprintln!("       {} slots remove <slots-file> <name>", name);
+    eprintln!("       {} compat <rom-dir> [--frames <n>] [--csv]", name);
+    eprintln!("       {} test <rom-file> [--frames <n>]", name);
+    eprintln!();
+    eprintln!("compat runs each .gb and .gbc file in a directory for 600 frames");
+    eprintln!("or the given number, and writes a Markdown table, or CSV, of");
+    eprintln!("whether they boot, the last frame and problems found to stdout.");
+    eprintln!();
+    eprintln!("test runs a blargg or mooneye test ROM for 7200 frames or the");
+    eprintln!("given number, until it reports its result over the serial port or");
+    eprintln!("by executing LD B, B. It exits with 0 if it passed, 1 if it failed");
+    eprintln!("and 2 if it did not finish.");
+    eprintln!();
+    eprintln!("demo runs a built-in cartridge for checking that video and the");
+    eprintln!("controls work, the D-pad moves a square and the boxes at the top");
+    eprintln!("are filled while A, B, Select and Start are held. It requires the");
+    eprintln!("demo feature.");
+    eprintln!();
+    eprintln!("With --save-state the state is saved to <rom-file>.state on exit,");
+    eprintln!("and it is always overwritten if resumed from a state file. Use -");
+    eprintln!("for reading the file from stdin, or an http(s) URL if built with");
+    eprintln!("the http feature, the state is not saved for those. Cartridge RAM");
+    eprintln!("of games with a battery is also saved to <rom-name>.sav, and");
+    eprintln!("loaded from it when starting from the ROM. The file must be as");
+    eprintln!("large as the cartridge RAM.");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --script         Read buttons to hold for each frame line-by-line");
+    eprintln!("                   from stdin and write the frame hashes to stdout.");
+    eprintln!("  --uncap-sprites  Draw all sprites on a line instead of only the");
+    eprintln!("                   first 10, reduces flicker but is not accurate.");
+    eprintln!("  --overclock      Run the CPU at twice the speed of the rest, which");
+    eprintln!("                   reduces slowdown in games but is not accurate.");
+    eprintln!("  --halt-bug       Emulate the HALT bug, where the byte after a HALT");
+    eprintln!("                   is run twice if it does not halt, for accuracy.");
+    eprintln!("  --dmg-colors     Show monochrome games in color, like a CGB does");
+    eprintln!("                   for games it does not know.");
+    eprintln!("  --freeze <addr>=<value>");
+    eprintln!("                   Keep the byte at the hexadecimal address at the");
+    eprintln!("                   hexadecimal value, writes by the game write the");
+    eprintln!("                   value instead. It can be given multiple times.");
+    eprintln!("  --cheat <code>   Enable a Game Genie(like 00A-17B-C49) or GameShark");
+    eprintln!("                   (like 010238CD) code. It can be given multiple times.");
+    eprintln!("                   Cheats are remembered for each game, the ones given");
+    eprintln!("                   replace the remembered ones.");
+    eprintln!("  --clear-cheats   Forget the remembered cheats of the game.");
+    eprintln!("  --touch          Show on-screen buttons for touchscreens.");
+    eprintln!("  --reset-combo    Reset when A, B, Start and Select are held together,");
+    eprintln!("                   for games which do not handle it themselves.");
+    eprintln!("  --break-on-bad-exec");
+    eprintln!("                   Pause when code runs from VRAM, OAM or I/O, or past");
+    eprintln!("                   the end of bank 0 into a bank other than 1.");
+    eprintln!("  --serial-console Show text the game sends over the link port line by");
+    eprintln!("                   line, for debug messages of homebrew.");
+    eprintln!("  --opposite-dpad <mode>");
+    eprintln!("                   What the game sees when left and right, or up and");
+    eprintln!("                   down, are held together, which a real D-pad cannot");
+    eprintln!("                   do. One of allow(both), neutral(neither) or last");
+    eprintln!("                   (the one pressed last). Default is allow.");
+    eprintln!("  --ir <source>    Light seen by the infrared port of a CGB, one of");
+    eprintln!("                   dark, noise(random flickers) or noise:<seed> for the");
+    eprintln!("                   same flickers every run. Default is dark.");
+    eprintln!("  --aspect <mode>  How the frame fits the window, one of integer,");
+    eprintln!("                   fit or stretch. Default is integer.");
+    eprintln!("  --rewind <MiB>   Keep a rewind history of the given size, hold R");
+    eprintln!("                   to rewind.");
+    eprintln!("  --save-state     Save the state to <rom-file>.state on exit.");
+    eprintln!("  --save-rewind <seconds>");
+    eprintln!("                   Keep the last seconds of the rewind history in the");
+    eprintln!("                   state saved on exit, for rewinding past the point");
+    eprintln!("                   resumed from. Use it along with --rewind.");
+    eprintln!("  --background <RRGGBB>");
+    eprintln!("                   Color of the borders around the frame.");
+    eprintln!("  --watch          Reload the ROM file when it changes and restart.");
+    eprintln!("  --watch-keep-state");
+    eprintln!("                   Same as --watch, but continue from the same state");
+    eprintln!("                   with the new ROM instead of restarting.");
+    eprintln!("  --viewer         Show tiles, background map, sprites and palettes");
+    eprintln!("                   beside the frame, press F4 to toggle it.");
+    eprintln!("  --direct         Run the emulator a frame at a time as frames are");
+    eprintln!("                   drawn, instead of in its own thread. It has less");
+    eprintln!("                   input latency, but the speed follows the display");
+    eprintln!("                   refresh rate.");
+    eprintln!("  --test-pattern   Run a built-in cartridge for checking video latency");
+    eprintln!("                   and smoothness, instead of a file. It shows a bar");
+    eprintln!("                   moving 2 pixels per frame and flashes once in");
+    eprintln!("                   60 frames. It has no sound, as sound is not");
+    eprintln!("                   emulated yet.");
+    eprintln!("  --timing-csv <file>");
+    eprintln!("                   Write time spent on each drawn frame to a CSV file.");
+    eprintln!("  --gdb <[host:]port>");
+    eprintln!("                   Wait for a GDB remote debugger to connect and run");
+    eprintln!("                   without the GUI under its control. Requires the gdb");
+    eprintln!("                   feature, use `set architecture z80` in the debugger.");
+    eprintln!("  --bootrom <file> Start the ROM with a DMG or CGB boot ROM, instead of");
+    eprintln!("                   from the state it leaves the machine in. The game");
+    eprintln!("                   runs on a DMG or a CGB depending on which it is.");
+    eprintln!("  --rom-db <dat-file>");
+    eprintln!("                   Identify the ROM using a No-Intro style XML DAT file");
+    eprintln!("                   and tell if it is a bad dump. Requires the romdb");
+    eprintln!("                   feature.");
+    eprintln!();
+    eprintln!("Options for running without the GUI, as fast as possible:");
+    eprintln!("  --frames <n>     Stop after running for n frames.");
+    eprintln!("  --until-pc <addr>");
+    eprintln!("                   Stop when PC reaches the hexadecimal address,");
+    eprintln!("                   exits with status 2 if --frames ran out first.");
+    eprintln!("  --exit-screenshot <file>");
+    eprintln!("                   Write the last frame to a file as PPM image.");
+    eprintln!("  --exit-state <file>");
+    eprintln!("                   Write the state to a file when stopped.");
+    eprintln!();
+    eprintln!("Press P to pause or resume, N to advance a frame while paused,");
+    eprintln!("F2 to toggle tracing executed instructions to stderr, F3 to");
+    eprintln!("switch the aspect mode, F4 to toggle the viewer panel, F5 to");
+    eprintln!("save to the quick slot and F9 to load from it. Shift+1 to 4 save");
+    eprintln!("to slots 1 to 4 and 1 to 4 load from them. Slots are kept in");
+    eprintln!("<rom-file>.slots, or next to the state file if resumed from one.");
+    eprintln!("For cartridges with a tilt sensor, the mouse tilts the cartridge");
+    eprintln!("towards it by how far it is from the center of the window.");
+    eprintln!();
+    eprintln!("The window size, aspect mode, background, viewer panel and cheats are");
+    eprintln!("remembered in gbemu/settings.txt under the user config directory.");
+
+    exit(1);
+}
+
+/// Print what the ROM is according to a DAT file.
+#[cfg(feature = "romdb")]
+fn identify_rom(rom: &[u8], dat_path: &str) {
+    let dat = String::from_utf8_lossy(&read_file(dat_path)).into_owned();
+    let db = gbemu::RomDb::from_dat(&dat);
+    match db.lookup(rom) {
+        Some(e) => {
+            let status = if e.bad_dump {
+                ", bad dump, it may not work"
+            } else if e.verified {
+                ", verified good dump"
+            } else {
+                ""
+            };
+            eprintln!("identified as '{}'{}", e.name, status);
+        }
+        None => eprintln!(
+            "not found in the ROM database, CRC-32 {:08X}",
+            CartridgeInfo::from_rom(rom).map_or(0, |i| i.crc32)
+        ),
+    }
+}
+
+#[cfg(not(feature = "romdb"))]
+fn identify_rom(_rom: &[u8], _dat_path: &str) {
+    eprintln!("cannot identify the ROM: built without the romdb feature");
+}
+
+/// Serve a GDB remote debugger on `addr`, localhost if only a port is given.
+#[cfg(feature = "gdb")]
+fn run_gdb(mut emu: Emulator, addr: &str) {
+    let addr = if addr.contains(':') {
+        addr.to_string()
+    } else {
+        format!("127.0.0.1:{}", addr)
     };
 
-    // Start the emulator and give it channels to send and recieve messages.
-    let (user_tx, user_rx) = mpsc::channel::<UserMsg>();
-    let (emu_tx, emu_rx) = mpsc::channel::<EmulatorMsg>();
-    let handle = thread::spawn(move || {
-        emu.run(user_rx, emu_tx);
-    });
+    eprintln!("waiting for a debugger on {}", addr);
+    if let Err(e) = gbemu::serve_gdb(&mut emu, addr.as_str()) {
+        eprintln!("debugger connection failed: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(not(feature = "gdb"))]
+fn run_gdb(_emu: Emulator, _addr: &str) {
+    eprintln!("cannot serve a debugger: built without the gdb feature");
+    exit(1);
+}
+
+/// Advance one frame for each line read from stdin while holding the
+/// buttons listed on it and write the frame number and hash to stdout.
+///
+/// Buttons are separated by spaces, commas or pluses, for example,
+/// `a+right` or `start`. An empty line advances a frame with no buttons
+/// held and lines starting with `#` are ignored.
+fn run_script(mut emu: Emulator) {
+    let mut out = io::stdout().lock();
+    let mut frames = 0u64;
+
+    for (n, line) in io::stdin().lock().lines().enumerate() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("cannot read stdin: {:?}", e);
+                exit(1);
+            }
+        };
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let btns = match parse_buttons(&line) {
+            Ok(b) => b,
+            Err(name) => {
+                eprintln!("line {}: unknown button '{}'", n + 1, name);
+                exit(1);
+            }
+        };
+
+        frames += 1;
+        let hash = emu.step_frame(btns).hash();
+        // Flush every line, so that the other end can respond to it.
+        if writeln!(out, "{} {:016x}", frames, hash)
+            .and_then(|_| out.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Run with no buttons held until the limits given in `args` are reached,
+/// then write the requested exit files.
+fn run_headless(mut emu: Emulator, args: &Args) {
+    let mut frames = 0u64;
+    let mut hit_pc = false;
+
+    while args.frames.is_none_or(|n| frames < n) {
+        let btns = ButtonState::default();
+        if let Some(pc) = args.until_pc {
+            hit_pc = emu.step_frame_until(btns, pc);
+            if hit_pc {
+                break;
+            }
+        } else {
+            emu.step_frame(btns);
+        }
+        for line in emu.take_console_lines() {
+            println!("serial: {}", line);
+        }
+        frames += 1;
+    }
+
+    if hit_pc {
+        println!("reached PC ${:04X} in frame {}", emu.pc(), frames + 1);
+    } else {
+        println!("ran {} frames", frames);
+    }
+
+    if let Some(path) = &args.exit_screenshot {
+        let (w, h) = SCREEN_SIZE;
+        let mut ppm = format!("P6\n{} {}\n255\n", w, h).into_bytes();
+        ppm.extend(emu.frame().to_rgb24());
+        write_file(path, &ppm);
+    }
+    if let Some(path) = &args.exit_state {
+        write_file(path, &emu.save_state());
+    }
+
+    if args.until_pc.is_some() && !hit_pc {
+        exit(2);
+    }
+}
+
+/// Parse button names, returns the unknown name on failure.
+fn parse_buttons(line: &str) -> Result<ButtonState, &str> {
+    let mut btns = ButtonState::default();
+
+    for name in line.split([' ', '\t', ',', '+']).filter(|s| !s.is_empty()) {
+        let pressed = match name.to_ascii_lowercase().as_str() {
+            "a" => &mut btns.a,
+            "b" => &mut btns.b,
+            "select" => &mut btns.select,
+            "start" => &mut btns.start,
+            "up" => &mut btns.up,
+            "down" => &mut btns.down,
+            "left" => &mut btns.left,
+            "right" => &mut btns.right,
+            _ => return Err(name),
+        };
+        *pressed = true;
+    }
+
+    Ok(btns)
+}
+
+/// Open the window and run the GUI in it. Failing to open it is reported
+/// with a hint instead of a panic backtrace, macroquad panics on failure.
+fn start_gui(emu: Emulator, state_path: Option<PathBuf>, args: Args) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if WINDOW_OPENED.load(Ordering::Relaxed) {
+            default_hook(info);
+        }
+    }));
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        macroquad::Window::new("[C]GB-Emulator", run_gui(emu, state_path, args));
+    }));
+
+    let Err(e) = res else {
+        return;
+    };
+    if WINDOW_OPENED.load(Ordering::Relaxed) {
+        panic::resume_unwind(e);
+    }
+
+    let reason = e
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| e.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    eprintln!("cannot open the window: {}", reason);
+    eprintln!("Check that a display is available, on Linux DISPLAY or");
+    eprintln!("WAYLAND_DISPLAY must be set and OpenGL 2.1 or GLES 2 supported.");
+    eprintln!("Use --frames, --until-pc or --script for running without a window.");
+    exit(1);
+}
+
+async fn run_gui(mut emu: Emulator, state_path: Option<PathBuf>, args: Args) {
+    WINDOW_OPENED.store(true, Ordering::Relaxed);
+
+    // Quick save slots are kept next to the state file.
+    let slots_path = state_path.as_ref().map(|p| p.with_extension("slots"));
+    if let Some(path) = slots_path.as_ref().filter(|p| p.exists()) {
+        match SaveSlots::from_bytes(&read_file(&path.to_string_lossy())) {
+            Ok(slots) => emu.set_save_slots(slots),
+            Err(e) => eprintln!("cannot load save-slots, not using them: {:?}", e),
+        }
+    }
+
+    let has_accelerometer = emu.cartridge_info().has_accelerometer;
+    // Command line options take precedence over the saved settings.
+    let mut settings = Settings::load();
+
+    // Cheats given on the command line replace the saved ones of the game.
+    let crc32 = emu.cartridge_info().crc32;
+    let cheats = if args.cheats.is_empty() && !args.clear_cheats {
+        let saved = settings.cheats.get(&crc32).cloned().unwrap_or_default();
+        for cheat in saved.iter().filter_map(|c| Cheat::parse(c)) {
+            emu.add_cheat(cheat);
+        }
+        saved
+    } else {
+        args.cheats.clone()
+    };
+
+    let mut driver = Driver::new(emu, args.direct);
+    // If the emulator has stopped, then sending fails, ignore it as it is
+    // noticed when waiting for the next frame.
+    let user_tx = driver.sender();
+    let send = |msg: UserMsg| {
+        let _ = user_tx.send(msg);
+    };
 
     let mut btn_state = ButtonState::default();
+    let mut tilt = (0.0, 0.0);
+    let mut tracing = false;
+    let mut paused = false;
+    let mut aspect = args.aspect.or(settings.aspect).unwrap_or_default();
+    let background = args.background.or(settings.background).unwrap_or(BLACK);
+    let mut rewinding = false;
+    send(UserMsg::SetRewindBuffer(args.rewind_mib << 20));
+    let mut timing_log = args.timing_csv.as_deref().map(TimingLog::new);
+    let mut watcher = args.watch.then(|| FileWatcher::new(&args.path));
+    let mut viewer = (args.viewer || settings.viewer).then(DebugViewer::new);
+    // Last problem reported by the emulator and when.
+    let mut notice: Option<(String, Instant)> = None;
 
     // Configure window.
     prevent_quit();
-    set_window_size(WX, WY);
+    let (w, h) = match settings.window {
+        // Make room for the panel if it was not open last time.
+        Some((w, h)) if args.viewer && !settings.viewer => (w + VIEWER_W as u32, h),
+        Some(size) => size,
+        None if viewer.is_some() => (WX + VIEWER_W as u32, WY),
+        None => (WX, WY),
+    };
+    set_window_size(w, h);
 
     loop {
         // Handle events
@@ -58,49 +1310,636 @@ async fn main() {
             break;
         }
 
-        let new_state = get_button_state();
+        if is_key_pressed(KeyCode::P) {
+            paused = !paused;
+            send(UserMsg::SetPaused(paused));
+        }
+        if paused && is_key_pressed(KeyCode::N) {
+            send(UserMsg::StepFrame);
+        }
+        if is_key_pressed(KeyCode::F5) {
+            send(UserMsg::SaveSlot(QUICK_SLOT.to_string()));
+        }
+        if is_key_pressed(KeyCode::F9) {
+            send(UserMsg::LoadSlot(QUICK_SLOT.to_string()));
+        }
+        let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        for (key, name) in SLOT_KEYS {
+            if !is_key_pressed(key) {
+                continue;
+            }
+            if shift {
+                send(UserMsg::SaveSlot(name.to_string()));
+            } else {
+                send(UserMsg::LoadSlot(name.to_string()));
+            }
+        }
+        if is_key_pressed(KeyCode::F2) {
+            tracing = !tracing;
+            send(UserMsg::SetTraceExecution(tracing));
+        }
+        if is_key_pressed(KeyCode::F3) {
+            aspect = aspect.next();
+        }
+        if is_key_pressed(KeyCode::F4) {
+            // Make room for the panel, keeping the frame size.
+            let (w, h) = (screen_width(), screen_height());
+            let w = if viewer.is_some() {
+                viewer = None;
+                w - VIEWER_W
+            } else {
+                viewer = Some(DebugViewer::new());
+                w + VIEWER_W
+            };
+            set_window_size(w as u32, h as u32);
+        }
+        if is_key_down(KeyCode::R) != rewinding {
+            rewinding = !rewinding;
+            send(UserMsg::SetRewinding(rewinding));
+        }
+
+        if let Some(rom) = watcher.as_mut().and_then(|w| w.poll()) {
+            eprintln!("\nreloading '{}'", args.path);
+            let msg = UserMsg::ReplaceRom(rom, args.watch_keep_state);
+            send(msg);
+        }
+
+        // Window can be resized, so lay out the touch controls every time.
+        let touch_controls = args
+            .touch
+            .then(|| TouchControls::new(screen_width(), screen_height()));
+
+        let mut new_state = get_button_state();
+        if let Some(tc) = &touch_controls {
+            new_state = ButtonState::from_bits(new_state.to_bits() | tc.button_state().to_bits());
+        }
+        // Reset once when the last button of the combo gets pressed.
+        let is_combo = |b: ButtonState| b.a && b.b && b.start && b.select;
+        if args.reset_combo && is_combo(new_state) && !is_combo(btn_state) {
+            send(UserMsg::Reset);
+        }
         if new_state != btn_state {
             btn_state = new_state;
-            user_tx.send(UserMsg::Buttons(btn_state)).unwrap();
+            send(UserMsg::Buttons(btn_state));
+        }
+        if has_accelerometer {
+            let new_tilt = get_mouse_tilt();
+            if new_tilt != tilt {
+                tilt = new_tilt;
+                send(UserMsg::UpdateTilt(tilt.0, tilt.1));
+            }
         }
 
         // Get frame
-        user_tx.send(UserMsg::GetFrame).unwrap();
-        let frame = match emu_rx.recv() {
-            Ok(EmulatorMsg::NewFrame(f)) => f,
+        let wait_start = Instant::now();
+        send(UserMsg::GetFrame);
+        let frame = match recv_reply(&mut driver, &mut notice, &mut paused) {
+            Some(EmulatorMsg::NewFrame(f)) => f,
             _ => break,
         };
+        let wait = wait_start.elapsed();
+
+        let mut video_mem = None;
+        if viewer.is_some() {
+            send(UserMsg::GetVideoMemory);
+            video_mem = match recv_reply(&mut driver, &mut notice, &mut paused) {
+                Some(EmulatorMsg::VideoMemory(m)) => Some(m),
+                _ => break,
+            };
+        }
+
+        let mut timing = FrameTiming::default();
+        if timing_log.is_some() {
+            send(UserMsg::GetFrameTiming);
+            timing = match recv_reply(&mut driver, &mut notice, &mut paused) {
+                Some(EmulatorMsg::FrameTiming(t)) => t,
+                _ => break,
+            };
+        }
 
         // Get clock speed
-        // user_tx.send(UserMsg::GetFrequency).unwrap();
-        // let freq = match emu_rx.recv() {
-        //     Ok(EmulatorMsg::Frequency(f)) => f,
-        //     _ => break,
-        // };
+        send(UserMsg::GetFrequency);
+        let freq = match recv_reply(&mut driver, &mut notice, &mut paused) {
+            Some(EmulatorMsg::Frequency(f)) => f,
+            _ => break,
+        };
+        print!("\r{:.3}Hz", freq / 1e6);
+        _ = io::stdout().flush();
 
         // Draw stuff
         //-----------------------------------------------------------
-        clear_background(BLACK);
+        let draw_start = Instant::now();
+        clear_background(background);
+
+        let panel_w = if viewer.is_some() { VIEWER_W } else { 0.0 };
+        let area = aspect.frame_rect(screen_width() - panel_w, screen_height());
+        let bw = area.w / SCREEN_SIZE.0 as f32;
+        let bh = area.h / SCREEN_SIZE.1 as f32;
 
         for y in 0..SCREEN_SIZE.1 {
             for x in 0..SCREEN_SIZE.0 {
                 let (r, g, b) = frame.get(x, y).to_f32_triple();
                 let col = Color { r, g, b, a: 1.0 };
 
-                let px = x as f32 * BLOCK_SZ as f32;
-                let py = y as f32 * BLOCK_SZ as f32;
+                let px = area.x + x as f32 * bw;
+                let py = area.y + y as f32 * bh;
+
+                draw_rectangle(px, py, bw, bh, col);
+            }
+        }
+
+        if let (Some(v), Some(mem)) = (&viewer, &video_mem) {
+            v.draw(mem, screen_width() - VIEWER_W);
+        }
+        if let Some(tc) = &touch_controls {
+            tc.draw(btn_state);
+        }
+        if let Some((text, _)) = notice.as_ref().filter(|(_, at)| at.elapsed() < NOTICE_TIME) {
+            draw_text(text, 8.0, 24.0, 24.0, RED);
+        }
+        let draw = draw_start.elapsed();
+
+        let present_start = Instant::now();
+        next_frame().await;
+
+        if let Some(log) = &mut timing_log {
+            log.record(&timing, [wait, draw, present_start.elapsed()]);
+        }
+    }
+
+    if cheats.is_empty() {
+        settings.cheats.remove(&crc32);
+    } else {
+        settings.cheats.insert(crc32, cheats);
+    }
+    let settings = Settings {
+        window: Some((screen_width() as u32, screen_height() as u32)),
+        aspect: Some(aspect),
+        background: Some(background),
+        viewer: viewer.is_some(),
+        cheats: settings.cheats,
+    };
+    settings.save();
+
+    send(UserMsg::Shutdown);
+    matches!(
+        recv_reply(&mut driver, &mut notice, &mut paused),
+        Some(EmulatorMsg::ShuttingDown)
+    );
 
-                draw_rectangle(px, py, BLOCK_SZ as f32, BLOCK_SZ as f32, col);
+    let Some(emu) = driver.finish() else {
+        eprintln!("\nemulator crashed, the state is not saved");
+        exit(1);
+    };
+    if let Some(path) = state_path.as_ref().filter(|_| args.save_state) {
+        let frames = args.save_rewind_secs * 60; // About 60 frames per second.
+        write_file(path, &emu.save_state_with_rewind(frames));
+    }
+    if let Some(path) = slots_path.filter(|_| !emu.save_slots().slots().is_empty()) {
+        write_file(path, &emu.save_slots().to_bytes());
+    }
+    // Cartridges with a battery and no RAM have nothing to save.
+    let save_data = emu.save_data();
+    if let Some(path) = battery_save_path(&emu, &state_path).filter(|_| !save_data.is_empty()) {
+        write_file(path, &save_data);
+    }
+}
+
+/// Get the battery save file, which is kept next to the ROM with its
+/// extension replaced by `.sav`, like other emulators do. It is found
+/// from the state file by dropping `.state` and the ROM extension.
+/// `None` if the cartridge has no battery or there is no state file.
+fn battery_save_path(emu: &Emulator, state_path: &Option<PathBuf>) -> Option<PathBuf> {
+    let path = state_path
+        .as_ref()?
+        .with_extension("")
+        .with_extension("sav");
+    emu.cartridge_info().has_battery.then_some(path)
+}
+
+/// GUI settings kept across runs, they are saved on exit as `key=value`
+/// lines in `gbemu/settings.txt` under the user config directory.
+#[derive(Default)]
+struct Settings {
+    window: Option<(u32, u32)>,
+    aspect: Option<AspectMode>,
+    background: Option<Color>,
+    viewer: bool,
+    /// Cheat codes of each game, by the CRC-32 of its ROM.
+    cheats: BTreeMap<u32, Vec<String>>,
+}
+
+impl Settings {
+    /// Load saved settings, unknown or invalid ones are ignored.
+    fn load() -> Self {
+        let mut s = Self::default();
+        let Some(text) = Self::path().and_then(|p| fs::read_to_string(p).ok()) else {
+            return s;
+        };
+
+        for (key, val) in text.lines().filter_map(|l| l.split_once('=')) {
+            match key.trim() {
+                "window" => {
+                    s.window = val
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+                }
+                "aspect" => s.aspect = AspectMode::parse(val.trim()),
+                "background" => s.background = parse_color(val.trim()),
+                "viewer" => s.viewer = val.trim() == "true",
+                k if k.starts_with("cheats.") => {
+                    if let Ok(crc32) = u32::from_str_radix(&k["cheats.".len()..], 16) {
+                        let codes = val.split_whitespace().map(String::from).collect();
+                        s.cheats.insert(crc32, codes);
+                    }
+                }
+                _ => (),
+            }
+        }
+        s
+    }
+
+    /// Save settings, failing to do so is reported but not fatal.
+    fn save(&self) {
+        let Some(path) = Self::path() else {
+            return;
+        };
+
+        let mut text = String::new();
+        if let Some((w, h)) = self.window {
+            text += &format!("window={}x{}\n", w, h);
+        }
+        if let Some(a) = self.aspect {
+            text += &format!("aspect={}\n", a.name());
+        }
+        if let Some(c) = self.background {
+            let [r, g, b, _]: [u8; 4] = c.into();
+            text += &format!("background={:02X}{:02X}{:02X}\n", r, g, b);
+        }
+        text += &format!("viewer={}\n", self.viewer);
+        for (crc32, codes) in &self.cheats {
+            text += &format!("cheats.{:08X}={}\n", crc32, codes.join(" "));
+        }
+
+        let res = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, text));
+        if let Err(e) = res {
+            eprintln!("\ncannot save settings to '{}': {:?}", path.display(), e);
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            std::env::var_os("HOME").map(|h| Path::new(&h).join("Library/Application Support"))
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+        };
+        Some(dir?.join("gbemu").join("settings.txt"))
+    }
+}
+
+/// Shows VRAM contents in a panel: background map with the visible area,
+/// tiles of both VRAM banks, sprites and color palettes.
+/// Tiles are drawn in grey levels, as they have no palette of their own.
+struct DebugViewer {
+    map: Texture2D,
+    tiles: Texture2D,
+    sprites: Texture2D,
+}
+
+impl DebugViewer {
+    const MAP_SZ: (u16, u16) = (256, 256);
+    /// Both banks side-by-side, 16x24 tiles each.
+    const TILES_SZ: (u16, u16) = (256, 192);
+    /// 8x5 sprites of 8x16 pixels.
+    const SPRITES_SZ: (u16, u16) = (64, 80);
+
+    fn new() -> Self {
+        let texture = |(w, h): (u16, u16)| {
+            let t = Texture2D::from_rgba8(w, h, &vec![0; w as usize * h as usize * 4]);
+            t.set_filter(FilterMode::Nearest);
+            t
+        };
+
+        Self {
+            map: texture(Self::MAP_SZ),
+            tiles: texture(Self::TILES_SZ),
+            sprites: texture(Self::SPRITES_SZ),
+        }
+    }
+
+    /// Draw the panel with its left edge at `x0`.
+    fn draw(&self, mem: &VideoMemory, x0: f32) {
+        let x = x0 + 12.0;
+        let label = |text: &str, y: f32| draw_text(text, x, y, 16.0, WHITE);
+
+        label("Background map", 16.0);
+        update_texture(&self.map, Self::MAP_SZ, |x, y| map_pixel(mem, x, y));
+        draw_texture(&self.map, x, 24.0, WHITE);
+        let (scx, scy) = (mem.scx as f32, mem.scy as f32);
+        let (sw, sh) = (SCREEN_SIZE.0 as f32, SCREEN_SIZE.1 as f32);
+        draw_rectangle_lines(x + scx, 24.0 + scy, sw, sh, 2.0, RED);
+
+        label("Tiles, bank 0 and 1", 300.0);
+        update_texture(&self.tiles, Self::TILES_SZ, |x, y| {
+            let pal = if mem.is_cgb {
+                0xE4
+            } else {
+                mem.dmg_palettes[0]
+            };
+            let (bank, x) = (x / 128, x % 128);
+            let idx = tile_pixel(&mem.vram[bank], (y / 8 * 16 + x / 8) * 16, x % 8, y % 8);
+            grey(pal, idx)
+        });
+        draw_texture(&self.tiles, x, 308.0, WHITE);
+
+        label("Sprites", 520.0);
+        update_texture(&self.sprites, Self::SPRITES_SZ, |x, y| {
+            sprite_pixel(mem, y / 16 * 8 + x / 8, x % 8, y % 16)
+        });
+        let size = vec2(Self::SPRITES_SZ.0 as f32, Self::SPRITES_SZ.1 as f32) * 2.0;
+        let params = DrawTextureParams {
+            dest_size: Some(size),
+            ..Default::default()
+        };
+        draw_texture_ex(&self.sprites, x, 528.0, WHITE, params);
+
+        if mem.is_cgb {
+            draw_text("Palettes", x + 150.0, 520.0, 16.0, WHITE);
+            let palettes = mem.bg_palettes.iter().chain(&mem.obj_palettes);
+            for (i, pal) in palettes.enumerate() {
+                for (j, c) in pal.iter().enumerate() {
+                    let col = Color::from_rgba(c.r, c.g, c.b, 255);
+                    let px = x + 150.0 + j as f32 * 12.0 + (i / 8) as f32 * 56.0;
+                    let py = 528.0 + (i % 8) as f32 * 12.0;
+                    draw_rectangle(px, py, 10.0, 10.0, col);
+                }
             }
         }
+    }
+}
 
-        next_frame().await
+/// Fill a texture of `size` with RGBA colors given by `pixel`.
+fn update_texture(t: &Texture2D, size: (u16, u16), pixel: impl Fn(usize, usize) -> [u8; 4]) {
+    let (w, h) = (size.0 as usize, size.1 as usize);
+    let mut bytes = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        for x in 0..w {
+            bytes.extend(pixel(x, y));
+        }
     }
+    t.update_from_bytes(w as u32, h as u32, &bytes);
+}
+
+/// Get color index of a pixel of the tile at `addr` in a VRAM bank.
+fn tile_pixel(bank: &[u8], addr: usize, x: usize, y: usize) -> u8 {
+    let lo = bank[addr + y * 2];
+    let hi = bank[addr + y * 2 + 1];
+    let bit = 7 - x;
+    (hi >> bit & 1) << 1 | (lo >> bit & 1)
+}
+
+fn grey(palette: u8, idx: u8) -> [u8; 4] {
+    let v = SHADES[(palette >> (idx * 2) & 3) as usize];
+    [v, v, v, 255]
+}
+
+fn rgba(c: gbemu::Color) -> [u8; 4] {
+    [c.r, c.g, c.b, 255]
+}
+
+/// Get a pixel of the background map selected by LCDC.
+fn map_pixel(mem: &VideoMemory, x: usize, y: usize) -> [u8; 4] {
+    let base = if mem.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+    let entry = base + y / 8 * 32 + x / 8;
+    let tile = mem.vram[0][entry];
+    // Attributes are in bank 1, it is all zeros in DMG mode.
+    let attr = mem.vram[1][entry];
 
-    user_tx.send(UserMsg::Shutdown).unwrap();
-    matches!(emu_rx.recv(), Ok(EmulatorMsg::ShuttingDown));
+    let addr = if mem.lcdc & 0x10 != 0 {
+        tile as usize * 16
+    } else {
+        (0x1000 + tile as i8 as isize * 16) as usize
+    };
+    let bank = &mem.vram[(attr >> 3 & 1) as usize];
+    let idx = tile_pixel(bank, addr, x % 8, y % 8);
+
+    if mem.is_cgb {
+        rgba(mem.bg_palettes[(attr & 7) as usize][idx as usize])
+    } else {
+        grey(mem.dmg_palettes[0], idx)
+    }
+}
+
+/// Get a pixel of the sprite `n` drawn as 8x16, transparent ones are clear.
+fn sprite_pixel(mem: &VideoMemory, n: usize, x: usize, y: usize) -> [u8; 4] {
+    let [_, _, tile, attr] = mem.oam[n * 4..n * 4 + 4] else {
+        unreachable!()
+    };
+    let is_tall = mem.lcdc & 0x04 != 0;
+    if y >= 8 && !is_tall {
+        return [0; 4];
+    }
+
+    let tile = if is_tall { tile & 0xFE } else { tile } as usize;
+    let bank = if mem.is_cgb {
+        (attr >> 3 & 1) as usize
+    } else {
+        0
+    };
+    let idx = tile_pixel(&mem.vram[bank], tile * 16, x, y);
+
+    if idx == 0 {
+        [0; 4]
+    } else if mem.is_cgb {
+        rgba(mem.obj_palettes[(attr & 7) as usize][idx as usize])
+    } else {
+        grey(mem.dmg_palettes[1 + (attr >> 4 & 1) as usize], idx)
+    }
+}
+
+/// Polls a file for changes, for reloading a ROM when it is rebuilt.
+struct FileWatcher {
+    path: String,
+    /// Modification time and size when last checked.
+    last: Option<(SystemTime, u64)>,
+    /// It changed, but is not read until it stops changing, as it may
+    /// still be being written.
+    changed: bool,
+    next_poll: Instant,
+}
+
+impl FileWatcher {
+    fn new(path: &str) -> Self {
+        let mut w = Self {
+            path: path.to_string(),
+            last: None,
+            changed: false,
+            next_poll: Instant::now(),
+        };
+        w.last = w.stat();
+        w
+    }
+
+    /// Get the new contents once the file has changed and settled.
+    fn poll(&mut self) -> Option<Vec<u8>> {
+        let now = Instant::now();
+        if now < self.next_poll {
+            return None;
+        }
+        self.next_poll = now + WATCH_INTERVAL;
+
+        // It may be missing for a moment while it is rebuilt.
+        let current = self.stat()?;
+        if self.last != Some(current) {
+            self.last = Some(current);
+            self.changed = true;
+            return None;
+        }
+        if !self.changed {
+            return None;
+        }
+
+        self.changed = false;
+        fs::read(&self.path).ok()
+    }
+
+    fn stat(&self) -> Option<(SystemTime, u64)> {
+        let meta = fs::metadata(&self.path).ok()?;
+        Some((meta.modified().ok()?, meta.len()))
+    }
+}
+
+/// Per drawn frame timing, written as CSV for diagnosing stutter.
+/// Emulator times are totals since the previous drawn frame.
+struct TimingLog {
+    out: BufWriter<fs::File>,
+    frame: u64,
+}
+
+impl TimingLog {
+    fn new(path: &str) -> Self {
+        let file = fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("cannot create file '{}': {:?}", path, e);
+            exit(1);
+        });
+        let mut out = BufWriter::new(file);
+        writeln!(
+            out,
+            "frame,emulated_frames,instructions,lines_drawn,emulation_us,sleep_us,wait_us,draw_us,present_us"
+        )
+        .unwrap();
+        Self { out, frame: 0 }
+    }
+
+    /// Write a row, `gui` holds time spent waiting for the frame, drawing
+    /// it and presenting it.
+    fn record(&mut self, emu: &FrameTiming, gui: [Duration; 3]) {
+        let [wait, draw, present] = gui.map(|d| d.as_micros());
+        writeln!(
+            self.out,
+            "{},{},{},{},{},{},{},{},{}",
+            self.frame,
+            emu.frames,
+            emu.instructions,
+            emu.lines_drawn,
+            emu.emulation.as_micros(),
+            emu.sleep.as_micros(),
+            wait,
+            draw,
+            present
+        )
+        .unwrap();
+        self.frame += 1;
+    }
+}
+
+/// On-screen buttons drawn over the bottom of the frame, for touchscreens.
+/// Each finger can hold a different button.
+struct TouchControls {
+    keys: Vec<(Button, Rect, &'static str)>,
+}
+
+impl TouchControls {
+    /// Lay out the buttons for a window of size `w`x`h`.
+    fn new(w: f32, h: f32) -> Self {
+        let sz = w.min(h) / 8.0;
+        let (dx, dy) = (sz * 1.5, h - sz * 2.0);
+        let (ax, ay) = (w - sz * 1.5, h - sz * 2.5);
+        let key = |x: f32, y: f32, w: f32, h: f32| Rect::new(x - w / 2.0, y - h / 2.0, w, h);
+
+        Self {
+            keys: vec![
+                (Button::Up, key(dx, dy - sz, sz, sz), "^"),
+                (Button::Down, key(dx, dy + sz, sz, sz), "v"),
+                (Button::Left, key(dx - sz, dy, sz, sz), "<"),
+                (Button::Right, key(dx + sz, dy, sz, sz), ">"),
+                (Button::A, key(ax, ay, sz, sz), "A"),
+                (Button::B, key(ax - sz * 1.5, ay + sz * 0.75, sz, sz), "B"),
+                (
+                    Button::Select,
+                    key(w / 2.0 - sz, h - sz / 2.0, sz * 1.5, sz / 2.0),
+                    "SELECT",
+                ),
+                (
+                    Button::Start,
+                    key(w / 2.0 + sz, h - sz / 2.0, sz * 1.5, sz / 2.0),
+                    "START",
+                ),
+            ],
+        }
+    }
+
+    /// Get buttons held by touches, the mouse also acts as a touch.
+    fn button_state(&self) -> ButtonState {
+        let mut points: Vec<Vec2> = touches()
+            .into_iter()
+            .filter(|t| !matches!(t.phase, TouchPhase::Ended | TouchPhase::Cancelled))
+            .map(|t| t.position)
+            .collect();
+        if is_mouse_button_down(MouseButton::Left) {
+            points.push(mouse_position().into());
+        }
+
+        let mut btns = ButtonState::default();
+        for (button, rect, _) in &self.keys {
+            if points.iter().any(|&p| rect.contains(p)) {
+                btns.set(*button, true);
+            }
+        }
+        btns
+    }
+
+    /// Draw buttons, the ones pressed in `btns` are highlighted.
+    fn draw(&self, btns: ButtonState) {
+        for &(button, r, label) in &self.keys {
+            let alpha = if btns.is_pressed(button) { 0.6 } else { 0.25 };
+            let col = Color::new(1.0, 1.0, 1.0, alpha);
+            draw_rectangle(r.x, r.y, r.w, r.h, col);
+
+            let font_sz = r.h.min(r.w / label.len() as f32 * 1.5);
+            let dim = measure_text(label, None, font_sz as u16, 1.0);
+            let (x, y) = (
+                r.center().x - dim.width / 2.0,
+                r.center().y + dim.offset_y / 2.0,
+            );
+            draw_text(label, x, y, font_sz, BLACK);
+        }
+    }
+}
 
-    handle.join().unwrap();
+/// Get the tilt in g from the mouse position, it is 1 at the window edges.
+fn get_mouse_tilt() -> (f32, f32) {
+    let (x, y) = mouse_position();
+    let (hw, hh) = (screen_width() / 2.0, screen_height() / 2.0);
+    let axis = |pos: f32, half: f32| ((pos - half) / half).clamp(-1.0, 1.0);
+    (axis(x, hw), axis(y, hh))
 }
 
 fn get_button_state() -> ButtonState {
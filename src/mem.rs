@@ -1,53 +1,127 @@
-use std::io;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     cartridge::Cartidge,
+    cheats::{self, Cheat},
     info::*,
+    ir::IrSource,
     macros::{in_ranges, match_range},
+    msg::RuntimeError,
     ppu::Ppu,
     regs::{ActionButtons, CgbPaletteIndex, DPad, IntData, JoyPad, Key1, Rp},
     serial::Serial,
+    sgb::{Sgb, SGB_PLAYERS},
+    state::byte_array,
     timer::Timer,
 };
 
 /// The memory sub-system, contains the `Cartridge`, `Ppu`, `Timer`, `Serial`
 /// and some registers, other registers are owned by components they belong to.
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct Mmu {
     pub(crate) is_2x: bool,
     pub(crate) ppu: Ppu,
     pub(crate) timer: Timer,
     pub(crate) serial: Serial,
     pub(crate) cart: Cartidge,
+    pub(crate) sgb: Sgb,
 
     // Registers and memory owned by it.
+    /// Written by the CGB boot ROM, it takes effect once it is unmapped.
+    key0: u8,
     pub(crate) key1: Key1,
     pub(crate) iflag: IntData,
     pub(crate) joypad: JoyPad,
     pub(crate) ienable: IntData,
     pub(crate) bgpi: CgbPaletteIndex,
     pub(crate) obpi: CgbPaletteIndex,
-    pub(crate) opri: u8,
     pub(crate) dma: u8,
     pub(crate) rp: Rp,
     pub(crate) wram_idx: usize,
     pub(crate) vram_idx: usize,
+    /// Boot ROM mapped over the cartridge ROM, empty if there is none or
+    /// it was unmapped.
+    pub(crate) boot_rom: Vec<u8>,
     // First WRAM region always refers to bank-0 and
     // second WRAM region can refer to any of the 1-7 banks.
-    wram: [[u8; SIZE_WRAM_BANK]; WRAM_BANKS],
+    #[serde(with = "byte_array")]
+    wram: Box<[[u8; SIZE_WRAM_BANK]; WRAM_BANKS]>,
+    #[serde(with = "byte_array")]
     hram: [u8; SIZE_HRAM],
 
-    dpad: DPad,
-    buttons: ActionButtons,
+    /// Buttons for each joypad, only the first one is used unless
+    /// multiple joypads are requested through SGB packets.
+    pads: [(DPad, ActionButtons); SGB_PLAYERS],
     oam_dma: Option<OamDma>,
+    vram_dma: VramDma,
+    /// M-cycles the CPU is stalled for by VRAM DMA, the rest of the
+    /// machine keeps running for them. Taken by the CPU.
+    pub(crate) dma_stall: u16,
+
+    /// Enhancement: run the CPU twice as fast as the rest of the machine.
+    /// It is an option, so it is not serialized.
+    #[serde(skip)]
+    pub(crate) overclock: bool,
+    /// An M-cycle left over from odd cycle counts while overclocked.
+    #[serde(skip)]
+    overclock_carry: u16,
+    /// Problems reported while running, the ones before `errors_taken` were
+    /// already taken. They are only for the frontend, so not serialized.
+    #[serde(skip)]
+    errors: Vec<RuntimeError>,
+    #[serde(skip)]
+    errors_taken: usize,
+    /// Addresses which keep their value, as (address, value). Writes to
+    /// them write the frozen value instead. It is an option, so it is not
+    /// serialized.
+    #[serde(skip)]
+    pub(crate) freezes: Vec<(usize, u8)>,
+    /// Cheat codes in effect, an option like freezes.
+    #[serde(skip)]
+    pub(crate) cheats: Vec<Cheat>,
+    /// Light seen by the infrared port, an option.
+    #[serde(skip)]
+    pub(crate) ir_source: IrSource,
+    /// Dots since power on, the time for IR as they are not changed by
+    /// the CPU speed.
+    dots: u64,
 }
 
-#[derive(Clone, Copy)]
+/// At most these many problems are reported, after that they are dropped.
+const MAX_ERRORS: usize = 64;
+/// At most these many M-cycles are run at once while idle, a scanline.
+const MAX_IDLE_MCYCLES: u16 = PPU_HSCAN_DOTS / 4;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct OamDma {
     src: usize,
     copied: usize,
     count: usize,
 }
 
+/// CGB VRAM DMA, which copies blocks of 16 bytes to VRAM.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct VramDma {
+    src: u16,
+    /// Offset into VRAM.
+    dst: u16,
+    /// Blocks left minus one, as HDMA5 reads it. 0x7F when done.
+    blocks_left: u8,
+    /// An HBlank DMA is ongoing, it copies a block each HBlank.
+    hblank: bool,
+}
+
+impl Default for VramDma {
+    fn default() -> Self {
+        Self {
+            src: 0,
+            dst: 0,
+            blocks_left: 0x7F,
+            hblank: false,
+        }
+    }
+}
+
 impl Mmu {
     pub(crate) fn new(cartd: Cartidge) -> Self {
         Self {
@@ -57,13 +131,111 @@ impl Mmu {
         }
     }
 
+    /// Get M-cycles which can be run at once while the CPU is halted,
+    /// without missing anything which has to be handled cycle by cycle.
+    /// It is 1 unless the LCD is off, then it runs until the timer changes,
+    /// which speeds up load screens where the game waits with the LCD off.
+    pub(crate) fn idle_mcycles(&self) -> u16 {
+        let is_busy = self.ppu.fetcher.lcdc.ppu_enable == 1
+            || self.oam_dma.is_some()
+            || self.serial.sc.tx_enable == 1
+            || self.overclock;
+        if is_busy {
+            return 1;
+        }
+        self.timer.mcycles_to_change().min(MAX_IDLE_MCYCLES)
+    }
+
+    /// Report a problem, it is dropped if it was reported before.
+    pub(crate) fn report_error(&mut self, err: RuntimeError) {
+        if self.errors.len() < MAX_ERRORS && !self.errors.contains(&err) {
+            self.errors.push(err);
+        }
+    }
+
+    /// Get the ROM bank mapped at `addr`, `None` if it is not in ROM.
+    pub(crate) fn rom_bank_at(&self, addr: u16) -> Option<usize> {
+        let (rom0, rom1) = self.cart.mapped_rom_banks();
+        match addr as usize {
+            a if ADDR_ROM0.contains(&a) => Some(rom0),
+            a if ADDR_ROM1.contains(&a) => Some(rom1),
+            _ => None,
+        }
+    }
+
+    /// Freeze `addr` to `val` and write it, or unfreeze it if `None`.
+    pub(crate) fn set_freeze(&mut self, addr: u16, val: Option<u8>) {
+        let addr = unmirror(addr as usize);
+        self.freezes.retain(|f| f.0 != addr);
+
+        if let Some(val) = val {
+            self.freezes.push((addr, val));
+            self.write(addr as u16, val);
+        }
+    }
+
+    /// Write the values of GameShark codes, done at every VBlank.
+    fn apply_cheat_writes(&mut self) {
+        for i in 0..self.cheats.len() {
+            if let Cheat::GameShark { bank, addr, data } = self.cheats[i] {
+                let offset = (addr as usize).wrapping_sub(*ADDR_EXT_RAM.start());
+                let is_ext_ram = ADDR_EXT_RAM.contains(&(addr as usize));
+                if !(is_ext_ram && self.cart.write_ram_bank(bank as usize, offset, data)) {
+                    self.write(addr, data);
+                }
+            }
+        }
+    }
+
+    /// Take the problems reported since the last call.
+    pub(crate) fn take_errors(&mut self) -> Vec<RuntimeError> {
+        let new = self.errors[self.errors_taken..].to_vec();
+        self.errors_taken = self.errors.len();
+        new
+    }
+
     /// Advance DMA(if any) and manage system clock.
     pub(crate) fn tick(&mut self, mcycles: u16) {
+        // Overclocking gives the CPU two M-cycles for each one of others.
+        let mcycles = if self.overclock {
+            let total = mcycles + self.overclock_carry;
+            self.overclock_carry = total % 2;
+            total / 2
+        } else {
+            mcycles
+        };
+
         // Dual-speed mode does not change PPU or Audio speed.
         let dots = if self.is_2x { mcycles * 2 } else { mcycles * 4 };
+        self.dots += dots as u64;
 
-        let news = self.ppu.tick(dots);
-        self.add_interrupt(news);
+        // HBlank DMA copies a block on entering each HBlank. The CPU can be
+        // stalled for several scan-lines, so run the PPU in steps during it,
+        // which are too short for going from one HBlank to the next.
+        let step = if self.vram_dma.hblank {
+            PPU_SCAN_DOTS
+        } else {
+            dots
+        };
+        let mut dots_left = dots;
+        loop {
+            let dots = dots_left.min(step);
+            dots_left -= dots;
+
+            let old_mode = self.get_mode();
+            let news = self.ppu.tick(dots);
+            if news.vblank == 1 && !self.cheats.is_empty() {
+                self.apply_cheat_writes();
+            }
+            self.add_interrupt(news);
+            if self.vram_dma.hblank && old_mode != MODE_HBLANK && self.get_mode() == MODE_HBLANK {
+                self.vram_dma_step();
+            }
+
+            if dots_left == 0 {
+                break;
+            }
+        }
         if self.timer.tick(mcycles) {
             self.iflag.timer = 1;
         }
@@ -83,7 +255,7 @@ impl Mmu {
             }
 
             let addr = dma.src + dma.copied;
-            self.ppu.oam[dma.copied] = self.read(addr as u16);
+            self.ppu.oam[dma.copied] = self.dma_read(addr);
             dma.copied += 1;
         }
 
@@ -100,19 +272,37 @@ impl Mmu {
     // In our emulator we do check for such conditions when writing data, but
     // not when reading as reading does not have any side-effects.
 
-    /// Reads one byte, use when executing instructions by CPU.
+    /// Reads one byte as the CPU does. While OAM DMA is ongoing memory
+    /// which is not accessible gives the byte DMA is moving on the bus,
+    /// the last one it copied.
+    pub(crate) fn cpu_read(&self, addr: u16) -> u8 {
+        match self.oam_dma {
+            Some(dma) if !self.is_accessible(unmirror(addr as usize)) => {
+                dma.copied.checked_sub(1).map_or(0xFF, |i| self.ppu.oam[i])
+            }
+            _ => self.read(addr),
+        }
+    }
+
+    /// Reads one byte, regardless of any ongoing DMA.
     pub(crate) fn read(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
+        let addr = unmirror(addr as usize);
 
+        if self.is_boot_rom_addr(addr) {
+            return self.boot_rom[addr];
+        }
         if is_cart_addr(addr) {
-            return self.cart.read(addr);
+            let val = self.cart.read(addr);
+            if self.cheats.is_empty() {
+                return val;
+            }
+            return cheats::patch_rom(&self.cheats, addr as u16, val);
         }
 
         match_range! { a@addr {
             ADDR_VRAM => { self.ppu.fetcher.vram[self.vram_idx][a] }
             ADDR_WRAM0 => { self.wram[0][a] }
             ADDR_WRAM1 => { self.wram[self.wram_idx][a] }
-            ADDR_ECHO_RAM => { self.read(get_echo_ram_addr(a) as u16) }
             ADDR_OAM => { self.ppu.oam[a] }
             ADDR_UNUSABLE => { 0 }
             ADDR_HRAM => { self.hram[a] }
@@ -124,15 +314,21 @@ impl Mmu {
     }
 
     /// Writes one byte, use when executing instructions by CPU.
-    /// Writes to read-only registers are ignored, use `reg_set` for that.    timer:
-
+    /// Writes to read-only registers are ignored, use `reg_set` for that.
     pub(crate) fn write(&mut self, addr: u16, val: u8) {
-        let addr = addr as usize;
+        let addr = unmirror(addr as usize);
+        let val = match self.freezes.iter().find(|f| f.0 == addr) {
+            Some(&(_, frozen)) => frozen,
+            None => val,
+        };
 
         if !self.is_accessible(addr) {
             return;
         }
         if is_cart_addr(addr) {
+            if !self.cart.is_mbc_supported() {
+                self.report_error(RuntimeError::UnsupportedMbc);
+            }
             self.cart.write(addr, val);
             return;
         }
@@ -149,7 +345,6 @@ impl Mmu {
             }
             ADDR_WRAM0 => { self.wram[0][a] = val}
             ADDR_WRAM1 => { self.wram[self.wram_idx][a] = val }
-            ADDR_ECHO_RAM => { self.write(get_echo_ram_addr(a) as u16, val) }
 
             ADDR_OAM => {
                 if mode != MODE_DRAW && mode != MODE_SCAN {
@@ -193,6 +388,11 @@ impl Mmu {
     fn read_reg(&self, addr: usize) -> u8 {
         // pub(crate) const IO_WAVE_RAM: URange = 0xFF30..=0xFF3F;
 
+        // CGB registers do not exist on DMG, reads return open bus.
+        if !self.cart.is_cgb && is_cgb_reg(addr) {
+            return 0xFF;
+        }
+
         match addr {
             IO_JOYPAD => self.joypad.read(),
             IO_SB => self.serial.sb,
@@ -241,17 +441,22 @@ impl Mmu {
             IO_BGPD => self.ppu.bg_palette[self.bgpi.addr as usize],
             IO_OBPI => self.obpi.read(),
             IO_OBPD => self.ppu.obj_palette[self.obpi.addr as usize],
-            IO_OPRI => self.opri,
+            IO_OPRI => self.ppu.fetcher.opri,
             IO_SVBK => self.wram_idx as u8,
             IO_VBK => self.vram_idx as u8,
-            // IO_HDMA1 => {}
-            // IO_HDMA2 => {}
-            // IO_HDMA3 => {}
-            // IO_HDMA4 => {}
-            // IO_HDMA5 => {}
+            IO_HDMA1..=IO_HDMA4 => 0xFF,
+            IO_HDMA5 => {
+                let dma = self.vram_dma;
+                ((!dma.hblank as u8) << 7) | dma.blocks_left
+            }
             IO_DMA => self.dma,
             IO_KEY1 => self.key1.read(),
-            IO_RP => self.rp.read(),
+            IO_RP => {
+                // Bit 1 is 0 while light is seen, if reading is enabled.
+                let lit = self.rp.read_en == 0b11 && self.ir_source.is_lit(self.dots / 4);
+                self.rp.read() & !((lit as u8) << 1)
+            }
+            IO_KEY0 | IO_BOOT => 0xFF,
 
             _ => 0,
         }
@@ -262,6 +467,10 @@ impl Mmu {
     ///
     /// Writes to read-only registers(or register fields) are ignored.
     fn write_reg(&mut self, addr: usize, val: u8) {
+        if !self.cart.is_cgb && is_cgb_reg(addr) {
+            return;
+        }
+
         // Set value but keep masked bits preserved(if mask present).
         macro_rules! set {
             ($target:expr, $val:expr, $keep_mask:expr) => {{
@@ -277,8 +486,9 @@ impl Mmu {
         // Verify written data and perform the action.
         match addr {
             IO_JOYPAD => {
-                set!(self.joypad, val, mask(4) << 4);
-                self.update_joypad(self.dpad, self.buttons);
+                set!(self.joypad, val, !(mask(2) << 4));
+                self.sgb.write_select(val >> 4 & 0b11);
+                self.update_joypad();
             }
             IO_SB => self.serial.sb = val,
             IO_SC => set!(self.serial.sc, val, mask(5) << 2),
@@ -319,9 +529,9 @@ impl Mmu {
             IO_LYC => self.ppu.lyc = val,
             IO_WY => self.ppu.fetcher.wy = val,
             IO_WX => self.ppu.fetcher.wx = val,
-            IO_BGP => self.ppu.bgp = val,
-            IO_OBP0 => self.ppu.obp0 = val,
-            IO_OBP1 => self.ppu.obp1 = val,
+            IO_BGP => self.ppu.write_dmg_palette(0, val),
+            IO_OBP0 => self.ppu.write_dmg_palette(1, val),
+            IO_OBP1 => self.ppu.write_dmg_palette(2, val),
             IO_BGPI => set!(self.bgpi, val),
             IO_OBPI => self.obpi.write(val),
 
@@ -339,38 +549,68 @@ impl Mmu {
                 }
             }
 
-            IO_OPRI => self.opri = val & 1,
-            IO_SVBK if self.is_2x => {
+            IO_OPRI => self.ppu.fetcher.opri = val & 1,
+            IO_SVBK => {
                 if val == 0 {
                     self.wram_idx = 1;
                 } else {
                     self.wram_idx = (val & mask(3)) as usize;
                 }
             }
-            IO_VBK if self.is_2x => self.vram_idx = (val as usize) & 1,
+            IO_VBK => self.vram_idx = (val as usize) & 1,
 
-            // IO_HDMA1 => { = val}
-            // IO_HDMA2 => { = val}
-            // IO_HDMA3 => { = val}
-            // IO_HDMA4 => { = val}
-            // IO_HDMA5 => { = val}
+            IO_HDMA1 => self.vram_dma.src = (self.vram_dma.src & 0xFF) | (val as u16) << 8,
+            IO_HDMA2 => self.vram_dma.src = (self.vram_dma.src & 0xFF00) | (val & 0xF0) as u16,
+            IO_HDMA3 => self.vram_dma.dst = (self.vram_dma.dst & 0xFF) | ((val & 0x1F) as u16) << 8,
+            IO_HDMA4 => self.vram_dma.dst = (self.vram_dma.dst & 0xFF00) | (val & 0xF0) as u16,
+            IO_HDMA5 => self.start_vram_dma(val),
             IO_DMA => self.start_dma(val),
+            IO_KEY0 if !self.boot_rom.is_empty() => self.key0 = val,
             IO_KEY1 => set!(self.key1, val, !mask(1)),
             IO_RP => set!(self.rp, val, 1 << 1),
+            IO_BOOT if val != 0 => self.unmap_boot_rom(),
 
             _ => (),
         }
     }
 
+    /// Unmap the boot ROM, it cannot be mapped back in. The mode chosen
+    /// by the CGB boot ROM through KEY0 takes effect. In DMG mode CGB
+    /// registers are gone, and shades are drawn with the color palettes
+    /// it loaded.
+    fn unmap_boot_rom(&mut self) {
+        if self.boot_rom.len() == SIZE_CGB_BOOT_ROM && self.key0 & KEY0_DMG_MODE != 0 {
+            self.cart.is_cgb = false;
+            self.ppu.fetcher.is_cgb = false;
+            self.ppu.cgb_dmg_mode = true;
+        }
+        self.boot_rom = Vec::new();
+    }
+
+    fn is_boot_rom_addr(&self, addr: usize) -> bool {
+        match self.boot_rom.len() {
+            SIZE_DMG_BOOT_ROM => ADDR_BOOT_ROM0.contains(&addr),
+            SIZE_CGB_BOOT_ROM => in_ranges!(addr, ADDR_BOOT_ROM0, ADDR_BOOT_ROM1),
+            _ => false,
+        }
+    }
+
     /// Set IF register by ORing bits of `iflag` in.
     pub(crate) fn add_interrupt(&mut self, iflag: IntData) {
         let val = self.iflag.read() | iflag.read();
         self.iflag.write(val);
     }
 
-    /// Update joypad buttons and Joypad/P1 register.
+    /// Set buttons of a joypad, `player` must be less than `SGB_PLAYERS`.
+    pub(crate) fn set_buttons(&mut self, player: usize, dpad: DPad, btns: ActionButtons) {
+        self.pads[player] = (dpad, btns);
+        self.update_joypad();
+    }
+
+    /// Update Joypad/P1 register from buttons of the current joypad.
     /// Also, raise Joypad interrupt condition is met.
-    pub(crate) fn update_joypad(&mut self, dpad: DPad, btns: ActionButtons) {
+    fn update_joypad(&mut self) {
+        let (dpad, btns) = self.pads[self.sgb.player()];
         let mut new_state = mask(4); // In Joypad 0-bit means pressed.
 
         if self.joypad.select_dpad == 0 {
@@ -379,6 +619,11 @@ impl Mmu {
         if self.joypad.select_buttons == 0 {
             new_state &= !btns.read();
         }
+        // With multiple joypads, the current joypad ID is read instead.
+        if self.joypad.select_dpad == 1 && self.joypad.select_buttons == 1 && self.sgb.players() > 1
+        {
+            new_state -= self.sgb.player() as u8;
+        }
 
         // Interrupt only when any of the lower 4-bits of Joypad falls.
         if (self.joypad.state & !new_state) & mask(4) != 0 {
@@ -389,8 +634,12 @@ impl Mmu {
         }
 
         self.joypad.state = new_state;
-        self.dpad = dpad;
-        self.buttons = btns;
+    }
+
+    /// True if any of the selected joypad input lines is low, that is,
+    /// a selected button is pressed.
+    pub(crate) fn is_joypad_line_low(&self) -> bool {
+        self.joypad.state != mask(4)
     }
 
     /// Get `IF & IE` as `IntData`.
@@ -404,9 +653,9 @@ impl Mmu {
 
     fn start_dma(&mut self, addr: u8) {
         // DMA address specifies the high-byte value of the 16-bit
-        // source address. Valid values for it are from 0x00 to 0xDF.
-        // If it is overflowing we just wrap around it.
-        let src = ((addr as usize) % 0xDF) << 4;
+        // source address. Valid values for it are from 0x00 to 0xDF,
+        // others are handled by `dma_read`.
+        let src = (addr as usize) << 8;
 
         // Src is from $XX00 to $XX9F.
         self.oam_dma = Some(OamDma {
@@ -418,6 +667,65 @@ impl Mmu {
         self.dma = addr;
     }
 
+    /// Start a VRAM DMA of `(val & 0x7F) + 1` blocks. If bit 7 is set a
+    /// block is copied each HBlank, otherwise all of them are copied at once.
+    /// Writing with bit 7 reset during an HBlank DMA stops it instead.
+    fn start_vram_dma(&mut self, val: u8) {
+        let dma = &mut self.vram_dma;
+        if dma.hblank && val & 0x80 == 0 {
+            dma.hblank = false;
+            return;
+        }
+
+        dma.blocks_left = val & 0x7F;
+        dma.hblank = val & 0x80 != 0;
+        if !dma.hblank {
+            for _ in 0..=dma.blocks_left {
+                self.vram_dma_step();
+            }
+        }
+    }
+
+    /// Copy a block for VRAM DMA and stall the CPU for it.
+    fn vram_dma_step(&mut self) {
+        let VramDma { src, dst, .. } = self.vram_dma;
+        for i in 0..16 {
+            let val = self.read(src.wrapping_add(i));
+            let addr = (dst + i) as usize & (SIZE_VRAM_BANK - 1);
+            self.ppu.fetcher.vram[self.vram_idx][addr] = val;
+        }
+
+        // A block takes 32 dots, the same time in both speeds.
+        self.dma_stall += if self.is_2x { 16 } else { 8 };
+
+        let dma = &mut self.vram_dma;
+        dma.src = src.wrapping_add(16);
+        dma.dst = (dst + 16) & 0x1FF0;
+        if dma.blocks_left == 0 {
+            *dma = VramDma {
+                blocks_left: 0x7F,
+                hblank: false,
+                ..*dma
+            };
+        } else {
+            dma.blocks_left -= 1;
+        }
+    }
+
+    /// Read a byte as seen by the OAM DMA unit.
+    fn dma_read(&self, src: usize) -> u8 {
+        // DMA cannot access OAM, IO registers or HRAM. Sources from 0xE000
+        // upwards are decoded by the WRAM which ignores the upper address
+        // bits, so those read WRAM just like echo RAM does.
+        // Cartridge reads behave as usual, that is, reading disabled
+        // external RAM gives 0xFF.
+        if src >= *ADDR_ECHO_RAM.start() {
+            self.read(wram_addr(src) as u16)
+        } else {
+            self.read(src as u16)
+        }
+    }
+
     // Utility methods
     //---------------------------------------------------------------
     /// Checks if memroy region is accesible by CPU, when DMA ongoing.
@@ -437,7 +745,7 @@ impl Mmu {
         let is_wram_addr = |v| in_ranges!(v, ADDR_WRAM0, ADDR_WRAM1);
         // But for CGB, HRAM and either Cartridge or WRAM, whichever
         // is not a DMA source is also accesible.
-        self.is_2x
+        self.cart.is_cgb
             && ((is_cart_addr(addr) != is_cart_addr(src))
                 || (is_wram_addr(addr) != is_wram_addr(src)))
     }
@@ -452,24 +760,36 @@ impl Default for Mmu {
             ppu: Ppu::new(),
             timer: Timer::new(),
             serial: Serial::new(),
+            sgb: Sgb::new(),
 
-            wram: [[0; SIZE_WRAM_BANK]; WRAM_BANKS],
+            wram: Box::new([[0; SIZE_WRAM_BANK]; WRAM_BANKS]),
             hram: [0; SIZE_HRAM],
             ienable: Default::default(),
             iflag: Default::default(),
+            key0: 0,
             key1: Default::default(),
             joypad: Default::default(),
             bgpi: Default::default(),
             obpi: Default::default(),
             wram_idx: 1,
             vram_idx: 0,
-            opri: 0,
+            boot_rom: Vec::new(),
             dma: 0,
             rp: Rp::new(0b10),
 
-            dpad: Default::default(),
-            buttons: Default::default(),
+            pads: Default::default(),
             oam_dma: None,
+            vram_dma: Default::default(),
+            dma_stall: 0,
+
+            overclock: false,
+            overclock_carry: 0,
+            errors: Vec::new(),
+            errors_taken: 0,
+            freezes: Vec::new(),
+            cheats: Vec::new(),
+            ir_source: IrSource::Dark,
+            dots: 0,
         }
     }
 }
@@ -479,13 +799,232 @@ fn is_cart_addr(addr: usize) -> bool {
     in_ranges!(addr, ADDR_ROM0, ADDR_ROM1, ADDR_EXT_RAM)
 }
 
-/// Get ECHO RAM addres which is mapped to WRAM masked by 13-bits.
+/// Check if it is a register which only exists in CGB mode.
 #[inline]
-fn get_echo_ram_addr(rel_addr: usize) -> usize {
-    (rel_addr & ECHO_RAM_ADDR_MASK) + *ADDR_WRAM0.start()
+fn is_cgb_reg(addr: usize) -> bool {
+    matches!(
+        addr,
+        IO_KEY0 | IO_KEY1 | IO_VBK | IO_HDMA1..=IO_HDMA5 | IO_RP | IO_BGPI..=IO_OPRI | IO_SVBK
+    )
+}
+
+/// Map addresses in mirrored regions to the memory they mirror, others are
+/// returned as is. Reads and writes go through it before being dispatched,
+/// so the mirrored regions need no handling of their own.
+///
+/// Echo RAM, 0xE000-0xFDFF, only has the lower 13-bits connected and
+/// mirrors 0xC000-0xDDFF, which is WRAM0 and the selected WRAM1 bank.
+#[inline]
+fn unmirror(addr: usize) -> usize {
+    if ADDR_ECHO_RAM.contains(&addr) {
+        wram_addr(addr)
+    } else {
+        addr
+    }
+}
+
+/// Get the WRAM address for `addr` as WRAM decodes it, from the lower
+/// 13-bits only.
+#[inline]
+fn wram_addr(addr: usize) -> usize {
+    (addr & ECHO_RAM_ADDR_MASK) + *ADDR_WRAM0.start()
 }
 
 #[inline(always)]
 const fn mask(bit_cnt: u32) -> u8 {
     u8::MAX >> (8 - bit_cnt)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MMU with a 32KiB MBC1 cartridge having 8KiB of RAM, its ROM bytes
+    /// are their address XORed with the bank.
+    fn mmu() -> Mmu {
+        let mut rom: Vec<u8> = (0..0x8000).map(|a| (a ^ (a >> 8)) as u8).collect();
+        rom[CART_TYPE] = 0x03;
+        rom[CART_ROM_SIZE] = 0x00;
+        rom[CART_RAM_SIZE] = 0x02;
+        Mmu::new(Cartidge::new(&rom).unwrap())
+    }
+
+    /// Same as `mmu`, but the cartridge is for CGB.
+    fn cgb_mmu() -> Mmu {
+        let mut mmu = mmu();
+        mmu.cart.is_cgb = true;
+        mmu
+    }
+
+    /// Fill 0xA0 bytes from `page`, value for each is given by `val`.
+    fn fill(mmu: &mut Mmu, page: u16, val: impl Fn(u16) -> u8) {
+        for i in 0..0xA0 {
+            mmu.write(page + i, val(i));
+        }
+    }
+
+    /// Run an OAM DMA from `page` to its end and get OAM.
+    fn oam_dma(mmu: &mut Mmu, page: u8) -> Vec<u8> {
+        mmu.write(IO_DMA as u16, page);
+        mmu.tick(ADDR_OAM.count() as u16);
+        assert!(mmu.oam_dma.is_none());
+        mmu.ppu.oam.to_vec()
+    }
+
+    fn expected(mmu: &Mmu, page: u16) -> Vec<u8> {
+        (0..0xA0).map(|i| mmu.read(page + i)).collect()
+    }
+
+    #[test]
+    fn dma_from_rom() {
+        let mut mmu = mmu();
+        for page in [0x00, 0x01, 0x40, 0x7F] {
+            let want = expected(&mmu, (page as u16) << 8);
+            assert_eq!(oam_dma(&mut mmu, page), want);
+        }
+    }
+
+    #[test]
+    fn dma_from_vram() {
+        let mut mmu = mmu();
+        fill(&mut mmu, 0x8100, |i| i as u8 ^ 0x5A);
+        let want: Vec<u8> = (0..0xA0).map(|i| i as u8 ^ 0x5A).collect();
+        assert_eq!(oam_dma(&mut mmu, 0x81), want);
+    }
+
+    #[test]
+    fn dma_from_ext_ram() {
+        let mut mmu = mmu();
+        // Disabled RAM reads as 0xFF.
+        assert_eq!(oam_dma(&mut mmu, 0xA0), [0xFF; 0xA0]);
+
+        mmu.write(0x0000, 0x0A);
+        fill(&mut mmu, 0xA000, |i| 0xA0 ^ i as u8);
+        let want: Vec<u8> = (0..0xA0).map(|i| 0xA0 ^ i as u8).collect();
+        assert_eq!(oam_dma(&mut mmu, 0xA0), want);
+    }
+
+    #[test]
+    fn dma_from_wram() {
+        let mut mmu = mmu();
+        fill(&mut mmu, 0xC000, |i| i as u8);
+        fill(&mut mmu, 0xD000, |i| !i as u8);
+        let wram0: Vec<u8> = (0..0xA0).map(|i| i as u8).collect();
+        let wram1: Vec<u8> = (0..0xA0).map(|i| !i as u8).collect();
+
+        assert_eq!(oam_dma(&mut mmu, 0xC0), wram0);
+        assert_eq!(oam_dma(&mut mmu, 0xD0), wram1);
+        // Echo RAM mirrors WRAM.
+        assert_eq!(oam_dma(&mut mmu, 0xE0), wram0);
+        assert_eq!(oam_dma(&mut mmu, 0xF0), wram1);
+    }
+
+    #[test]
+    fn dma_from_oam_and_io_pages_reads_wram() {
+        let mut mmu = mmu();
+        fill(&mut mmu, 0xDE00, |i| 0x11 ^ i as u8);
+        fill(&mut mmu, 0xDF00, |i| 0x22 ^ i as u8);
+        // HRAM and registers must not be read.
+        mmu.write(0xFF80, 0x99);
+
+        let de: Vec<u8> = (0..0xA0).map(|i| 0x11 ^ i as u8).collect();
+        let df: Vec<u8> = (0..0xA0).map(|i| 0x22 ^ i as u8).collect();
+        assert_eq!(oam_dma(&mut mmu, 0xFE), de);
+        assert_eq!(oam_dma(&mut mmu, 0xFF), df);
+    }
+
+    #[test]
+    fn unmirror_maps_echo_ram_to_wram() {
+        for addr in ADDR_ECHO_RAM {
+            assert_eq!(unmirror(addr), addr - 0x2000);
+        }
+        for addr in [
+            0x0000, 0xA000, 0xC000, 0xDE00, 0xDFFF, 0xFE00, 0xFF00, 0xFFFF,
+        ] {
+            assert_eq!(unmirror(addr), addr);
+        }
+    }
+
+    #[test]
+    fn echo_ram_reads_and_writes_wram() {
+        let mut mmu = mmu();
+        for addr in ADDR_ECHO_RAM.map(|a| a as u16) {
+            let wram = addr - 0x2000;
+            mmu.write(addr, addr as u8 ^ 0x3C);
+            assert_eq!(mmu.read(wram), addr as u8 ^ 0x3C);
+            mmu.write(wram, !addr as u8);
+            assert_eq!(mmu.read(addr), !addr as u8);
+        }
+    }
+
+    #[test]
+    fn echo_ram_follows_wram1_bank() {
+        let mut mmu = cgb_mmu();
+        for bank in 1..8 {
+            mmu.write(IO_SVBK as u16, bank);
+            mmu.write(0xF000, bank * 0x11);
+            mmu.write(0xDDFF, bank * 0x22);
+        }
+
+        for bank in (1..8).rev() {
+            mmu.write(IO_SVBK as u16, bank);
+            assert_eq!(mmu.read(0xD000), bank * 0x11);
+            assert_eq!(mmu.read(0xF000), bank * 0x11);
+            assert_eq!(mmu.read(0xFDFF), bank * 0x22);
+        }
+        // Bank 0 selects bank 1.
+        mmu.write(IO_SVBK as u16, 0);
+        assert_eq!(mmu.read(0xF000), 0x11);
+
+        // WRAM0 is not banked.
+        mmu.write(0xE123, 0x42);
+        mmu.write(IO_SVBK as u16, 5);
+        assert_eq!(mmu.read(0xC123), 0x42);
+        assert_eq!(mmu.read(0xE123), 0x42);
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_each_hblank() {
+        let mut mmu = cgb_mmu();
+        mmu.write(IO_LCDC as u16, 0x91);
+        for addr in 0xC000..0xC100 {
+            mmu.write(addr, addr as u8 ^ 0x77);
+        }
+        // Start at the beginning of a scan-line, in OAM scan.
+        mmu.tick(PPU_HSCAN_DOTS / 4);
+        assert_eq!(mmu.get_mode(), MODE_SCAN);
+
+        mmu.write(IO_HDMA1 as u16, 0xC0);
+        mmu.write(IO_HDMA2 as u16, 0x00);
+        mmu.write(IO_HDMA3 as u16, 0x00);
+        mmu.write(IO_HDMA4 as u16, 0x00);
+        mmu.write(IO_HDMA5 as u16, 0x80 | 7);
+        // Three HBlanks are entered at once, like when the CPU is stalled.
+        mmu.tick(3 * PPU_HSCAN_DOTS / 4);
+
+        assert_eq!(mmu.read(IO_HDMA5 as u16) & 0x7F, 4);
+        for addr in 0..0x40 {
+            let want = if addr < 0x30 { addr as u8 ^ 0x77 } else { 0 };
+            assert_eq!(mmu.ppu.fetcher.vram[0][addr], want, "at {:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn general_dma_stall_spans_scan_lines() {
+        let mut mmu = cgb_mmu();
+        mmu.write(IO_LCDC as u16, 0x91);
+        // Copy the longest block count from ROM, it stalls for 1024 M-cycles.
+        mmu.write(IO_HDMA1 as u16, 0x00);
+        mmu.write(IO_HDMA2 as u16, 0x00);
+        mmu.write(IO_HDMA3 as u16, 0x00);
+        mmu.write(IO_HDMA4 as u16, 0x00);
+        mmu.write(IO_HDMA5 as u16, 0x7F);
+        assert_eq!(mmu.dma_stall, 1024);
+
+        // Stalls are ticked at once, over two full scan-lines at a time.
+        for _ in 0..PPU_FRAME_DOTS / 4 / 1024 + 1 {
+            mmu.tick(1024);
+        }
+        assert_eq!(mmu.ppu.fetcher.vram[0][0x7FF], mmu.read(0x7FF));
+    }
+}
@@ -1,26 +1,409 @@
-use crate::{frame, regs};
+use std::time::Duration;
+
+use std::fmt;
+
+use crate::{cheats::Cheat, frame, ir::IrSource, regs, scale::ScaleFilter, EmuError};
 
 pub enum UserMsg {
     Buttons(ButtonState),
+    /// See `Emulator::set_player_buttons`, `Buttons` is the same as
+    /// setting them for the first player.
+    PlayerButtons(usize, ButtonState),
+    /// See `Emulator::set_tilt`.
+    UpdateTilt(f32, f32),
+    /// See `Emulator::clear_frame`.
     ClearFrame(frame::Color),
     GetFrame,
+    /// Same as `GetFrame` if the last completed frame has a sequence number
+    /// greater than the given one, otherwise replies `NoNewFrame` at once.
+    /// See `FrameInfo::sequence`.
+    GetFrameIfNew(u64),
     GetFrequency,
+    /// Get time spent since the last time it was requested.
+    GetFrameTiming,
+    /// Get recently dispatched interrupts, oldest first.
+    GetInterruptLog,
+    /// See `Emulator::video_memory`.
+    GetVideoMemory,
+    /// See `Emulator::lcdc_state`.
+    GetLcdcState,
+    /// See `Emulator::set_lcdc_override`.
+    SetLcdcOverride(LcdcOverride),
+    /// See `Emulator::line_objects`.
+    GetLineObjects(u8),
+    /// See `Emulator::set_object_mask`.
+    SetObjectMask(u64),
+    /// Sample the values at the watched addresses once per frame and
+    /// send them as `EmulatorMsg::WatchValues`, none stops it.
+    SetWatches(Vec<Watch>),
+    /// Stop or resume running the machine, messages are still handled
+    /// while paused and frames are sent as soon as requested.
+    /// Pausing takes effect once the frame being drawn is completed, so
+    /// the frame sent while paused is always a whole one.
+    SetPaused(bool),
+    /// Run a single frame if paused, for frame-by-frame advance.
+    StepFrame,
+    /// See `Emulator::set_uncapped_sprites`.
+    SetUncappedSprites(bool),
+    /// See `Emulator::set_cpu_overclock`.
+    SetCpuOverclock(bool),
+    /// See `Emulator::set_halt_bug`.
+    SetHaltBug(bool),
+    /// See `Emulator::set_break_on_bad_execution`.
+    SetBreakOnBadExecution(bool),
+    /// See `Emulator::set_sgb_joypads`.
+    SetSgbJoypads(bool),
+    /// See `Emulator::set_opposite_directions`.
+    SetOppositeDirections(OppositeDirections),
+    /// See `Emulator::set_serial_console`.
+    SetSerialConsole(bool),
+    /// See `Emulator::set_trace_execution`.
+    SetTraceExecution(bool),
+    /// Upscale frames before sending them, they are sent as
+    /// `EmulatorMsg::NewScaledFrame` instead of `EmulatorMsg::NewFrame`.
+    /// Set to `None` for sending unscaled frames.
+    SetScaleFilter(Option<ScaleFilter>),
+    /// See `Emulator::replace_rom`, errors are logged and the old ROM
+    /// keeps running.
+    ReplaceRom(Vec<u8>, bool),
+    /// See `Emulator::set_memory_freeze`.
+    SetMemoryFreeze(u16, Option<u8>),
+    /// See `Emulator::set_ir_source`.
+    SetIrSource(IrSource),
+    /// See `Emulator::add_cheat`.
+    AddCheat(Cheat),
+    /// See `Emulator::remove_cheat`.
+    RemoveCheat(Cheat),
+    /// Get the cheat codes in effect, replies `Cheats`.
+    ListCheats,
+    /// Save the state to a slot, see `Emulator::save_to_slot`. Replies
+    /// `StateSaved` or `SlotError`, it is handled after the messages sent
+    /// before it, so it saves the state as of then.
+    SaveSlot(String),
+    /// Load the state from a slot, see `Emulator::load_from_slot`.
+    /// Replies `StateLoaded` or `SlotError`.
+    LoadSlot(String),
+    /// See `Emulator::reset`.
+    Reset,
+    /// Record a rewind history of at most the given bytes, 0 disables it.
+    /// The history is kept if it was already enabled.
+    SetRewindBuffer(usize),
+    /// While rewinding it goes back a frame at a time, instead of running.
+    SetRewinding(bool),
     Shutdown,
 
-    // TODO For debugging the CPU and execution.
+    // For debugging the CPU and execution.
+    /// Pause and reply with `EmulatorMsg::Registers`.
     DebuggerStart,
+    /// Execute one instruction if paused and reply with
+    /// `EmulatorMsg::Registers`.
     DebuggerStep,
+    /// Resume running.
     DebuggerStop,
 }
 
 pub enum EmulatorMsg {
     NewFrame(Box<frame::Frame>),
+    NewScaledFrame(Box<frame::ScaledFrame>),
+    /// Reply to `GetFrameIfNew` when no frame was completed since.
+    NoNewFrame,
     Frequency(f64),
+    FrameTiming(FrameTiming),
+    InterruptLog(Vec<InterruptEvent>),
+    Registers(Registers),
+    VideoMemory(Box<VideoMemory>),
+    LcdcState(LcdcState),
+    LineObjects(LineObjects),
+    /// Values of the watches set by `SetWatches`, in the same order,
+    /// sampled in VBlank after each completed frame.
+    WatchValues(Vec<u16>),
+    /// Reply to `ListCheats`, in the order they were added.
+    Cheats(Vec<Cheat>),
+    /// Replies to `SaveSlot` and `LoadSlot` with the slot name.
+    StateSaved(String),
+    StateLoaded(String),
+    /// `SaveSlot` or `LoadSlot` failed, like `EmuError::UnknownSlot` for
+    /// an empty slot or `EmuError::SaveStateVersion` for an old one.
+    SlotError(String, EmuError),
+    /// Sent on its own whenever a problem is found while running, it can
+    /// arrive before the reply to any message.
+    Error(RuntimeError),
+    /// Sent on its own for each line the game printed to the serial
+    /// console, see `Emulator::set_serial_console`.
+    ConsoleLine(String),
+    /// Sent on its own once, see `Emulator::take_link_activity`.
+    LinkActivityDetected,
+    /// Sent on its own when the emulator paused itself, resume it with
+    /// `UserMsg::SetPaused`.
+    Paused,
     ShuttingDown,
     Stop,
     WakeUp,
 }
 
+/// Where the emulator thread spent its time, for diagnosing stutter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTiming {
+    /// Frames completed by the PPU.
+    pub frames: u64,
+    /// Instructions executed by the CPU.
+    pub instructions: u64,
+    /// Scanlines drawn by the PPU.
+    pub lines_drawn: u64,
+    /// Time spent running the machine.
+    pub emulation: Duration,
+    /// Time spent sleeping for staying in sync with the wall clock.
+    pub sleep: Duration,
+}
+
+/// Problems found while running, the machine keeps running after them.
+/// Each distinct problem is reported once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// An opcode with no instruction was executed, it is skipped.
+    IllegalInstruction { pc: u16, opcode: u8 },
+    /// PC went past 0xFFFF and wrapped back to zero.
+    PcOverflow,
+    /// Execution ran past the end of bank 0 into the given switchable bank,
+    /// which is not bank 1. Likely a banking bug.
+    BankFallThrough { bank: usize },
+    /// Execution entered VRAM, OAM or I/O registers at `pc`, which do not
+    /// hold code, coming from the instruction at `from`. `from_bank` is the
+    /// ROM bank mapped at `from` if it is in ROM.
+    BadExecutionAddress {
+        pc: u16,
+        from: u16,
+        from_bank: Option<usize>,
+    },
+    /// The MBC of the cartridge is not emulated, writes to it are ignored.
+    UnsupportedMbc,
+    /// `UserMsg::ReplaceRom` failed, the old ROM keeps running.
+    ReplaceRom(EmuError),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalInstruction { pc, opcode } => {
+                write!(f, "illegal instruction {:02X} at {:04X}", opcode, pc)
+            }
+            Self::PcOverflow => write!(f, "PC overflow, wrapped back to zero"),
+            Self::BankFallThrough { bank } => {
+                write!(
+                    f,
+                    "execution fell through from bank 0 into bank {:02X}",
+                    bank
+                )
+            }
+            Self::BadExecutionAddress {
+                pc,
+                from,
+                from_bank,
+            } => {
+                write!(f, "executing non-code memory at {:04X}, from ", pc)?;
+                match from_bank {
+                    Some(bank) => write!(f, "{:02X}:{:04X}", bank, from),
+                    None => write!(f, "{:04X}", from),
+                }
+            }
+            Self::UnsupportedMbc => write!(f, "cartridge MBC is not supported"),
+            Self::ReplaceRom(e) => write!(f, "cannot replace ROM: {:?}", e),
+        }
+    }
+}
+
+/// Interrupt sources, listed in the order of their priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+/// An interrupt dispatch recorded by the CPU, for debugging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptEvent {
+    pub kind: Interrupt,
+    /// M-cycles elapsed since power on when the interrupt was dispatched.
+    pub mcycle: u64,
+    /// Address of the instruction which was interrupted.
+    pub pc: u16,
+    /// Address of the interrupt service routine.
+    pub vector: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Video memory and registers, for debugging.
+#[derive(Clone)]
+pub struct VideoMemory {
+    /// VRAM banks, the second one is only used in CGB mode.
+    pub vram: [[u8; 0x2000]; 2],
+    pub oam: [u8; 0xA0],
+    /// Drawn in CGB mode, using color palettes and VRAM bank 1 attributes.
+    pub is_cgb: bool,
+    pub lcdc: u8,
+    pub scy: u8,
+    pub scx: u8,
+    /// Monochrome palettes: BGP, OBP0 and OBP1.
+    pub dmg_palettes: [u8; 3],
+    /// Color palettes as of the last completed frame.
+    pub bg_palettes: [[frame::Color; 4]; 8],
+    pub obj_palettes: [[frame::Color; 4]; 8],
+}
+
+/// LCDC bits to force on or off, for experimenting with how games build
+/// their scenes. `None` leaves a bit as the game set it. Games still read
+/// back the value they wrote.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LcdcOverride {
+    /// BG and window enable, or their priority in CGB mode.
+    pub bg_window: Option<bool>,
+    pub objects: Option<bool>,
+    /// Use 8x16 objects instead of 8x8.
+    pub tall_objects: Option<bool>,
+    pub window: Option<bool>,
+}
+
+impl LcdcOverride {
+    /// Get the LCDC value with the bits forced.
+    pub fn apply(self, lcdc: u8) -> u8 {
+        let bits = [
+            (self.bg_window, 0),
+            (self.objects, 1),
+            (self.tall_objects, 2),
+            (self.window, 5),
+        ];
+        bits.into_iter().fold(lcdc, |v, (force, bit)| match force {
+            Some(true) => v | (1 << bit),
+            Some(false) => v & !(1 << bit),
+            None => v,
+        })
+    }
+}
+
+/// A value in memory to sample each frame, like the player's health or
+/// position, see `UserMsg::SetWatches`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watch {
+    pub addr: u16,
+    /// Read two bytes from `addr` as a little-endian value, instead of
+    /// one.
+    pub is_word: bool,
+}
+
+/// Objects on a line as the PPU selects them, for debugging sprite limit
+/// flicker. Objects are given by OAM index.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LineObjects {
+    /// Selected objects in drawing priority order, the first one is drawn
+    /// above the others. It is by X position and then OAM index in DMG
+    /// mode, and by OAM index only in CGB mode.
+    pub selected: Vec<u8>,
+    /// Objects left out due to the limit of 10 objects per line.
+    pub dropped: Vec<u8>,
+    /// Objects left out as they are hidden by the object mask.
+    pub masked: Vec<u8>,
+}
+
+/// How the PPU interprets LCDC, for debugging.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LcdcState {
+    /// As written by the game.
+    pub lcdc: u8,
+    /// As used by the PPU, after the overrides.
+    pub effective: u8,
+    pub overrides: LcdcOverride,
+    // Rest are decoded from the effective value.
+    pub lcd_enabled: bool,
+    /// In CGB mode it is the BG and window priority instead, they are
+    /// always drawn then.
+    pub bg_window_enabled: bool,
+    pub objects_enabled: bool,
+    /// Objects are 8x16 instead of 8x8.
+    pub tall_objects: bool,
+    pub window_enabled: bool,
+    /// Address of the BG tile map, 0x9800 or 0x9C00.
+    pub bg_tile_map: u16,
+    /// Address of the window tile map, 0x9800 or 0x9C00.
+    pub window_tile_map: u16,
+    /// Address of the BG and window tile data, 0x8000 with unsigned tile
+    /// indices or 0x8800 with signed ones.
+    pub tile_data: u16,
+    /// Lines the window was drawn on in the last completed frame, 0 if it
+    /// was not shown.
+    pub window_lines: u8,
+}
+
+impl LcdcState {
+    pub(crate) fn new(lcdc: u8, overrides: LcdcOverride, window_lines: u8) -> Self {
+        let effective = overrides.apply(lcdc);
+        let bit = |i: u8| (effective >> i) & 1 == 1;
+        let map = |i: u8| if bit(i) { 0x9C00 } else { 0x9800 };
+
+        Self {
+            lcdc,
+            effective,
+            overrides,
+            lcd_enabled: bit(7),
+            bg_window_enabled: bit(0),
+            objects_enabled: bit(1),
+            tall_objects: bit(2),
+            window_enabled: bit(5),
+            bg_tile_map: map(3),
+            window_tile_map: map(6),
+            tile_data: if bit(4) { 0x8000 } else { 0x8800 },
+            window_lines,
+        }
+    }
+}
+
+/// CPU registers, for debugging.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    /// Flags: Z, N, H and C from MSB, lower 4-bits are always 0.
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// Interrupt master enable.
+    pub ime: bool,
+    pub is_halted: bool,
+    pub is_stopped: bool,
+}
+
+/// How opposite D-pad directions held together are passed to the game.
+/// A real D-pad cannot press both and some games glitch when they are.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OppositeDirections {
+    /// Pass both, for the glitches, which speedrunners sometimes want.
+    #[default]
+    Allow,
+    /// Release both.
+    Neutral,
+    /// Keep the one pressed last, both are released if they were pressed
+    /// at the same time.
+    LastPressed,
+}
+
 /// A glue type for sending button states from user to emulator.
 #[derive(Default, Clone, Copy, PartialEq, Eq)]
 pub struct ButtonState {
@@ -37,6 +420,96 @@ pub struct ButtonState {
 }
 
 impl ButtonState {
+    /// Make from a bit-mask, where bits from LSB to MSB are:
+    /// A, B, Select, Start, Right, Left, Up and Down.
+    pub fn from_bits(bits: u8) -> Self {
+        let is_set = |i: u8| (bits >> i) & 1 == 1;
+        Self {
+            a: is_set(0),
+            b: is_set(1),
+            select: is_set(2),
+            start: is_set(3),
+            right: is_set(4),
+            left: is_set(5),
+            up: is_set(6),
+            down: is_set(7),
+        }
+    }
+
+    /// Set a button as pressed or released.
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let b = match button {
+            Button::A => &mut self.a,
+            Button::B => &mut self.b,
+            Button::Select => &mut self.select,
+            Button::Start => &mut self.start,
+            Button::Up => &mut self.up,
+            Button::Down => &mut self.down,
+            Button::Left => &mut self.left,
+            Button::Right => &mut self.right,
+        };
+        *b = pressed;
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::A => self.a,
+            Button::B => self.b,
+            Button::Select => self.select,
+            Button::Start => self.start,
+            Button::Up => self.up,
+            Button::Down => self.down,
+            Button::Left => self.left,
+            Button::Right => self.right,
+        }
+    }
+
+    /// Resolve opposite D-pad directions held together by `handling`.
+    /// `last` is the previous state and `last_resolved` what it was
+    /// resolved to, they tell which direction was pressed last.
+    pub(crate) fn resolve_opposites(
+        self,
+        handling: OppositeDirections,
+        last: Self,
+        last_resolved: Self,
+    ) -> Self {
+        // Resolve a pair as (a, b), given what they were before.
+        let resolve = |now: (bool, bool), last: (bool, bool), resolved| {
+            if now != (true, true) {
+                return now;
+            }
+            match handling {
+                OppositeDirections::Allow => now,
+                OppositeDirections::Neutral => (false, false),
+                OppositeDirections::LastPressed => match last {
+                    (true, false) => (false, true),
+                    (false, true) => (true, false),
+                    (true, true) => resolved,
+                    (false, false) => (false, false),
+                },
+            }
+        };
+
+        let mut out = self;
+        (out.left, out.right) = resolve(
+            (self.left, self.right),
+            (last.left, last.right),
+            (last_resolved.left, last_resolved.right),
+        );
+        (out.up, out.down) = resolve(
+            (self.up, self.down),
+            (last.up, last.down),
+            (last_resolved.up, last_resolved.down),
+        );
+        out
+    }
+
+    /// Inverse of `from_bits`.
+    pub fn to_bits(self) -> u8 {
+        let (dpad, btns) = self.to_internal_repr();
+        (dpad.read() << 4) | btns.read()
+    }
+
     pub(crate) fn to_internal_repr(self) -> (regs::DPad, regs::ActionButtons) {
         let dpad = regs::DPad {
             right: self.right as u8,
@@ -1,20 +1,33 @@
 mod fetcher;
 
 use fetcher::{LineFetcher, OamEntry, Pixel};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    frame::{self, Color, Frame},
+    frame::{self, Color, Frame, FrameInfo},
     info::*,
+    msg::LineObjects,
     regs::{CgbPaletteColor, IntData, LcdStat},
+    state::byte_array,
 };
 
+/// Colors the CGB boot ROM gives monochrome games it does not know, in the
+/// CGB color format, from white to black. Objects use the same for both
+/// OBP0 and OBP1.
+const DMG_COMPAT_BG: [u16; 4] = [0x7FFF, 0x1BEF, 0x6180, 0x0000];
+const DMG_COMPAT_OBJ: [u16; 4] = [0x7FFF, 0x421F, 0x1CF2, 0x0000];
+
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct Ppu {
     pub(crate) fetcher: LineFetcher,
 
     // Memory and registers owned by it.
+    #[serde(with = "byte_array")]
     pub(crate) oam: [u8; SIZE_OAM],
     // CGB color palettes are stored in a seperate RAM accesed indirectly.
+    #[serde(with = "byte_array")]
     pub(crate) bg_palette: [u8; SIZE_CGB_PALETTE],
+    #[serde(with = "byte_array")]
     pub(crate) obj_palette: [u8; SIZE_CGB_PALETTE],
     pub(crate) stat: LcdStat,
     pub(crate) ly: u8,
@@ -22,11 +35,37 @@ pub(crate) struct Ppu {
     pub(crate) bgp: u8,
     pub(crate) obp0: u8,
     pub(crate) obp1: u8,
+    /// DMG palette writes made while drawing the current line, as the number
+    /// of pixels pushed before the write and [BGP, OBP0, OBP1] prior to it.
+    palette_writes: Vec<(u8, [u8; 3])>,
+    /// Number of frames completed, incremented on entering VBlank.
+    pub(crate) frame_count: u64,
+    /// Enhancement: draw all objects on a line ignoring `MAX_OBJ_PER_LINE`.
+    #[serde(skip)]
+    pub(crate) uncap_objects: bool,
+    /// Draw monochrome games using the color palettes, like a CGB does.
+    #[serde(skip)]
+    pub(crate) dmg_colors: bool,
+    /// Running in the DMG mode of a CGB, set by its boot ROM. Shades are
+    /// drawn like with `dmg_colors`.
+    pub(crate) cgb_dmg_mode: bool,
+    /// OAM entries hidden for debugging, bit N hides entry N. It is an
+    /// option, so it is not serialized.
+    #[serde(skip)]
+    pub(crate) object_mask: u64,
+    /// Lines drawn, for instrumentation.
+    #[serde(skip)]
+    pub(crate) lines_drawn: u64,
+    /// Lines the window was drawn on in the last completed frame.
+    #[serde(skip)]
+    pub(crate) window_lines: u8,
 
     /// Current PPU mode updates to it are carried to STAT register.
     mode: PpuMode,
     /// Frame containing an RGB-24 representation of the screen pixels.
-    frame: Frame,
+    /// It is only for presentation, so it is not serialized.
+    #[serde(skip)]
+    frame: Box<Frame>,
     /// Amount of dots left, which determines how much to advance.
     /// In normal mode     : 4 dots per M-cycle.
     /// In dual-speed mode : 2 dots per M-cycle.
@@ -35,7 +74,7 @@ pub(crate) struct Ppu {
     dots_in_line: u16,
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
 enum PpuMode {
     HBlank = MODE_HBLANK,
@@ -58,6 +97,14 @@ impl Ppu {
             bgp: 0,
             obp0: 0,
             obp1: 0,
+            palette_writes: Vec::new(),
+            frame_count: 0,
+            uncap_objects: false,
+            dmg_colors: false,
+            cgb_dmg_mode: false,
+            object_mask: 0,
+            lines_drawn: 0,
+            window_lines: 0,
             frame: Default::default(),
             mode: PpuMode::Scan,
             dots_in_line: 0,
@@ -66,7 +113,35 @@ impl Ppu {
     }
 
     pub(crate) fn fill_frame(&self, frame: &mut frame::Frame) {
-        *frame = self.frame.clone();
+        frame.clone_from(&self.frame);
+    }
+
+    pub(crate) fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    pub(crate) fn clear_frame(&mut self, color: Color) {
+        self.frame.fill(color);
+    }
+
+    /// Show the LCD as it looks during STOP. In DMG mode it turns white,
+    /// it is produced as a new frame with the LCD disabled.
+    pub(crate) fn blank_frame(&mut self) {
+        if self.fetcher.is_cgb {
+            return;
+        }
+
+        self.frame_count += 1;
+        for y in 0..SCREEN_RESOLUTION.1 {
+            for x in 0..SCREEN_RESOLUTION.0 {
+                self.frame.set(x, y, mono_to_color(0));
+                self.frame.set_index(x, y, 0);
+            }
+        }
+        self.frame.info = FrameInfo {
+            lcd_enabled: false,
+            ..self.frame_info()
+        };
     }
 
     /// Run for `dots` cycles, `dots` must be an even number.
@@ -77,7 +152,7 @@ impl Ppu {
             return IntData::new(0);
         }
 
-        assert!(dots % 2 == 0);
+        assert!(dots.is_multiple_of(2));
         self.dots_left += dots;
         let mut ret = IntData::default();
 
@@ -97,10 +172,26 @@ impl Ppu {
     }
 
     fn reset(&mut self) {
+        // Frames are not produced while disabled, so update it here.
+        self.frame.info.lcd_enabled = false;
         self.stat.ppu_mode = MODE_HBLANK;
         self.ly = 0;
         self.dots_in_line = 0;
         self.mode = PpuMode::Scan;
+        self.palette_writes.clear();
+    }
+
+    /// Write a DMG palette register, 0 is BGP, 1 is OBP0 and 2 is OBP1.
+    /// A write while drawing only affects the pixels pushed after it.
+    pub(crate) fn write_dmg_palette(&mut self, reg: usize, val: u8) {
+        let mut palettes = [self.bgp, self.obp0, self.obp1];
+        if self.mode == PpuMode::Draw {
+            let pushed = self.fetcher.screen_line.len() as u8;
+            self.palette_writes.push((pushed, palettes));
+        }
+
+        palettes[reg] = val;
+        [self.bgp, self.obp0, self.obp1] = palettes;
     }
 
     fn step_scan(&mut self) -> PpuMode {
@@ -115,18 +206,11 @@ impl Ppu {
         self.eat_dots(2);
         let obj = get_oam_entry(&self.oam, idx);
 
-        // If the spte buffer is not full, then a sprite is added to it if:
-        // It is on the scan-line as per its Y-pos and objects are enabled.
-        // "Ypos - 16" is sprite top position on screen.
-        // A sprite can have size: 8x8 or 8x16(tall object mode).
-        let height = if self.fetcher.lcdc.obj_size == 1 {
-            16
-        } else {
-            8
-        };
-        if self.fetcher.objects.len() < MAX_OBJ_PER_LINE
-            && obj.ypos <= self.ly + 16
-            && self.ly + 16 < obj.ypos + height
+        // If the spte buffer is not full, then a sprite is added to it if
+        // it is on the scan-line as per its Y-pos. Masked ones are skipped.
+        if self.fetcher.objects.len() < self.max_objects()
+            && self.is_on_line(obj, self.ly)
+            && !self.is_masked(idx)
         {
             self.fetcher.objects.push(obj);
         }
@@ -134,17 +218,83 @@ impl Ppu {
         PpuMode::Scan
     }
 
+    /// Get the objects selected for line `ly` from the current OAM, in
+    /// the same way as scanning OAM does.
+    pub(crate) fn line_objects(&self, ly: u8) -> LineObjects {
+        let mut objs = LineObjects::default();
+        let mut selected = Vec::new();
+
+        for idx in 0..OAM_ENTRIES {
+            let obj = get_oam_entry(&self.oam, idx);
+            if !self.is_on_line(obj, ly) {
+                continue;
+            }
+
+            if self.is_masked(idx) {
+                objs.masked.push(idx as u8);
+            } else if selected.len() < self.max_objects() {
+                selected.push((idx as u8, obj.xpos));
+            } else {
+                objs.dropped.push(idx as u8);
+            }
+        }
+
+        // Sorting is stable, so equal X positions stay in OAM order.
+        if self.fetcher.opri == 1 {
+            selected.sort_by_key(|&(_, xpos)| xpos);
+        }
+        objs.selected = selected.into_iter().map(|(idx, _)| idx).collect();
+        objs
+    }
+
+    /// Check if `obj` lies on line `ly`, "Ypos - 16" is its top position
+    /// on screen. Objects are 8x8 or 8x16(tall object mode).
+    fn is_on_line(&self, obj: OamEntry, ly: u8) -> bool {
+        let height = if self.fetcher.lcdc().obj_size == 1 {
+            16
+        } else {
+            8
+        };
+        let (top, y) = (obj.ypos as u16, ly as u16 + 16);
+        top <= y && y < top + height
+    }
+
+    fn is_masked(&self, idx: usize) -> bool {
+        (self.object_mask >> idx) & 1 == 1
+    }
+
+    fn max_objects(&self) -> usize {
+        if self.uncap_objects {
+            OAM_ENTRIES
+        } else {
+            MAX_OBJ_PER_LINE
+        }
+    }
+
     fn step_draw(&mut self) -> PpuMode {
         self.eat_dots(2);
         self.fetcher.tick_2_dots();
 
         if self.fetcher.is_done() {
             // Copy all pixel colors to frame if done.
+            // Each pixel uses the DMG palettes as they were when it was pushed,
+            // that is, the ones prior to the first write made after it.
+            let writes = std::mem::take(&mut self.palette_writes);
+            let mut next = 0;
             for i in 0..SCREEN_RESOLUTION.0 {
+                while next < writes.len() && writes[next].0 as usize <= i {
+                    next += 1;
+                }
+                let palettes = writes
+                    .get(next)
+                    .map_or([self.bgp, self.obp0, self.obp1], |w| w.1);
+
                 let px = self.fetcher.screen_line[i];
-                let color = self.pixel_to_color(px);
+                let color = self.pixel_to_color(px, palettes);
                 self.frame.set(i, self.ly as usize, color);
+                self.frame.set_index(i, self.ly as usize, px.frame_index());
             }
+            self.lines_drawn += 1;
 
             PpuMode::HBlank
         } else {
@@ -156,7 +306,8 @@ impl Ppu {
         // TODO goto Scan directly if reset detected??
         // If current scan-line finishes and it was last draw line then
         // goto VBlank, if not last line then just go back to OAM-Scan mode.
-        if self.eat_dots(self.dots_left) {
+        // VRAM DMA can stall the CPU for more than a scan-line.
+        if self.eat_dots(self.dots_left.min(PPU_HSCAN_DOTS)) {
             if self.ly == PPU_DRAW_LINES {
                 PpuMode::VBlank
             } else {
@@ -168,7 +319,7 @@ impl Ppu {
     }
 
     fn step_vblank(&mut self) -> PpuMode {
-        self.eat_dots(self.dots_left);
+        self.eat_dots(self.dots_left.min(PPU_HSCAN_DOTS));
 
         if self.ly == PPU_DRAW_LINES + PPU_VBLANK_LINES {
             self.dots_in_line = 0;
@@ -192,6 +343,11 @@ impl Ppu {
         // If mode changes and interrupt condition is met then interrupt.
         if new_mode != self.mode {
             iflag.vblank = matches!(new_mode, PpuMode::VBlank) as u8;
+            if iflag.vblank == 1 {
+                self.frame_count += 1;
+                self.frame.info = self.frame_info();
+                self.window_lines = self.fetcher.window_lines();
+            }
             iflag.stat = match self.mode {
                 PpuMode::HBlank if self.stat.mode0 == 1 => 1,
                 PpuMode::VBlank if self.stat.mode1 == 1 => 1,
@@ -226,34 +382,75 @@ impl Ppu {
         }
     }
 
+    /// Information about the current rendering state.
+    fn frame_info(&self) -> FrameInfo {
+        let mut info = FrameInfo {
+            sequence: self.frame_count,
+            is_cgb: self.fetcher.is_cgb,
+            lcd_enabled: self.fetcher.lcdc.ppu_enable == 1,
+            ..Default::default()
+        };
+
+        if info.is_cgb {
+            for i in 0..8 {
+                info.bg_palettes[i] = self.read_cgb_palette(false, i as u8).map(cgb_to_color);
+                info.obj_palettes[i] = self.read_cgb_palette(true, i as u8).map(cgb_to_color);
+            }
+        } else {
+            info.dmg_palettes = [self.bgp, self.obp0, self.obp1];
+        }
+
+        info
+    }
+
     // Pixel to color synthesis stuff
     //---------------------------------------------------------------
-    fn pixel_to_color(&self, px: Pixel) -> Color {
+    fn pixel_to_color(&self, px: Pixel, dmg_palettes: [u8; 3]) -> Color {
         // In non-CGB mode palette is taken from BGP/OBP0/OBP1 registers,
         // where colors are stored according to color IDs as: [MSB] 33-22-11-00 [LSB]
-        let mono_color = |palette, color_id| (palette >> color_id * 2) & 0b11;
+        let mono_color = |palette, color_id| (palette >> (color_id * 2)) & 0b11;
 
-        if self.fetcher.is_2x {
+        if self.fetcher.is_cgb {
             // Transparent[color=0] object pixels have already been
             // handeled by the fetcher during pixel mixing.
             let palette = self.read_cgb_palette(px.is_obj, px.palette);
             cgb_to_color(palette[px.color_id as usize])
         } else {
+            let [bgp, obp0, obp1] = dmg_palettes;
             let palette = if px.is_obj {
                 if px.palette == 0 {
-                    self.obp0
+                    obp0
                 } else {
-                    self.obp1
+                    obp1
                 }
             } else {
-                self.bgp
+                bgp
             };
 
             let color = mono_color(palette, px.color_id);
-            mono_to_color(color)
+            if self.dmg_colors || self.cgb_dmg_mode {
+                // Shades index the first BG or the first two OBJ palettes.
+                let palette = self.read_cgb_palette(px.is_obj, px.is_obj as u8 * px.palette);
+                cgb_to_color(palette[color as usize])
+            } else {
+                mono_to_color(color)
+            }
         }
     }
 
+    /// Load the palettes which the CGB boot ROM uses for monochrome games
+    /// not in its list of known titles, see `dmg_colors`.
+    pub(crate) fn load_dmg_compat_palettes(&mut self) {
+        let load = |ram: &mut [u8], colors: [u16; 4]| {
+            for (dst, c) in ram.chunks_exact_mut(2).zip(colors) {
+                dst.copy_from_slice(&c.to_le_bytes());
+            }
+        };
+        load(&mut self.bg_palette[..8], DMG_COMPAT_BG);
+        load(&mut self.obj_palette[..8], DMG_COMPAT_OBJ);
+        load(&mut self.obj_palette[8..16], DMG_COMPAT_OBJ);
+    }
+
     fn read_cgb_palette(&self, is_obj: bool, pal_index: u8) -> [u16; 4] {
         let mut ret = [0u16; 4];
 
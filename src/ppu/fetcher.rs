@@ -1,6 +1,10 @@
 use std::{cmp::max, collections::VecDeque};
 
-use crate::{info::*, macros::bit_fields, regs::LcdCtrl};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame, info::*, macros::bit_fields, msg::LcdcOverride, regs::LcdCtrl, state::byte_array,
+};
 
 type VramArray = [[u8; SIZE_VRAM_BANK]; VRAM_BANKS];
 
@@ -8,25 +12,35 @@ type VramArray = [[u8; SIZE_VRAM_BANK]; VRAM_BANKS];
 /// Put scanned OAM objects in `objects` sorted by OAM index.
 /// Use `is_done` to check if line has been constructed and get the
 /// pixels from `screen_line`.
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct LineFetcher {
-    /// Objects(sprites) which lie on the current scan line. Max 10.
+    /// Objects(sprites) which lie on the current scan line.
+    /// Max 10, unless the sprite limit has been lifted.
     /// Objects which come first in OAM should be placed first.
     // For drawing priority following rules are followed:
-    // In non-CGB sort by first X-position and then OAM index.
-    // In CGB mode sort by OAM index only. In case of a overlap with other
+    // If OPRI is 1 sort by first X-position and then OAM index.
+    // If OPRI is 0 sort by OAM index only. In case of a overlap with other
     // objects the one which lies earlier in list this is drawn at the top.
     pub(crate) objects: Vec<OamEntry>,
     /// Containing pixels for the currently being drawn line.
     pub(crate) screen_line: Vec<Pixel>,
-    pub(crate) is_2x: bool,
+    /// CGB mode, which uses BG map attributes, VRAM bank 1 and color
+    /// palettes. It is set for games supporting CGB.
+    pub(crate) is_cgb: bool,
 
     // Registers and memory owned by it.
-    pub(crate) vram: VramArray,
+    #[serde(with = "byte_array")]
+    pub(crate) vram: Box<VramArray>,
+    /// As written, use `lcdc()` for the one with overrides applied.
     pub(crate) lcdc: LcdCtrl,
+    #[serde(skip)]
+    pub(crate) lcdc_override: LcdcOverride,
     pub(crate) scx: u8,
     pub(crate) scy: u8,
     pub(crate) wx: u8,
     pub(crate) wy: u8,
+    /// Object priority mode(OPRI), 0 for CGB and 1 for DMG style.
+    pub(crate) opri: u8,
 
     /// Pixel FIFO, it should always contain at least 8-pixels for mixing.
     fifo: VecDeque<Pixel>,
@@ -53,7 +67,7 @@ pub(crate) struct LineFetcher {
 }
 
 /// One processed pixel with information for constructing its color.
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 pub(crate) struct Pixel {
     /// 2-bit color index into palette.
     pub(crate) color_id: u8,
@@ -67,10 +81,18 @@ pub(crate) struct Pixel {
     bg_priority: u8,
 }
 
+impl Pixel {
+    /// Index of the pixel as `Frame::index` gives it.
+    pub(crate) fn frame_index(self) -> u8 {
+        let obj = if self.is_obj { frame::INDEX_OBJ } else { 0 };
+        obj | self.palette << 2 | self.color_id
+    }
+}
+
 // Representation:
 // Byte-0: Y-position, Byte-1: X-posiiton, Byte-2: Tile-index
 // Byte-3: See OamAttrs.
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
 pub(crate) struct OamEntry {
     /// Object vertical position on screen + 16.
     pub(crate) ypos: u8,
@@ -96,21 +118,23 @@ impl OamEntry {
 impl LineFetcher {
     pub(crate) fn new() -> Self {
         Self {
-            is_2x: false,
+            is_cgb: false,
             fifo: VecDeque::with_capacity(16),
             state: FetcherState::GetTileId,
             objects: Vec::with_capacity(10),
             screen_line: Vec::with_capacity(SCREEN_RESOLUTION.0),
-            vram: [[0; SIZE_VRAM_BANK]; VRAM_BANKS],
+            vram: Box::new([[0; SIZE_VRAM_BANK]; VRAM_BANKS]),
             scx: 0,
             scy: 0,
             draw_x: 0,
             line: 0,
             wx: 0,
             wy: 0,
+            opri: 0,
             win_y: 0,
             fetch_x: 0,
             lcdc: Default::default(),
+            lcdc_override: Default::default(),
             tile_extra_pixels: 0,
             window: None,
             object: None,
@@ -153,6 +177,20 @@ impl LineFetcher {
         };
     }
 
+    /// Get LCDC as used for drawing, with the overrides applied.
+    pub(crate) fn lcdc(&self) -> LcdCtrl {
+        if self.lcdc_override == LcdcOverride::default() {
+            self.lcdc
+        } else {
+            LcdCtrl::new(self.lcdc_override.apply(self.lcdc.read()))
+        }
+    }
+
+    /// Number of lines the window was drawn on so far in this frame.
+    pub(crate) fn window_lines(&self) -> u8 {
+        self.win_y + self.window.is_some() as u8
+    }
+
     /// Initialize for fetching pixels for a new line and set LY.
     /// If Line 0 then, start a new frame.
     /// Call before starting a new line(OAM scan mode).
@@ -177,9 +215,8 @@ impl LineFetcher {
         self.tile_extra_pixels = self.scx % 8;
         self.state = FetcherState::GetTileId;
 
-        assert!(self.objects.len() <= MAX_OBJ_PER_LINE);
-        if !self.is_2x {
-            self.objects.sort_by(|a, b| a.xpos.cmp(&b.xpos));
+        if self.opri == 1 {
+            self.objects.sort_by_key(|a| a.xpos);
         }
     }
 
@@ -203,7 +240,7 @@ impl LineFetcher {
             )
         };
 
-        self.tile = read_tile_info(self.is_2x, &self.vram, tile_map, tx, y / 8);
+        self.tile = read_tile_info(self.is_cgb, &self.vram, tile_map, tx, y / 8);
         self.tile.line = y % 8;
 
         FetcherState::GetTileLow
@@ -211,12 +248,12 @@ impl LineFetcher {
 
     fn fetch_tile_id_obj(&mut self) -> FetcherState {
         let obj = self.object.unwrap();
-        self.tile = tile_info_from_obj(self.is_2x, obj);
+        self.tile = tile_info_from_obj(self.is_cgb, obj);
 
         // Tall objects are comprised of two consecutive tiles.
         // Upper part has even numbered tile-ID.
         // When yflip is enabled the two tiles switch positions.
-        if self.lcdc.obj_size == 1 {
+        if self.lcdc().obj_size == 1 {
             let is_second = self.line + 16 - obj.ypos > 8;
             self.tile.id = if is_second == self.tile.yflip {
                 self.tile.id & !1
@@ -240,7 +277,7 @@ impl LineFetcher {
         let addr_mode = if self.object.is_some() {
             1 // Objects always follow 1 addressing-mode.
         } else {
-            self.lcdc.bg_win_tile_data
+            self.lcdc().bg_win_tile_data
         };
 
         (self.tile.low, self.tile.high) = read_tile_line(
@@ -266,7 +303,7 @@ impl LineFetcher {
         // In non-CGB mode lcdc 0-bit controls bg/window enable.
         // If diabled display blank color, that is 0.
         for i in 0..8 {
-            let color = if !self.is_2x && self.lcdc.bg_win_priotity == 0 {
+            let color = if !self.is_cgb && self.lcdc().bg_win_priotity == 0 {
                 0
             } else {
                 tile_color_id(self.tile.low, self.tile.high, i)
@@ -290,10 +327,10 @@ impl LineFetcher {
 
         // Clip parts of the which are off-screen to the left.
         // obj.xpos is object's real X-position + 8.
-        let xclip_start = if obj.xpos < 8 { 8 - obj.xpos } else { 0 };
+        let xclip_start = 8_u8.saturating_sub(obj.xpos);
         for x in xclip_start..8 {
             let old_idx = (x - xclip_start) as usize;
-            let px = self.mix_obj_pixel(self.is_2x, self.fifo[old_idx], x);
+            let px = self.mix_obj_pixel(self.is_cgb, self.fifo[old_idx], x);
             self.fifo[old_idx] = px;
         }
 
@@ -335,7 +372,7 @@ impl LineFetcher {
 
         // If window detected then discard fetched BG-pixel
         // and start fetching window tiles for this line.
-        if self.window.is_none() && self.lcdc.win_enable == 1 {
+        if self.window.is_none() && self.lcdc().win_enable == 1 {
             // Windows top-left position is (wx=7, wy=0).
             if self.wx <= self.draw_x + 7 && self.wy <= self.line {
                 // WX being less than 7 causes abnormal behaviour,
@@ -349,7 +386,7 @@ impl LineFetcher {
 
         // If any object at current position then restart the fetch cycle
         // and fetch the object tile-line and attributes for pixel mixing.
-        if self.object.is_none() && self.lcdc.obj_enable == 1 {
+        if self.object.is_none() && self.lcdc().obj_enable == 1 {
             self.object = self.pop_obj_at(self.draw_x);
 
             if self.object.is_some() {
@@ -378,9 +415,9 @@ impl LineFetcher {
     /// Get which tile-map to use for BG/Window.
     fn get_tile_map_num(&self) -> u8 {
         if self.window.is_some() {
-            self.lcdc.win_tile_map
+            self.lcdc().win_tile_map
         } else {
-            self.lcdc.bg_tile_map
+            self.lcdc().bg_tile_map
         }
     }
 
@@ -399,7 +436,7 @@ impl LineFetcher {
 
         // FIXME Fix object overlaid over BG/Window wrongly.
         // Color 0 for objects is transparent.
-        if px.color_id != 0 && is_obj_priority(is_cgb, self.lcdc, old, obj) {
+        if px.color_id != 0 && is_obj_priority(is_cgb, self.lcdc(), old, obj) {
             px
         } else {
             old
@@ -436,7 +473,7 @@ bit_fields! {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 enum FetcherState {
     #[default]
     GetTileId,
@@ -445,7 +482,7 @@ enum FetcherState {
     PushPixels,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct TileLine {
     id: u8,
     low: u8,
@@ -506,16 +543,17 @@ fn read_tile_line(
 }
 
 /// Read tile infomation from given tile-position and map number.
-fn read_tile_info(is_2x: bool, vram: &VramArray, tile_map: u8, tx: u8, ty: u8) -> TileLine {
+fn read_tile_info(is_cgb: bool, vram: &VramArray, tile_map: u8, tx: u8, ty: u8) -> TileLine {
     // Tile map is in Bank 0 VRAM and attributes in Bank 1 of VRAM.
     let addr = tile_id_vram_addr(tile_map, tx, ty);
     let id = vram[0][addr];
     // If in non-CGB mode disable attributes to emulate the same.
-    let attrs = BgMapAttr::new(if is_2x { vram[1][addr] } else { 0 });
+    let attrs = BgMapAttr::new(if is_cgb { vram[1][addr] } else { 0 });
 
     TileLine {
         id,
         bank: attrs.bank,
+        palette: attrs.palette,
         xflip: attrs.xflip == 1,
         yflip: attrs.yflip == 1,
         priority: attrs.priority,
@@ -0,0 +1,56 @@
+//! Python bindings, built as the `gbemu` extension module with the
+//! `python` feature enabled.
+//!
+//! The framebuffer is exposed as RGB-24 bytes which can be viewed as a numpy
+//! array without copying: `np.frombuffer(obs, np.uint8).reshape(144, 160, 3)`.
+
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+use crate::{ButtonState, Emulator, SCREEN_SIZE};
+
+#[pyclass(name = "Emulator", module = "gbemu")]
+struct PyEmulator {
+    emu: Emulator,
+}
+
+#[pymethods]
+impl PyEmulator {
+    #[classattr]
+    const WIDTH: usize = SCREEN_SIZE.0;
+    #[classattr]
+    const HEIGHT: usize = SCREEN_SIZE.1;
+
+    #[new]
+    fn new(rom: &[u8]) -> PyResult<Self> {
+        Emulator::new(rom)
+            .map(|emu| Self { emu })
+            .map_err(|e| PyValueError::new_err(format!("{e:?}")))
+    }
+
+    /// Hold the buttons in `action` for `frames` frames and return the last
+    /// frame. Action bits from LSB to MSB are:
+    /// A, B, Select, Start, Right, Left, Up and Down.
+    #[pyo3(signature = (action, frames = 1))]
+    fn step<'py>(&mut self, py: Python<'py>, action: u8, frames: u32) -> Bound<'py, PyBytes> {
+        let btns = ButtonState::from_bits(action);
+        for _ in 0..frames {
+            self.emu.step_frame(btns);
+        }
+        self.frame(py)
+    }
+
+    /// Get the last completed frame as RGB-24 bytes.
+    fn frame<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.emu.frame().to_rgb24())
+    }
+
+    /// Get the last completed frame as palette indices, 1 byte per pixel.
+    fn frame_indexed<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.emu.frame().to_indexed())
+    }
+}
+
+#[pymodule]
+fn gbemu(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEmulator>()
+}
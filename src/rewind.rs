@@ -0,0 +1,317 @@
+//! History of recent machine states for rewinding.
+//!
+//! States are kept in groups, each group starts with a full keyframe state
+//! and the rest are stored as deltas against it. A delta is the state XORed
+//! with the keyframe and then run-length encoded. States of nearby frames
+//! differ in a small part of memory, so deltas are mostly runs of zeros and
+//! compress well.
+//!
+//! Delta layout: state length, then pairs of zero-run length and literal
+//! length followed by that many literal bytes, all lengths as LEB128.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{state, EmuError, Emulator};
+
+/// Number of states in a group, including the keyframe.
+const GROUP_LEN: usize = 60;
+/// Shortest run of zeros which ends a literal run, shorter ones are cheaper
+/// to store as literals than to start a new pair.
+const MIN_ZERO_RUN: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+struct Group {
+    key: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+    /// Total bytes used by the keyframe and deltas.
+    size: usize,
+}
+
+/// A bounded history of states, when full the oldest ones are dropped.
+pub struct RewindBuffer {
+    groups: VecDeque<Group>,
+    size: usize,
+    max_size: usize,
+}
+
+impl RewindBuffer {
+    /// Create a buffer using about `max_size` bytes at most.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            groups: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    /// Save the current state of `emu` as the latest one.
+    pub fn push(&mut self, emu: &Emulator) {
+        self.push_state(emu.machine_state());
+    }
+
+    /// Restore `emu` to the latest state and remove it from the history.
+    /// Returns false if there is none. Options set on `emu` are kept.
+    ///
+    /// The frame is not a part of the state, step a frame to redraw it.
+    pub fn pop(&mut self, emu: &mut Emulator) -> Result<bool, EmuError> {
+        match self.pop_state() {
+            Some(state) => {
+                emu.restore_machine_state(&state?)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Number of states in the history.
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|g| 1 + g.deltas.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Bytes used by the stored states.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Change the size limit, dropping the oldest states if over it.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.drop_excess();
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.size = 0;
+    }
+
+    pub(crate) fn push_state(&mut self, state: Vec<u8>) {
+        match self.groups.back_mut() {
+            Some(g) if 1 + g.deltas.len() < GROUP_LEN => {
+                let delta = encode_delta(&g.key, &state);
+                g.size += delta.len();
+                self.size += delta.len();
+                g.deltas.push(delta);
+            }
+            _ => {
+                self.size += state.len();
+                self.groups.push_back(Group {
+                    size: state.len(),
+                    key: state,
+                    deltas: Vec::new(),
+                });
+            }
+        }
+
+        self.drop_excess();
+    }
+
+    fn drop_excess(&mut self) {
+        // Deltas depend on their keyframe, so drop whole groups.
+        // The latest group is always kept, even if it does not fit.
+        while self.size > self.max_size && self.groups.len() > 1 {
+            let g = self.groups.pop_front().unwrap();
+            self.size -= g.size;
+        }
+    }
+
+    /// Encode the latest `count` states, or all if there are fewer, for
+    /// keeping them in a save-state. Whole groups are encoded, so a few
+    /// more may be included.
+    pub(crate) fn encode_latest(&self, count: usize) -> Vec<u8> {
+        let mut n = 0;
+        let skip = self
+            .groups
+            .iter()
+            .rev()
+            .take_while(|g| {
+                let take = n < count;
+                n += 1 + g.deltas.len();
+                take
+            })
+            .count();
+        let groups: Vec<&Group> = self.groups.iter().skip(self.groups.len() - skip).collect();
+        state::to_bytes(&groups)
+    }
+
+    /// Decode states made by `encode_latest`, the size limit is set to the
+    /// size of them. Returns `None` if any of the deltas is damaged.
+    pub(crate) fn decode(data: &[u8]) -> Option<Self> {
+        let groups: VecDeque<Group> = state::from_bytes(data)?;
+        let is_valid = |g: &Group| g.deltas.iter().all(|d| decode_delta(&g.key, d).is_ok());
+        if !groups.iter().all(is_valid) {
+            return None;
+        }
+
+        let size = groups.iter().map(|g| g.size).sum();
+        Some(Self {
+            groups,
+            size,
+            max_size: size,
+        })
+    }
+
+    pub(crate) fn pop_state(&mut self) -> Option<Result<Vec<u8>, EmuError>> {
+        let g = self.groups.back_mut()?;
+
+        if let Some(delta) = g.deltas.pop() {
+            g.size -= delta.len();
+            self.size -= delta.len();
+            return Some(decode_delta(&g.key, &delta));
+        }
+
+        let g = self.groups.pop_back().unwrap();
+        self.size -= g.size;
+        Some(Ok(g.key))
+    }
+}
+
+/// Encode `state` as a delta against `key`, they can differ in length.
+/// Bytes past the end of `key` are taken as zero.
+fn encode_delta(key: &[u8], state: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| state[i] ^ key.get(i).copied().unwrap_or(0);
+    let is_zero_run = |i: usize| (i..state.len().min(i + MIN_ZERO_RUN)).all(|j| xor(j) == 0);
+
+    let mut out = Vec::new();
+    write_len(&mut out, state.len());
+
+    let mut i = 0;
+    while i < state.len() {
+        let zeros_start = i;
+        while i < state.len() && xor(i) == 0 {
+            i += 1;
+        }
+
+        let lits_start = i;
+        while i < state.len() && !is_zero_run(i) {
+            i += 1;
+        }
+
+        write_len(&mut out, lits_start - zeros_start);
+        write_len(&mut out, i - lits_start);
+        out.extend((lits_start..i).map(xor));
+    }
+
+    out
+}
+
+/// Decode a delta made by `encode_delta` with the same `key`.
+/// Fails if it is truncated or its runs do not add up to its length.
+fn decode_delta(key: &[u8], delta: &[u8]) -> Result<Vec<u8>, EmuError> {
+    let mut src = delta.iter().copied();
+    let len = read_len(&mut src)?;
+    let mut state = Vec::new();
+    let key_at = |i: usize| key.get(i).copied().unwrap_or(0);
+
+    while state.len() < len {
+        let zeros = read_len(&mut src)?;
+        let lits = read_len(&mut src)?;
+        let end = state.len().saturating_add(zeros).saturating_add(lits);
+        if end == state.len() || end > len {
+            return Err(EmuError::InvalidSaveState);
+        }
+
+        for _ in 0..zeros {
+            state.push(key_at(state.len()));
+        }
+        for _ in 0..lits {
+            let b = src.next().ok_or(EmuError::InvalidSaveState)?;
+            state.push(b ^ key_at(state.len()));
+        }
+    }
+
+    match src.next() {
+        Some(_) => Err(EmuError::InvalidSaveState),
+        None => Ok(state),
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_len(src: &mut impl Iterator<Item = u8>) -> Result<usize, EmuError> {
+    let mut n = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let b = src.next().ok_or(EmuError::InvalidSaveState)?;
+        n |= (b as usize & 0x7F) << shift;
+        if b < 0x80 {
+            return Ok(n);
+        }
+    }
+    Err(EmuError::InvalidSaveState)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(key: &[u8], state: &[u8]) {
+        let delta = encode_delta(key, state);
+        assert_eq!(decode_delta(key, &delta).unwrap(), state);
+    }
+
+    #[test]
+    fn delta_of_empty_state() {
+        round_trip(&[], &[]);
+        round_trip(&[1, 2, 3], &[]);
+        assert_eq!(encode_delta(&[1, 2, 3], &[]), [0]);
+    }
+
+    #[test]
+    fn delta_of_identical_state() {
+        let key: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        round_trip(&key, &key);
+        // Only the length and a single run of zeros.
+        assert_eq!(encode_delta(&key, &key).len(), 5);
+    }
+
+    #[test]
+    fn delta_of_different_state() {
+        let key: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let state: Vec<u8> = key.iter().map(|b| !b).collect();
+        round_trip(&key, &state);
+
+        let mut state = key.clone();
+        state[0] ^= 1;
+        state[500..503].fill(0xAA);
+        state[999] ^= 0x80;
+        round_trip(&key, &state);
+    }
+
+    #[test]
+    fn delta_of_state_with_other_length() {
+        let key: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        round_trip(&key, &key[..100]);
+        round_trip(&key[..100], &key);
+        round_trip(&[], &key);
+    }
+
+    #[test]
+    fn damaged_delta_is_rejected() {
+        let key: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let state: Vec<u8> = key.iter().map(|b| b.wrapping_mul(3)).collect();
+        let delta = encode_delta(&key, &state);
+
+        for len in 0..delta.len() {
+            assert!(decode_delta(&key, &delta[..len]).is_err());
+        }
+        let mut extra = delta.clone();
+        extra.push(0);
+        assert!(decode_delta(&key, &extra).is_err());
+        // Runs which are empty or go past the length.
+        assert!(decode_delta(&key, &[4, 0, 0]).is_err());
+        assert!(decode_delta(&key, &[4, 5, 0]).is_err());
+        // Length which does not fit in a usize.
+        assert!(decode_delta(&key, &[0xFF; 12]).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Identifying ROMs using a user-provided DAT file, in the Logiqx XML
+//! format which No-Intro and others publish. ROMs are looked up by their
+//! CRC-32 and size, giving the proper title, revision and dump status.
+
+use std::collections::HashMap;
+
+use crate::hash::crc32;
+
+/// A ROM listed in a DAT file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatEntry {
+    /// Full name with tags, like `Tetris (World) (Rev 1)`.
+    pub name: String,
+    pub size: usize,
+    pub crc32: u32,
+    /// Known to be a bad dump, it may not work.
+    pub bad_dump: bool,
+    /// Verified to be a good dump.
+    pub verified: bool,
+}
+
+impl DatEntry {
+    /// Get the title without the tags in parentheses.
+    pub fn title(&self) -> &str {
+        self.name.split(" (").next().unwrap_or_default()
+    }
+
+    /// Get the revision from a `(Rev N)` tag, `None` for a first release.
+    pub fn revision(&self) -> Option<&str> {
+        self.tags().find_map(|t| t.strip_prefix("Rev "))
+    }
+
+    /// Iterate over the tags in parentheses, like `World` and `Rev 1`.
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.name
+            .split('(')
+            .skip(1)
+            .filter_map(|t| t.split(')').next())
+    }
+}
+
+/// ROMs of a DAT file, indexed by CRC-32.
+#[derive(Debug, Default, Clone)]
+pub struct RomDb {
+    entries: HashMap<u32, Vec<DatEntry>>,
+}
+
+impl RomDb {
+    /// Parse a Logiqx XML DAT file. Games without a ROM having a valid
+    /// size and CRC are skipped, the rest of the file is still used.
+    pub fn from_dat(text: &str) -> Self {
+        let mut db = Self::default();
+
+        for game in text.split("<game ").skip(1) {
+            let game = game.split("</game>").next().unwrap_or_default();
+            let Some(name) = attr(game, "name") else {
+                continue;
+            };
+
+            for rom in game.split("<rom ").skip(1) {
+                let tag = rom.split('>').next().unwrap_or_default();
+                let size = attr(tag, "size").and_then(|s| s.parse().ok());
+                let crc = attr(tag, "crc").and_then(|c| u32::from_str_radix(&c, 16).ok());
+                let (Some(size), Some(crc32)) = (size, crc) else {
+                    continue;
+                };
+
+                let status = attr(tag, "status").unwrap_or_default();
+                db.entries.entry(crc32).or_default().push(DatEntry {
+                    name: name.clone(),
+                    size,
+                    crc32,
+                    bad_dump: status == "baddump",
+                    verified: status == "verified",
+                });
+            }
+        }
+
+        db
+    }
+
+    /// Number of ROMs listed.
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Find the entry for `rom`, `None` if it is not listed.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&DatEntry> {
+        self.entries
+            .get(&crc32(rom))?
+            .iter()
+            .find(|e| e.size == rom.len())
+    }
+}
+
+/// Get the value of attribute `key` in an XML tag, with entities decoded.
+fn attr(tag: &str, key: &str) -> Option<String> {
+    let start = tag.match_indices(key).map(|(i, _)| i).find(|&i| {
+        let before = tag[..i].chars().next_back();
+        before.is_none_or(char::is_whitespace) && tag[i + key.len()..].starts_with("=\"")
+    })? + key.len()
+        + 2;
+    let len = tag[start..].find('"')?;
+
+    Some(
+        tag[start..start + len]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
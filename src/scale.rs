@@ -0,0 +1,123 @@
+//! Software upscaling filters for frontends without shader support.
+//! Nearest-neighbor ones only repeat pixels, for frontends which cannot
+//! even afford scaling on their own.
+
+use crate::frame::{Color, Frame, ScaledFrame, SCREEN_SIZE};
+
+/// Pixel-art upscaling filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleFilter {
+    /// EPX/Scale2x, doubles the size.
+    Scale2x,
+    /// AdvMAME Scale3x, triples the size.
+    Scale3x,
+    /// Nearest-neighbor, doubles the size.
+    Nearest2x,
+    /// Nearest-neighbor, triples the size.
+    Nearest3x,
+}
+
+impl ScaleFilter {
+    pub fn factor(self) -> usize {
+        match self {
+            ScaleFilter::Scale2x | ScaleFilter::Nearest2x => 2,
+            ScaleFilter::Scale3x | ScaleFilter::Nearest3x => 3,
+        }
+    }
+}
+
+/// Upscale `frame` using `filter`.
+pub(crate) fn scale(frame: &Frame, filter: ScaleFilter) -> ScaledFrame {
+    let n = filter.factor();
+    let (w, h) = SCREEN_SIZE;
+    let mut out = ScaledFrame::new(w * n, h * n, frame.info());
+
+    if matches!(filter, ScaleFilter::Nearest2x | ScaleFilter::Nearest3x) {
+        for y in 0..h * n {
+            for x in 0..w * n {
+                out.set(x, y, frame.get(x / n, y / n));
+            }
+        }
+        return out;
+    }
+
+    // Get pixel at an offset from (x, y), clamped to the frame edges.
+    let at = |x: usize, y: usize, dx: isize, dy: isize| {
+        let x = x.saturating_add_signed(dx).min(w - 1);
+        let y = y.saturating_add_signed(dy).min(h - 1);
+        frame.get(x, y)
+    };
+
+    for y in 0..h {
+        for x in 0..w {
+            // Neighbourhood of E as:
+            // A B C
+            // D E F
+            // G H I
+            let [a, b, c] = [-1, 0, 1].map(|dx| at(x, y, dx, -1));
+            let [d, e, f] = [-1, 0, 1].map(|dx| at(x, y, dx, 0));
+            let [g, h, i] = [-1, 0, 1].map(|dx| at(x, y, dx, 1));
+
+            let block = match filter {
+                ScaleFilter::Scale2x => scale2x(b, d, e, f, h).to_vec(),
+                ScaleFilter::Scale3x => scale3x([a, b, c, d, e, f, g, h, i]).to_vec(),
+                ScaleFilter::Nearest2x | ScaleFilter::Nearest3x => unreachable!(),
+            };
+
+            for (k, &color) in block.iter().enumerate() {
+                out.set(x * n + k % n, y * n + k / n, color);
+            }
+        }
+    }
+
+    out
+}
+
+/// Returns 2x2 block in row-major order.
+fn scale2x(b: Color, d: Color, e: Color, f: Color, h: Color) -> [Color; 4] {
+    if b == h || d == f {
+        return [e; 4];
+    }
+
+    [
+        if d == b { d } else { e },
+        if b == f { f } else { e },
+        if d == h { d } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+/// Takes 3x3 neighbourhood and returns 3x3 block, both in row-major order.
+fn scale3x([a, b, c, d, e, f, g, h, i]: [Color; 9]) -> [Color; 9] {
+    if b == h || d == f {
+        return [e; 9];
+    }
+
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        },
+        e,
+        if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        },
+        if h == f { f } else { e },
+    ]
+}
@@ -1,6 +1,13 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
 use crate::regs::SerialCtrl;
 
-#[derive(Default)]
+/// At most these many lines are kept by the console until taken.
+const MAX_CONSOLE_LINES: usize = 256;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Serial {
     pub(crate) is_2x: bool,
 
@@ -13,6 +20,46 @@ pub(crate) struct Serial {
     period: u16,
     bits_done: u16,
     transferring: bool,
+    /// Reads bytes sent by the game as text, if enabled. It is an option,
+    /// so it is not serialized.
+    #[serde(skip)]
+    pub(crate) console: Option<Console>,
+    /// A transfer was started with the internal clock, as the master, and
+    /// there is no peer. Set once, as `link_reported` is set on taking it.
+    #[serde(skip)]
+    link_requested: bool,
+    #[serde(skip)]
+    pub(crate) link_reported: bool,
+}
+
+/// A virtual terminal on the link port, for printf-style debugging of
+/// homebrew. Bytes are printable ASCII, a newline ends a line, backspace
+/// erases the last character of the line, and the rest are ignored.
+#[derive(Default, Clone)]
+pub(crate) struct Console {
+    line: String,
+    lines: VecDeque<String>,
+}
+
+impl Console {
+    fn put(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                if self.lines.len() == MAX_CONSOLE_LINES {
+                    self.lines.pop_front();
+                }
+                self.lines.push_back(std::mem::take(&mut self.line));
+            }
+            0x08 => _ = self.line.pop(),
+            b'\t' | 0x20..=0x7E => self.line.push(byte as char),
+            _ => (),
+        }
+    }
+
+    /// Take the lines completed since the last call.
+    pub(crate) fn take_lines(&mut self) -> Vec<String> {
+        self.lines.drain(..).collect()
+    }
 }
 
 impl Serial {
@@ -20,6 +67,14 @@ impl Serial {
         Self::default()
     }
 
+    /// Check if the game tried to talk to a peer since the last call,
+    /// true only the first time.
+    pub(crate) fn take_link_request(&mut self) -> bool {
+        let requested = std::mem::take(&mut self.link_requested);
+        self.link_reported |= requested;
+        requested
+    }
+
     pub(crate) fn tick(&mut self, mcycles: u16, is_cgb_cart: bool) -> bool {
         if self.sc.tx_enable == 0 {
             return false;
@@ -27,6 +82,12 @@ impl Serial {
 
         // Start a new transfer if enabled from the next cycle.
         if !self.transferring {
+            if let Some(console) = &mut self.console {
+                console.put(self.sb);
+            } else if self.sc.clock_select == 1 && !self.link_reported {
+                self.link_requested = true;
+            }
+
             // There is no external clock as this is an emulator, use a default.
             self.period = if self.sc.clock_select == 0 {
                 1
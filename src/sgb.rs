@@ -0,0 +1,114 @@
+//! Super Game Boy command packets, which are sent by pulsing P14 and P15
+//! bits of the Joypad register. Only the multiplayer request(MLT_REQ) is
+//! acted upon, other commands are received and ignored.
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of joypads which can be connected.
+pub(crate) const SGB_PLAYERS: usize = 4;
+
+const PACKET_SIZE: usize = 16;
+const PACKET_BITS: u8 = PACKET_SIZE as u8 * 8;
+const CMD_MLT_REQ: u8 = 0x11;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Sgb {
+    /// Respond to SGB packets, it is an option, so it is not serialized.
+    #[serde(skip)]
+    pub(crate) enabled: bool,
+
+    /// Number of joypads that games can read, it is one of 1, 2 or 4.
+    players: u8,
+    /// Joypad that is currently read through the Joypad register.
+    player: u8,
+
+    /// Last written P14 and P15 bits, as bits 0 and 1.
+    select: u8,
+    packet: [u8; PACKET_SIZE],
+    bits_done: u8,
+    transferring: bool,
+    /// Continuation packets of a multi-packet command to be skipped.
+    packets_left: u8,
+}
+
+impl Sgb {
+    pub(crate) fn new() -> Self {
+        Self {
+            players: 1,
+            select: 0b11,
+            ..Default::default()
+        }
+    }
+
+    /// Number of joypads that games can read.
+    pub(crate) fn players(&self) -> u8 {
+        self.players
+    }
+
+    /// Joypad that is currently read, starting from 0.
+    pub(crate) fn player(&self) -> usize {
+        self.player as usize
+    }
+
+    /// Handle a write to the select bits(P14 and P15) of Joypad register.
+    ///
+    /// Both bits low resets and starts a packet, then each bit is sent by
+    /// pulling P14(for 0) or P15(for 1) low, followed by both high.
+    /// A packet is 128 bits, followed by a 0 stop bit.
+    /// When not transferring, setting both high selects the next joypad.
+    pub(crate) fn write_select(&mut self, select: u8) {
+        let prev = self.select;
+        self.select = select;
+        if !self.enabled || prev == select {
+            return;
+        }
+
+        match select {
+            0b00 => {
+                self.transferring = true;
+                self.bits_done = 0;
+                self.packet = [0; PACKET_SIZE];
+            }
+
+            0b01 | 0b10 if self.transferring && prev == 0b11 => {
+                if self.bits_done < PACKET_BITS {
+                    let bit = (select == 0b01) as u8;
+                    let i = self.bits_done as usize;
+                    self.packet[i / 8] |= bit << (i % 8);
+                } else if self.bits_done == PACKET_BITS {
+                    self.packet_done();
+                }
+                self.bits_done += 1;
+            }
+
+            // Transfer ends when the stop bit pulse ends.
+            0b11 if self.transferring => {
+                self.transferring = self.bits_done <= PACKET_BITS;
+            }
+
+            0b11 => self.player = (self.player + 1) % self.players,
+
+            _ => (),
+        }
+    }
+
+    fn packet_done(&mut self) {
+        if self.packets_left > 0 {
+            self.packets_left -= 1;
+            return;
+        }
+
+        // Header is command in upper 5-bits and packet count in lower 3-bits.
+        let cmd = self.packet[0] >> 3;
+        self.packets_left = (self.packet[0] & 0b111).saturating_sub(1);
+
+        if cmd == CMD_MLT_REQ {
+            self.players = match self.packet[1] & 0b11 {
+                0b01 => 2,
+                0b11 => 4,
+                _ => 1,
+            };
+            self.player = 0;
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Multiple named save-states of a game, kept in a single file.
+//! The ROM is stored once for all of them, as all slots are of one game.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{state, EmuError, Emulator};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveSlot {
+    name: String,
+    timestamp: u64,
+    /// Encoded machine state, without the ROM.
+    machine: Vec<u8>,
+}
+
+impl SaveSlot {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Seconds since the UNIX epoch when it was saved.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+/// Save-slots, kept in the order they were first saved.
+#[derive(Default, Clone)]
+pub struct SaveSlots {
+    rom: Vec<u8>,
+    slots: Vec<SaveSlot>,
+}
+
+impl SaveSlots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a save-slot file made by `to_bytes`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, EmuError> {
+        let (rom, slots) = state::load_slots(data)?;
+        Ok(Self { rom, slots })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        state::save_slots(&self.rom, &self.slots)
+    }
+
+    /// Check if `data` looks like a save-slot file.
+    pub fn is_save_slots(data: &[u8]) -> bool {
+        state::is_save_slots(data)
+    }
+
+    pub fn slots(&self) -> &[SaveSlot] {
+        &self.slots
+    }
+
+    /// Save the state to slot `name`, replacing it if it exists.
+    /// All slots must be of the same ROM.
+    pub fn save(&mut self, name: &str, emu: &Emulator) -> Result<(), EmuError> {
+        if self.slots.is_empty() {
+            self.rom = emu.rom().to_vec();
+        } else if self.rom != emu.rom() {
+            return Err(EmuError::RomMismatch);
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let slot = SaveSlot {
+            name: name.to_string(),
+            timestamp,
+            machine: emu.machine_state(),
+        };
+
+        match self.slots.iter_mut().find(|s| s.name == name) {
+            Some(s) => *s = slot,
+            None => self.slots.push(slot),
+        }
+        Ok(())
+    }
+
+    /// Resume from the state saved in slot `name`.
+    pub fn load(&self, name: &str) -> Result<Emulator, EmuError> {
+        Emulator::from_machine_state(self.machine_state(name)?, &self.rom)
+    }
+
+    /// Get the ROM all slots are of, it is empty if there are no slots.
+    pub(crate) fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Get the machine state saved in slot `name`.
+    pub(crate) fn machine_state(&self, name: &str) -> Result<&[u8], EmuError> {
+        self.slots
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.machine.as_slice())
+            .ok_or(EmuError::UnknownSlot)
+    }
+
+    /// Remove slot `name`, returns false if there was no such slot.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.slots.len();
+        self.slots.retain(|s| s.name != name);
+        self.slots.len() != len
+    }
+}
@@ -0,0 +1,246 @@
+//! Machine state serialization.
+//!
+//! All of the state is reachable from `Cpu` and is serialized with bincode,
+//! fields are encoded in declaration order, which gives a canonical byte
+//! representation of the state. Presentation buffers like the frame and the
+//! cartridge ROM are not part of it.
+//!
+//! Save-state files are laid out as: magic, version(u32 LE) and then the
+//! ROM followed by the state, both bincode encoded. The ROM is included so
+//! that a save-state file is enough to resume a game.
+//!
+//! Save-slot files have the same layout with a different magic, followed
+//! by the ROM and then the slots, each slot has its state encoded in it.
+//!
+//! Everything saved, including rewind history, is encoded with `to_bytes`
+//! so that they share one bincode configuration. It is spelled out rather
+//! than relying on the defaults of bincode, which differ between its APIs.
+//! Reordering, adding or removing a serialized field of any component
+//! changes the layout, bump `STATE_VERSION` along with it and make the
+//! fixture in `tests/state_tests.rs` again.
+
+use std::sync::Arc;
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{cpu::Cpu, hash::Fnv64, mem::Mmu, slots::SaveSlot, EmuError};
+
+const STATE_MAGIC: &[u8; 8] = b"GBEMU-ST";
+const SLOTS_MAGIC: &[u8; 8] = b"GBEMU-SL";
+/// Increment it whenever the layout of the state changes.
+const STATE_VERSION: u32 = 12;
+
+/// The bincode configuration: fixed size little-endian integers, and
+/// trailing bytes are an error as they mean the layout is different.
+fn config() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .reject_trailing_bytes()
+}
+
+/// Encode a value to be saved.
+pub(crate) fn to_bytes<T: Serialize + ?Sized>(v: &T) -> Vec<u8> {
+    config()
+        .serialize(v)
+        .expect("saved values are always serializable")
+}
+
+/// Decode a value made by `to_bytes`, `None` if it is invalid.
+pub(crate) fn from_bytes<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+    config().deserialize(data).ok()
+}
+
+/// Encode the state along with the ROM as a save-state file. `history` is
+/// rewind history made by `RewindBuffer::encode_latest`, it may be empty.
+pub(crate) fn save(cpu: &Cpu, history: &[u8]) -> Vec<u8> {
+    let mut out = header(STATE_MAGIC);
+    out.extend(to_bytes(&(cpu.mmu.cart.rom(), cpu, history)));
+    out
+}
+
+/// Decode a save-state file made by `save`, along with its rewind history.
+pub(crate) fn load(data: &[u8]) -> Result<(Cpu, Vec<u8>), EmuError> {
+    let body = strip_header(data, STATE_MAGIC)?;
+    let (rom, mut cpu, history): (Vec<u8>, Cpu, Vec<u8>) =
+        from_bytes(body).ok_or(EmuError::InvalidSaveState)?;
+    cpu.mmu.cart.set_rom(rom.into());
+    Ok((cpu, history))
+}
+
+/// Check if `data` looks like a save-state file.
+pub(crate) fn is_save_state(data: &[u8]) -> bool {
+    data.starts_with(STATE_MAGIC)
+}
+
+/// Encode only the state, without the ROM and the header.
+pub(crate) fn encode(cpu: &Cpu) -> Vec<u8> {
+    to_bytes(cpu)
+}
+
+/// Decode a state made by `encode`, it must be of the current version.
+pub(crate) fn decode(data: &[u8], rom: Arc<[u8]>) -> Result<Cpu, EmuError> {
+    let mut cpu: Cpu = from_bytes(data).ok_or(EmuError::InvalidSaveState)?;
+    cpu.mmu.cart.set_rom(rom);
+    Ok(cpu)
+}
+
+/// Encode save-slots along with the ROM which they belong to.
+pub(crate) fn save_slots(rom: &[u8], slots: &[SaveSlot]) -> Vec<u8> {
+    let mut out = header(SLOTS_MAGIC);
+    out.extend(to_bytes(&(rom, slots)));
+    out
+}
+
+/// Decode a save-slot file made by `save_slots`.
+pub(crate) fn load_slots(data: &[u8]) -> Result<(Vec<u8>, Vec<SaveSlot>), EmuError> {
+    let body = strip_header(data, SLOTS_MAGIC)?;
+    from_bytes(body).ok_or(EmuError::InvalidSaveState)
+}
+
+/// Check if `data` looks like a save-slot file.
+pub(crate) fn is_save_slots(data: &[u8]) -> bool {
+    data.starts_with(SLOTS_MAGIC)
+}
+
+/// Encode each component of the state on its own, for finding which of
+/// them differ between two machines. The CPU and memory exclude the other
+/// components, they are replaced with ones of a fresh machine.
+pub(crate) fn encode_components(cpu: &Cpu) -> Vec<(&'static str, Vec<u8>)> {
+    let mmu = &cpu.mmu;
+    let blank = Mmu::default();
+    let mut memory = mmu.clone();
+    memory.ppu = blank.ppu;
+    memory.timer = blank.timer;
+    memory.serial = blank.serial;
+    memory.cart = blank.cart;
+    memory.sgb = blank.sgb;
+    let mut cpu_only = cpu.clone();
+    cpu_only.mmu = Mmu::default();
+
+    vec![
+        ("cpu", to_bytes(&cpu_only)),
+        ("memory", to_bytes(&memory)),
+        ("ppu", to_bytes(&mmu.ppu)),
+        ("timer", to_bytes(&mmu.timer)),
+        ("serial", to_bytes(&mmu.serial)),
+        ("cartridge", to_bytes(&mmu.cart)),
+        ("sgb", to_bytes(&mmu.sgb)),
+    ]
+}
+
+/// Stable hash of the machine state.
+pub(crate) fn hash(cpu: &Cpu) -> u64 {
+    let mut h = Fnv64::new();
+    h.write(&encode(cpu));
+    h.finish()
+}
+
+fn header(magic: &[u8; 8]) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    out.extend(STATE_VERSION.to_le_bytes());
+    out
+}
+
+/// Check the magic and version, and return rest of the data.
+fn strip_header<'a>(data: &'a [u8], magic: &[u8; 8]) -> Result<&'a [u8], EmuError> {
+    let body = data.strip_prefix(magic).ok_or(EmuError::InvalidSaveState)?;
+    if body.len() < 4 {
+        return Err(EmuError::InvalidSaveState);
+    }
+
+    let (version, body) = body.split_at(4);
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    if version != STATE_VERSION {
+        return Err(EmuError::SaveStateVersion(version));
+    }
+
+    Ok(body)
+}
+
+/// Serde adapter for byte arrays larger than the 32 elements supported by
+/// serde, use it as `#[serde(with = "byte_array")]`.
+pub(crate) mod byte_array {
+    use std::fmt;
+
+    use serde::{
+        de::{self, SeqAccess, Visitor},
+        Deserializer, Serializer,
+    };
+
+    /// A fixed size array which can be viewed as a flat byte slice.
+    pub(crate) trait ByteArray: Sized {
+        fn as_bytes(&self) -> &[u8];
+        fn from_bytes(bytes: &[u8]) -> Option<Self>;
+    }
+
+    impl<const N: usize> ByteArray for [u8; N] {
+        fn as_bytes(&self) -> &[u8] {
+            self
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            bytes.try_into().ok()
+        }
+    }
+
+    impl<const N: usize, const M: usize> ByteArray for [[u8; N]; M] {
+        fn as_bytes(&self) -> &[u8] {
+            self.as_flattened()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            if bytes.len() != N * M {
+                return None;
+            }
+
+            let mut r = [[0; N]; M];
+            r.as_flattened_mut().copy_from_slice(bytes);
+            Some(r)
+        }
+    }
+
+    impl<A: ByteArray> ByteArray for Box<A> {
+        fn as_bytes(&self) -> &[u8] {
+            (**self).as_bytes()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            A::from_bytes(bytes).map(Box::new)
+        }
+    }
+
+    pub(crate) fn serialize<S: Serializer, A: ByteArray>(a: &A, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(a.as_bytes())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>, A: ByteArray>(
+        d: D,
+    ) -> Result<A, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a byte array")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+                let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element()? {
+                    v.push(b);
+                }
+                Ok(v)
+            }
+        }
+
+        let bytes = d.deserialize_bytes(BytesVisitor)?;
+        A::from_bytes(&bytes).ok_or_else(|| de::Error::invalid_length(bytes.len(), &"array size"))
+    }
+}
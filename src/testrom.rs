@@ -0,0 +1,201 @@
+//! Built-in test cartridges, for trying the emulator without a ROM file.
+//!
+//! The test pattern is for checking video latency and frame pacing. It shows an 8 pixels wide black bar on white, which moves right by 2
+//! pixels every frame. Once every 60 frames the colors are inverted for a
+//! single frame. A smoothly moving bar means frames are neither dropped
+//! nor repeated, and the flash gives a reference point for measuring
+//! latency, like against a camera recording.
+//!
+//! The demo, with the `demo` feature, is for checking the controls. The
+//! D-pad moves a black square and the boxes at the top left are filled
+//! while A, B, Select and Start are held, in that order.
+
+use crate::info::{CART_HEADER_CSUM, CART_TITLE, SIZE_ROM_BANK};
+
+const TITLE: &[u8] = b"TEST PATTERN";
+const CODE_START: usize = 0x150;
+
+#[rustfmt::skip]
+const CODE: &[u8] = &[
+    0xF3,                   // di
+    0xF0, 0x44,             // ldh a, [LY]        ; Wait for VBlank
+    0xFE, 0x90,             // cp 144
+    0x20, 0xFA,             // jr nz, -6
+    0xAF,                   // xor a              ; and turn the LCD off.
+    0xE0, 0x40,             // ldh [LCDC], a
+    0x21, 0x00, 0x80,       // ld hl, $8000       ; Clear VRAM.
+    0x01, 0x00, 0x20,       // ld bc, $2000
+    0xAF,                   // xor a
+    0x22,                   // ld [hl+], a
+    0x0B,                   // dec bc
+    0x78,                   // ld a, b
+    0xB1,                   // or c
+    0x20, 0xF9,             // jr nz, -7
+    0x21, 0x10, 0x80,       // ld hl, $8010       ; Tile 1 is all black.
+    0x06, 0x10,             // ld b, 16
+    0x3E, 0xFF,             // ld a, $FF
+    0x22,                   // ld [hl+], a
+    0x05,                   // dec b
+    0x20, 0xFC,             // jr nz, -4
+    0x21, 0x00, 0x98,       // ld hl, $9800       ; First column of the
+    0x11, 0x20, 0x00,       // ld de, 32          ; map is tile 1.
+    0x06, 0x20,             // ld b, 32
+    0x3E, 0x01,             // ld a, 1
+    0x77,                   // ld [hl], a
+    0x19,                   // add hl, de
+    0x05,                   // dec b
+    0x20, 0xFB,             // jr nz, -5
+    0x3E, 0xE4,             // ld a, $E4
+    0xE0, 0x47,             // ldh [BGP], a
+    0x3E, 0x91,             // ld a, $91          ; LCD and BG on.
+    0xE0, 0x40,             // ldh [LCDC], a
+    0x0E, 0x3C,             // ld c, 60           ; Frames till flash.
+    // main:
+    0xF0, 0x44,             // ldh a, [LY]        ; Wait for VBlank.
+    0xFE, 0x90,             // cp 144
+    0x20, 0xFA,             // jr nz, -6
+    0xF0, 0x43,             // ldh a, [SCX]       ; Move the bar.
+    0xD6, 0x02,             // sub 2
+    0xE0, 0x43,             // ldh [SCX], a
+    0x3E, 0xE4,             // ld a, $E4          ; Normal or inverted
+    0x0D,                   // dec c              ; palette.
+    0x20, 0x04,             // jr nz, +4
+    0x3E, 0x1B,             // ld a, $1B
+    0x0E, 0x3C,             // ld c, 60
+    0xE0, 0x47,             // ldh [BGP], a
+    0xF0, 0x44,             // ldh a, [LY]        ; Wait for VBlank to end.
+    0xFE, 0x90,             // cp 144
+    0x28, 0xFA,             // jr z, -6
+    0x18, 0xE1,             // jr main
+];
+
+#[cfg(feature = "demo")]
+const DEMO_TITLE: &[u8] = b"GBEMU DEMO";
+
+#[cfg(feature = "demo")]
+#[rustfmt::skip]
+const DEMO_CODE: &[u8] = &[
+    0xF3,                   // di
+    0xF0, 0x44,             // ldh a, [LY]        ; Wait for VBlank
+    0xFE, 0x90,             // cp 144
+    0x20, 0xFA,             // jr nz, -6
+    0xAF,                   // xor a              ; and turn the LCD off.
+    0xE0, 0x40,             // ldh [LCDC], a
+    0x21, 0x00, 0x80,       // ld hl, $8000       ; Clear VRAM.
+    0x01, 0x00, 0x20,       // ld bc, $2000
+    0xAF,                   // xor a
+    0x22,                   // ld [hl+], a
+    0x0B,                   // dec bc
+    0x78,                   // ld a, b
+    0xB1,                   // or c
+    0x20, 0xF9,             // jr nz, -7
+    0x21, 0x10, 0x80,       // ld hl, $8010       ; Tile 1 is all black.
+    0x06, 0x10,             // ld b, 16
+    0x3E, 0xFF,             // ld a, $FF
+    0x22,                   // ld [hl+], a
+    0x05,                   // dec b
+    0x20, 0xFC,             // jr nz, -4
+    0x22,                   // ld [hl+], a        ; Tile 2 is a black
+    0x22,                   // ld [hl+], a        ; outline.
+    0x06, 0x0C,             // ld b, 12
+    0x3E, 0x81,             // ld a, $81
+    0x22,                   // ld [hl+], a
+    0x05,                   // dec b
+    0x20, 0xFC,             // jr nz, -4
+    0x3E, 0xFF,             // ld a, $FF
+    0x22,                   // ld [hl+], a
+    0x22,                   // ld [hl+], a
+    0x21, 0x00, 0xFE,       // ld hl, $FE00       ; Clear OAM.
+    0x06, 0xA0,             // ld b, $A0
+    0xAF,                   // xor a
+    0x22,                   // ld [hl+], a
+    0x05,                   // dec b
+    0x20, 0xFC,             // jr nz, -4
+    0x21, 0x00, 0xFE,       // ld hl, $FE00       ; Object 0 is tile 1
+    0x3E, 0x58,             // ld a, 88           ; in the middle.
+    0x22,                   // ld [hl+], a
+    0x3E, 0x54,             // ld a, 84
+    0x22,                   // ld [hl+], a
+    0x3E, 0x01,             // ld a, 1
+    0x77,                   // ld [hl], a
+    0x3E, 0xE4,             // ld a, $E4
+    0xE0, 0x47,             // ldh [BGP], a
+    0xE0, 0x48,             // ldh [OBP0], a
+    0x3E, 0x93,             // ld a, $93          ; LCD, BG and objects on.
+    0xE0, 0x40,             // ldh [LCDC], a
+    // main:
+    0xF0, 0x44,             // ldh a, [LY]        ; Wait for VBlank.
+    0xFE, 0x90,             // cp 144
+    0x20, 0xFA,             // jr nz, -6
+    0x3E, 0x20,             // ld a, $20          ; Read the D-pad
+    0xE0, 0x00,             // ldh [P1], a        ; to B,
+    0xF0, 0x00,             // ldh a, [P1]
+    0xF0, 0x00,             // ldh a, [P1]
+    0x2F,                   // cpl
+    0xE6, 0x0F,             // and $0F
+    0x47,                   // ld b, a
+    0x3E, 0x10,             // ld a, $10          ; and the buttons
+    0xE0, 0x00,             // ldh [P1], a        ; to C.
+    0xF0, 0x00,             // ldh a, [P1]
+    0xF0, 0x00,             // ldh a, [P1]
+    0x2F,                   // cpl
+    0xE6, 0x0F,             // and $0F
+    0x4F,                   // ld c, a
+    0x3E, 0x30,             // ld a, $30
+    0xE0, 0x00,             // ldh [P1], a
+    0x21, 0x00, 0xFE,       // ld hl, $FE00       ; Move the object.
+    0xCB, 0x50,             // bit 2, b           ; Up
+    0x28, 0x01,             // jr z, +1
+    0x35,                   // dec [hl]
+    0xCB, 0x58,             // bit 3, b           ; Down
+    0x28, 0x01,             // jr z, +1
+    0x34,                   // inc [hl]
+    0x2C,                   // inc l
+    0xCB, 0x40,             // bit 0, b           ; Right
+    0x28, 0x01,             // jr z, +1
+    0x34,                   // inc [hl]
+    0xCB, 0x48,             // bit 1, b           ; Left
+    0x28, 0x01,             // jr z, +1
+    0x35,                   // dec [hl]
+    0x21, 0x22, 0x98,       // ld hl, $9822       ; Boxes for A, B,
+    0x06, 0x04,             // ld b, 4            ; Select and Start,
+    0x3E, 0x02,             // ld a, 2            ; filled if held.
+    0xCB, 0x39,             // srl c
+    0x30, 0x02,             // jr nc, +2
+    0x3E, 0x01,             // ld a, 1
+    0x22,                   // ld [hl+], a
+    0x2C,                   // inc l
+    0x05,                   // dec b
+    0x20, 0xF3,             // jr nz, -13
+    0xF0, 0x44,             // ldh a, [LY]        ; Wait for VBlank to end.
+    0xFE, 0x90,             // cp 144
+    0x28, 0xFA,             // jr z, -6
+    0x18, 0xAC,             // jr main
+];
+
+/// Build the test pattern cartridge ROM.
+pub(crate) fn test_pattern_rom() -> Vec<u8> {
+    build_rom(TITLE, CODE)
+}
+
+/// Build the demo cartridge ROM.
+#[cfg(feature = "demo")]
+pub(crate) fn demo_rom() -> Vec<u8> {
+    build_rom(DEMO_TITLE, DEMO_CODE)
+}
+
+/// Build a ROM which runs `code`, it has no MBC and is 32kiB.
+fn build_rom(title: &[u8], code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 2 * SIZE_ROM_BANK];
+
+    // Entry point: nop, jp $0150.
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[*CART_TITLE.start()..][..title.len()].copy_from_slice(title);
+    rom[CODE_START..][..code.len()].copy_from_slice(code);
+
+    rom[CART_HEADER_CSUM] = rom[*CART_TITLE.start()..CART_HEADER_CSUM]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+
+    rom
+}
@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 use crate::regs::TimerCtrl;
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Timer {
     pub(crate) is_2x: bool,
 
@@ -26,6 +28,12 @@ impl Timer {
     /// Update timers for new `sys_clock` value.
     /// Returns true if TIMER interrupt has been requested.
     pub(crate) fn tick(&mut self, mcycles: u16) -> bool {
+        // Memory accesses tick the machine, so the rest of an instruction
+        // can take no cycles.
+        if mcycles == 0 {
+            return false;
+        }
+
         // DIV is either RESET or INCREMENTED.
         let mcycles = if self.div_reset {
             self.div_reset = false;
@@ -46,6 +54,22 @@ impl Timer {
         timer_intr
     }
 
+    /// Get M-cycles until TIMA changes or a TIMER interrupt is requested,
+    /// at least 1. `u16::MAX` if the timer is disabled.
+    pub(crate) fn mcycles_to_change(&self) -> u16 {
+        if self.tac.enable == 0 {
+            return u16::MAX;
+        }
+        if self.tima_overflowed || self.div_reset {
+            return 1;
+        }
+
+        // The fall bit falls once every period, when the clock wraps to a
+        // multiple of it.
+        let period = 2 << get_clock_fall_bit(self.tac.clock_select);
+        period - (self.sys_clock & (period - 1))
+    }
+
     pub(crate) fn set_div(&mut self, _val: u8) {
         // setting DIV resets it to 0.
         self.sys_clock = 0;
@@ -56,10 +80,6 @@ impl Timer {
         (self.sys_clock >> 6) as u8
     }
 
-    pub(crate) fn is_apu_event(&self) -> bool {
-        self.apu_event
-    }
-
     fn tick_from_to(&mut self, old: u16, new: u16) -> bool {
         let apu_idx = if self.is_2x { 11 } else { 10 };
         self.apu_event = has_fallen(old, new, apu_idx);
@@ -108,3 +128,23 @@ fn get_clock_fall_bit(clock_select: u8) -> u32 {
 fn has_fallen(old: u16, new: u16, fall_bit: u32) -> bool {
     (old >> fall_bit) & 1 == 1 && (new >> fall_bit) & 1 == 0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_reset_is_kept_over_empty_ticks() {
+        let mut timer = Timer::new();
+        timer.tick(100);
+        timer.set_div(0);
+
+        assert!(!timer.tick(0));
+        assert_eq!(timer.sys_clock, 0);
+        // The cycle after a reset does not count.
+        timer.tick(1);
+        assert_eq!(timer.sys_clock, 0);
+        timer.tick(1);
+        assert_eq!(timer.sys_clock, 1);
+    }
+}
@@ -0,0 +1,87 @@
+//! JavaScript bindings for embedding the emulator in web pages, built for
+//! the `wasm32-unknown-unknown` target with the `wasm` feature enabled and
+//! processed with `wasm-bindgen`. Build it as a cdylib with
+//! `cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib`.
+//!
+//! The page drives the emulator with `requestAnimationFrame`, stepping a
+//! frame each time and drawing it to a canvas:
+//!
+//! ```js
+//! const emu = new Emulator(romBytes);
+//! emu.stepFrame(buttons);
+//! ctx.putImageData(new ImageData(emu.frameRgba(), emu.width, emu.height), 0, 0);
+//! ```
+//!
+//! Sound is not emulated yet, so there are no samples to push.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{ButtonState, Emulator, SCREEN_SIZE};
+
+#[wasm_bindgen(js_name = Emulator)]
+pub struct WasmEmulator {
+    emu: Emulator,
+}
+
+#[wasm_bindgen(js_class = Emulator)]
+impl WasmEmulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8]) -> Result<WasmEmulator, JsError> {
+        Emulator::new(rom)
+            .map(|emu| Self { emu })
+            .map_err(|e| JsError::new(&format!("{e:?}")))
+    }
+
+    /// Resume from a save-state made by `saveState`.
+    #[wasm_bindgen(js_name = fromState)]
+    pub fn from_state(data: &[u8]) -> Result<WasmEmulator, JsError> {
+        Emulator::from_state(data)
+            .map(|emu| Self { emu })
+            .map_err(|e| JsError::new(&format!("{e:?}")))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        SCREEN_SIZE.0
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        SCREEN_SIZE.1
+    }
+
+    /// Hold the buttons in `buttons` for a frame, bits from LSB to MSB are:
+    /// A, B, Select, Start, Right, Left, Up and Down.
+    #[wasm_bindgen(js_name = stepFrame)]
+    pub fn step_frame(&mut self, buttons: u8) {
+        self.emu.step_frame(ButtonState::from_bits(buttons));
+    }
+
+    /// Get the last completed frame as RGBA bytes, for an `ImageData`.
+    #[wasm_bindgen(js_name = frameRgba)]
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        let rgb = self.emu.frame().to_rgb24();
+        rgb.chunks(3)
+            .flat_map(|c| [c[0], c[1], c[2], 0xFF])
+            .collect()
+    }
+
+    #[wasm_bindgen(js_name = saveState)]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.emu.save_state()
+    }
+
+    /// Get the cartridge RAM, for keeping it in browser storage.
+    #[wasm_bindgen(js_name = saveData)]
+    pub fn save_data(&self) -> Vec<u8> {
+        self.emu.save_data()
+    }
+
+    /// Load the cartridge RAM, it must be as large as `saveData` makes it.
+    #[wasm_bindgen(js_name = loadSaveData)]
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), JsError> {
+        self.emu
+            .load_save_data(data)
+            .map_err(|e| JsError::new(&format!("{e:?}")))
+    }
+}
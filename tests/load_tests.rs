@@ -0,0 +1,84 @@
+//! Feeds malformed ROM files to the loaders, which must reject them with
+//! an error or load them, and never panic.
+
+use gbemu::{ButtonState, CartridgeInfo, EmuError, Emulator};
+
+/// Xorshift generator, so that failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Load `rom` both ways and run it for a few frames if it loads. Returns
+/// the error of `Emulator::new`.
+fn load(rom: &[u8]) -> Result<(), EmuError> {
+    let info = CartridgeInfo::from_rom(rom);
+    let mut emu = Emulator::new(rom)?;
+    assert!(info.is_ok(), "header of a loaded ROM is not read");
+    for _ in 0..2 {
+        emu.step_frame(ButtonState::default());
+    }
+    Ok(())
+}
+
+#[test]
+fn short_files_are_too_small() {
+    for len in [0, 1, 0x100, 0x14F] {
+        let rom = vec![0x01; len];
+        assert_eq!(
+            CartridgeInfo::from_rom(&rom).err(),
+            Some(EmuError::FileTooSmall)
+        );
+        assert_eq!(load(&rom), Err(EmuError::FileTooSmall), "{} bytes", len);
+    }
+}
+
+#[test]
+fn blank_files_have_no_header() {
+    for len in [0x150, 0x8000] {
+        for byte in [0x00, 0xFF] {
+            let rom = vec![byte; len];
+            assert_eq!(
+                CartridgeInfo::from_rom(&rom).err(),
+                Some(EmuError::MissingHeader)
+            );
+            assert_eq!(load(&rom), Err(EmuError::MissingHeader));
+        }
+    }
+}
+
+#[test]
+fn header_only_file_loads() {
+    let mut rom = vec![0; 0x150];
+    // NOP; JP $0150, which jumps past the end of the file.
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    assert_eq!(load(&rom), Ok(()));
+}
+
+#[test]
+fn random_files_do_not_panic() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..200 {
+        let len = match rng.next() % 4 {
+            0 => (rng.next() % 0x200) as usize,
+            1 => 0x150 + (rng.next() % 0x100) as usize,
+            _ => 0x4000 * (1 + (rng.next() % 4) as usize),
+        };
+        let mut rom = rng.bytes(len);
+        // Mostly pick mappers which are emulated, so that the ROM runs.
+        if len > 0x147 && !rng.next().is_multiple_of(4) {
+            rom[0x147] = [0x00, 0x01, 0x03, 0x13, 0x1B, 0x22][(rng.next() % 6) as usize];
+        }
+        let _ = load(&rom);
+    }
+}
@@ -0,0 +1,195 @@
+//! Runs accuracy test ROMs through `run_test_rom`.
+//!
+//! Test ROMs are not distributed with the source. Set `GBEMU_TEST_ROMS` to a
+//! directory of blargg or mooneye `.gb` files for running them, every one
+//! of them must pass. Otherwise only the small ROMs built here are run,
+//! which check that results are detected.
+//!
+//! ROMs built here also check that save-states keep the whole machine,
+//! and how boot ROMs pick the hardware model.
+
+use std::{env, fs};
+
+use gbemu::{run_test_rom, ButtonState, Emulator, TestOutcome};
+
+/// Frames a test ROM gets for finishing, about two minutes.
+const MAX_FRAMES: u64 = 7200;
+
+/// Build a 32KiB ROM without an MBC, which runs `code` from 0x150.
+fn build_rom(code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // NOP; JP $0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x150..0x150 + code.len()].copy_from_slice(code);
+    rom
+}
+
+/// Build a boot ROM of `size` bytes, which runs `code`, turns on the LCD
+/// and then unmaps itself right before 0x100.
+fn build_boot_rom(size: usize, code: &[u8]) -> Vec<u8> {
+    let mut boot = vec![0; size];
+    boot[..code.len()].copy_from_slice(code);
+    let end = code.len();
+    // LD A, $91; LDH [LCDC], A; LD A, $01; JP $00FE
+    boot[end..end + 9].copy_from_slice(&[0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x01, 0xC3, 0xFE, 0x00]);
+    // LDH [BOOT], A
+    boot[0xFE..0x100].copy_from_slice(&[0xE0, 0x50]);
+    boot
+}
+
+/// Code loading the given B, C, D, E, H and L and then executing
+/// `LD B, B` followed by an endless loop, like mooneye tests end.
+fn mooneye_end(regs: [u8; 6]) -> Vec<u8> {
+    let mut code = Vec::new();
+    // LD B, n; LD C, n; LD D, n; LD E, n; LD H, n; LD L, n
+    for (op, val) in [0x06, 0x0E, 0x16, 0x1E, 0x26, 0x2E].into_iter().zip(regs) {
+        code.extend([op, val]);
+    }
+    // LD B, B; JR -2
+    code.extend([0x40, 0x18, 0xFE]);
+    code
+}
+
+/// Code sending `text` over the serial port with the internal clock,
+/// waiting for each byte to be sent, and then looping forever.
+fn serial_print(text: &str) -> Vec<u8> {
+    let mut code = Vec::new();
+    for &byte in text.as_bytes() {
+        // LD A, n; LDH [SB], A; LD A, $81; LDH [SC], A
+        code.extend([0x3E, byte, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+        // LDH A, [SC]; BIT 7, A; JR NZ, -6
+        code.extend([0xF0, 0x02, 0xCB, 0x7F, 0x20, 0xFA]);
+    }
+    // JR -2
+    code.extend([0x18, 0xFE]);
+    code
+}
+
+#[test]
+fn mooneye_result_is_detected() {
+    let passed = build_rom(&mooneye_end([3, 5, 8, 13, 21, 34]));
+    let report = run_test_rom(&passed, MAX_FRAMES).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Passed);
+
+    let failed = build_rom(&mooneye_end([0x42; 6]));
+    let report = run_test_rom(&failed, MAX_FRAMES).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Failed);
+}
+
+#[test]
+fn serial_result_is_detected() {
+    let passed = build_rom(&serial_print("test\n\nPassed\n"));
+    let report = run_test_rom(&passed, MAX_FRAMES).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Passed);
+    assert_eq!(report.serial, ["test", "", "Passed"]);
+
+    let failed = build_rom(&serial_print("Failed #2\n"));
+    let report = run_test_rom(&failed, MAX_FRAMES).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Failed);
+}
+
+#[test]
+fn endless_loop_times_out() {
+    let report = run_test_rom(&build_rom(&[0x18, 0xFE]), 10).unwrap();
+    assert_eq!(report.outcome, TestOutcome::TimedOut);
+    assert_eq!(report.frames, 10);
+}
+
+#[test]
+fn mutated_state_round_trips() {
+    // LD A, $05; LDH [TAC], A; LD HL, $C000
+    // loop: INC [HL]; LD A, [HL]; LDH [BGP], A; JR loop
+    let code = [
+        0x3E, 0x05, 0xE0, 0x07, 0x21, 0x00, 0xC0, 0x34, 0x7E, 0xE0, 0x47, 0x18, 0xFA,
+    ];
+    let mut emu = Emulator::new(&build_rom(&code)).unwrap();
+    for _ in 0..5 {
+        emu.step_frame(ButtonState::default());
+    }
+
+    // Tiles with all four colors, shown all over the background.
+    for addr in 0x8000..0x8800 {
+        emu.write_memory(addr, addr as u8 ^ (addr >> 4) as u8);
+    }
+    for addr in 0x9800..0x9C00 {
+        emu.write_memory(addr, (addr % 0x80) as u8);
+    }
+    emu.write_memory(0xC000, 0x80);
+    emu.write_memory(0xFF80, 0x42);
+    // Scroll, timer modulo and window position.
+    emu.write_memory(0xFF42, 3);
+    emu.write_memory(0xFF43, 5);
+    emu.write_memory(0xFF06, 0xF0);
+    emu.write_memory(0xFF4A, 40);
+
+    let mut regs = emu.registers();
+    regs.b = 0x12;
+    regs.d = 0x34;
+    regs.e = 0x56;
+    regs.sp = 0xDFF0;
+    emu.set_registers(&regs);
+
+    assert_eq!(emu.check_state_round_trip(10), Vec::<&str>::new());
+}
+
+#[test]
+fn cgb_boot_rom_selects_dmg_mode() {
+    // LD A, $04; LDH [KEY0], A; LD A, $80; LDH [BCPS], A
+    // LD A, $1F; LDH [BCPD], A; XOR A; LDH [BCPD], A
+    let code = [
+        0x3E, 0x04, 0xE0, 0x4C, 0x3E, 0x80, 0xE0, 0x68, 0x3E, 0x1F, 0xE0, 0x69, 0xAF, 0xE0, 0x69,
+    ];
+    let boot = build_boot_rom(0x900, &code);
+    let mut emu = Emulator::from_rom_with_bootrom(&build_rom(&[0x18, 0xFE]), &boot).unwrap();
+    // CGB registers are there while the boot ROM runs.
+    assert_ne!(emu.read_memory(0xFF70), 0xFF);
+
+    emu.step_frame(ButtonState::default());
+    emu.step_frame(ButtonState::default());
+    assert_eq!(emu.registers().pc, 0x150);
+    assert_eq!(emu.read_memory(0xFF70), 0xFF);
+    // Shade 0 is drawn with the first color the boot ROM loaded, red.
+    let frame = emu.frame();
+    assert!(!frame.info().is_cgb);
+    let c = frame.get(0, 0);
+    assert!(c.r > 0 && c.g == 0 && c.b == 0, "{:?}", c);
+}
+
+#[test]
+fn dmg_boot_rom_runs_cgb_cartridge_as_dmg() {
+    let mut rom = build_rom(&[0x18, 0xFE]);
+    rom[0x143] = 0x80;
+    let boot = build_boot_rom(0x100, &[]);
+    let mut emu = Emulator::from_rom_with_bootrom(&rom, &boot).unwrap();
+    assert_eq!(emu.read_memory(0xFF70), 0xFF);
+
+    emu.step_frame(ButtonState::default());
+    emu.step_frame(ButtonState::default());
+    assert_eq!(emu.registers().pc, 0x150);
+    assert!(!emu.frame().info().is_cgb);
+}
+
+#[test]
+fn test_roms_pass() {
+    let Ok(dir) = env::var("GBEMU_TEST_ROMS") else {
+        eprintln!("GBEMU_TEST_ROMS is not set, skipping");
+        return;
+    };
+
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("cannot open '{}': {:?}", dir, e))
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "gb" || e == "gbc"))
+        .collect();
+    paths.sort();
+
+    let failures: Vec<_> = paths
+        .iter()
+        .filter_map(|path| {
+            let report = run_test_rom(&fs::read(path).unwrap(), MAX_FRAMES).unwrap();
+            eprintln!("{}: {:?}", path.display(), report.outcome);
+            (report.outcome != TestOutcome::Passed).then(|| path.display().to_string())
+        })
+        .collect();
+    assert!(failures.is_empty(), "failed: {:?}", failures);
+}
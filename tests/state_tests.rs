@@ -0,0 +1,65 @@
+//! Checks save-states against a fixture, so that a change to the layout
+//! of the state is not made by accident.
+//!
+//! Changing the layout on purpose needs `STATE_VERSION` bumped, then the
+//! fixture is made again by running the tests with `GBEMU_UPDATE_FIXTURES`
+//! set. It also has to be made again when the machine in it runs
+//! differently, as it is saved again and compared.
+
+use std::{env, fs, path::PathBuf};
+
+use gbemu::{ButtonState, Emulator};
+
+fn fixture_path() -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", "state.bin"]
+        .iter()
+        .collect()
+}
+
+/// Make the machine saved in the fixture. It runs a 32KiB ROM without an
+/// MBC for a few frames, which counts up in WRAM with the timer enabled.
+fn fixture_machine() -> Emulator {
+    let mut rom = vec![0; 0x8000];
+    // NOP; JP $0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    // LD A, $05; LDH [TAC], A; LD HL, $C000
+    // loop: INC [HL]; LD A, [HL]; LDH [BGP], A; JR loop
+    rom[0x150..0x15D].copy_from_slice(&[
+        0x3E, 0x05, 0xE0, 0x07, 0x21, 0x00, 0xC0, 0x34, 0x7E, 0xE0, 0x47, 0x18, 0xFA,
+    ]);
+
+    let mut emu = Emulator::new(&rom).unwrap();
+    for _ in 0..3 {
+        emu.step_frame(ButtonState::default());
+    }
+    emu
+}
+
+#[test]
+fn state_fixture_reencodes_byte_for_byte() {
+    let path = fixture_path();
+    if env::var_os("GBEMU_UPDATE_FIXTURES").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, fixture_machine().save_state()).unwrap();
+    }
+
+    let data = fs::read(&path).unwrap_or_else(|e| panic!("cannot read the fixture: {:?}", e));
+    let emu = Emulator::from_state(&data).unwrap_or_else(|e| {
+        panic!(
+            "cannot decode the fixture: {:?}, if the layout was changed on purpose \
+             make it again with GBEMU_UPDATE_FIXTURES set",
+            e
+        )
+    });
+    assert!(
+        emu.save_state() == data,
+        "the fixture is encoded differently, fields may have been added or removed"
+    );
+    // Re-encoding cannot tell apart two fields of a type which were swapped,
+    // but the machine runs the same each time, so saving it again does.
+    assert!(
+        fixture_machine().save_state() == data,
+        "the machine is saved differently, fields may have been reordered, or it \
+         runs differently now"
+    );
+}